@@ -1,4 +1,4 @@
-CREATE TABLE "repository" (
+CREATE TABLE IF NOT EXISTS "repository" (
     id uuid PRIMARY KEY NOT NULL,
     url varchar(255) UNIQUE NOT NULL,
     created_at timestamp NOT NULL DEFAULT NOW(),
@@ -6,12 +6,12 @@ CREATE TABLE "repository" (
     uploader_ip varchar(21) NOT NULL
 );
 
-CREATE TABLE "email"(
+CREATE TABLE IF NOT EXISTS "email"(
     email varchar(120) PRIMARY KEY NOT NULL,
     hash_md5 varchar(32) UNIQUE NOT NULL
 );
 
-CREATE TABLE "commit" (
+CREATE TABLE IF NOT EXISTS "commit" (
     hash varchar(40) PRIMARY KEY NOT NULL,
     tree varchar(40) REFERENCES commit(hash) ON DELETE CASCADE NULL,
     text text NOT NULL,
@@ -23,7 +23,7 @@ CREATE TABLE "commit" (
     repository_url varchar(256) REFERENCES repository(url) ON DELETE CASCADE NOT NULL
 );
 
-CREATE TABLE "branch" (
+CREATE TABLE IF NOT EXISTS "branch" (
     id uuid PRIMARY KEY NOT NULL,
     name varchar(120) NOT NULL,
     repository_id uuid REFERENCES repository(id) ON DELETE CASCADE NOT NULL,
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS "credential" (
+    id uuid PRIMARY KEY NOT NULL,
+    kind varchar(5) NOT NULL,
+    username varchar(120) NOT NULL,
+    secret bytea NOT NULL,
+    created_at timestamp NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE "repository"
+    ADD COLUMN IF NOT EXISTS webhook_secret varchar(255) NULL,
+    ADD COLUMN IF NOT EXISTS credential_id uuid REFERENCES credential(id) ON DELETE SET NULL NULL;
@@ -0,0 +1,5 @@
+ALTER TABLE "commit"
+    ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', text)) STORED;
+
+CREATE INDEX IF NOT EXISTS commit_search_idx ON "commit" USING GIN (search);
@@ -0,0 +1,13 @@
+-- Commits imported before this migration are considered not merges
+ALTER TABLE "commit"
+    ADD COLUMN IF NOT EXISTS is_merge boolean NOT NULL DEFAULT false;
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS commit_date_idx ON "commit" (date DESC, hash DESC);
+CREATE INDEX IF NOT EXISTS commit_repository_url_idx ON "commit" (repository_url, date DESC);
+CREATE INDEX IF NOT EXISTS commit_author_email_idx ON "commit" (author_email);
+CREATE INDEX IF NOT EXISTS commit_committer_email_idx ON "commit" (committer_email);
+CREATE INDEX IF NOT EXISTS commit_author_name_trgm_idx ON "commit" USING GIN (author_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS commit_committer_name_trgm_idx ON "commit" USING GIN (committer_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS commit_text_trgm_idx ON "commit" USING GIN (text gin_trgm_ops);
@@ -0,0 +1,24 @@
+-- Emails are saved lowercase and without the surrounding whitespace, so the
+-- addresses which differ only by them are merged into the normalized one.
+-- `normalized_email` has every email normalized by the email model, with
+-- its hash and its noreply username, made by `migrations::normalize_emails`
+ALTER TABLE "email" ADD COLUMN IF NOT EXISTS username varchar(120) NULL;
+
+INSERT INTO "email"(email, hash_md5)
+SELECT DISTINCT normalized, hash_md5 FROM normalized_email
+ON CONFLICT (email) DO NOTHING;
+
+UPDATE "commit" SET author_email = n.normalized
+FROM normalized_email n
+WHERE "commit".author_email = n.email AND n.email <> n.normalized;
+UPDATE "commit" SET committer_email = n.normalized
+FROM normalized_email n
+WHERE "commit".committer_email = n.email AND n.email <> n.normalized;
+
+DELETE FROM "email" e USING normalized_email n
+WHERE e.email = n.email AND n.email <> n.normalized;
+
+-- Noreply addresses of GitHub, `[id+]username@users.noreply.github.com`,
+-- and of GitLab, `[id-]username@users.noreply.gitlab.com`
+UPDATE "email" SET username = n.username
+FROM normalized_email n WHERE "email".email = n.normalized;
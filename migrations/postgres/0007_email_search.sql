@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS email_email_trgm_idx ON "email" USING GIN (email gin_trgm_ops);
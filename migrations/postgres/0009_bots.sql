@@ -0,0 +1,5 @@
+-- Emails of bots, flagged by the patterns of the configuration. The flags
+-- are set again at every start
+ALTER TABLE "email" ADD COLUMN IF NOT EXISTS is_bot boolean NOT NULL DEFAULT false;
+
+CREATE INDEX IF NOT EXISTS email_is_bot_idx ON "email" (email) WHERE is_bot;
@@ -0,0 +1,21 @@
+-- Names used with each email, as author or as committer, with the number of
+-- commits and the dates of the first and the last one. The commits of the
+-- imports update them
+CREATE TABLE IF NOT EXISTS "email_name" (
+    email varchar(120) REFERENCES email(email) ON DELETE CASCADE NOT NULL,
+    name varchar(120) NOT NULL,
+    commits bigint NOT NULL,
+    first_seen timestamptz NOT NULL,
+    last_seen timestamptz NOT NULL,
+    PRIMARY KEY (email, name)
+);
+
+INSERT INTO email_name(email, name, commits, first_seen, last_seen)
+SELECT email, name, COUNT(*), MIN(date), MAX(date) FROM (
+    SELECT hash, date, author_email AS email, author_name AS name FROM commit
+    UNION
+    SELECT hash, date, committer_email, committer_name FROM commit
+) uses
+WHERE name <> ''
+GROUP BY email, name
+ON CONFLICT (email, name) DO NOTHING;
@@ -0,0 +1,10 @@
+-- The webhook secrets are encrypted like the credentials, bound to the id of
+-- their repository. `encrypted_webhook_secret` is filled by the code of the
+-- migration, which needs SECRET_KEY when a secret is saved
+ALTER TABLE "repository"
+    ALTER COLUMN webhook_secret TYPE bytea USING NULL;
+
+UPDATE "repository"
+SET webhook_secret = encrypted_webhook_secret.secret
+FROM encrypted_webhook_secret
+WHERE "repository".id = encrypted_webhook_secret.id;
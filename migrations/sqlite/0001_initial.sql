@@ -0,0 +1,75 @@
+-- UUIDs are saved as text and timestamps as UTC text, formatted as
+-- "YYYY-MM-DD HH:MM:SS.ssssss" so they are sorted as strings
+CREATE TABLE IF NOT EXISTS "credential" (
+    id text PRIMARY KEY NOT NULL,
+    kind varchar(5) NOT NULL,
+    username varchar(120) NOT NULL,
+    secret blob NOT NULL,
+    created_at text NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "repository" (
+    id text PRIMARY KEY NOT NULL,
+    url varchar(255) UNIQUE NOT NULL,
+    created_at text NOT NULL,
+    updated_at text NOT NULL,
+    uploader_ip varchar(21) NOT NULL,
+    webhook_secret varchar(255) NULL,
+    credential_id text REFERENCES credential(id) ON DELETE SET NULL NULL
+);
+
+CREATE TABLE IF NOT EXISTS "email"(
+    email varchar(120) PRIMARY KEY NOT NULL,
+    hash_md5 varchar(32) UNIQUE NOT NULL
+);
+
+-- `id` is only used by the full-text index
+CREATE TABLE IF NOT EXISTS "commit" (
+    id integer PRIMARY KEY,
+    hash varchar(40) UNIQUE NOT NULL,
+    tree varchar(40) REFERENCES "commit"(hash) ON DELETE CASCADE
+        DEFERRABLE INITIALLY DEFERRED NULL,
+    text text NOT NULL,
+    date text NOT NULL,
+    author_email varchar(120) REFERENCES email(email) ON DELETE NO ACTION NOT NULL,
+    author_name varchar(120) NOT NULL,
+    committer_email varchar(120) REFERENCES email(email) ON DELETE NO ACTION NOT NULL,
+    committer_name varchar(120) NOT NULL,
+    repository_url varchar(256) REFERENCES repository(url) ON DELETE CASCADE NOT NULL,
+    is_merge integer NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS "branch" (
+    id text PRIMARY KEY NOT NULL,
+    name varchar(120) NOT NULL,
+    repository_id text REFERENCES repository(id) ON DELETE CASCADE NOT NULL,
+    head varchar(40) REFERENCES "commit"(hash) ON DELETE SET NULL
+        DEFERRABLE INITIALLY DEFERRED NULL
+);
+
+CREATE INDEX IF NOT EXISTS commit_date_idx ON "commit" (date DESC, hash DESC);
+CREATE INDEX IF NOT EXISTS commit_repository_url_idx ON "commit" (repository_url, date DESC);
+CREATE INDEX IF NOT EXISTS commit_author_email_idx ON "commit" (author_email);
+CREATE INDEX IF NOT EXISTS commit_committer_email_idx ON "commit" (committer_email);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS commit_search USING fts5(
+    text, content='commit', content_rowid='id', tokenize='porter unicode61'
+);
+
+CREATE TRIGGER IF NOT EXISTS commit_search_insert AFTER INSERT ON "commit"
+BEGIN
+    INSERT INTO commit_search(rowid, text) VALUES (new.id, new.text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS commit_search_delete AFTER DELETE ON "commit"
+BEGIN
+    INSERT INTO commit_search(commit_search, rowid, text)
+    VALUES ('delete', old.id, old.text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS commit_search_update AFTER UPDATE OF text ON "commit"
+BEGIN
+    INSERT INTO commit_search(commit_search, rowid, text)
+    VALUES ('delete', old.id, old.text);
+    INSERT INTO commit_search(rowid, text) VALUES (new.id, new.text);
+END;
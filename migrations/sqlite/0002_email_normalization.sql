@@ -0,0 +1,18 @@
+-- Emails are saved lowercase and without the surrounding whitespace, so the
+-- addresses which differ only by them are merged into the normalized one.
+-- `normalize_email`, `hash_email` and `noreply_username` are the functions
+-- of the email model, registered by `migrations::run_sqlite`
+ALTER TABLE "email" ADD COLUMN username varchar(120) NULL;
+
+INSERT OR IGNORE INTO "email"(email, hash_md5)
+SELECT DISTINCT normalize_email(email), hash_email(normalize_email(email))
+FROM "email";
+
+UPDATE "commit" SET author_email = normalize_email(author_email)
+WHERE author_email <> normalize_email(author_email);
+UPDATE "commit" SET committer_email = normalize_email(committer_email)
+WHERE committer_email <> normalize_email(committer_email);
+
+DELETE FROM "email" WHERE email <> normalize_email(email);
+
+UPDATE "email" SET username = noreply_username(email);
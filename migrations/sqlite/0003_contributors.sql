@@ -0,0 +1,14 @@
+-- A contributor groups the emails of a person. An email without a
+-- contributor is a contributor by itself
+CREATE TABLE IF NOT EXISTS "contributor" (
+    id text PRIMARY KEY NOT NULL,
+    name varchar(120) NOT NULL,
+    created_at text NOT NULL
+);
+
+ALTER TABLE "email"
+    ADD COLUMN contributor_id text
+    REFERENCES contributor(id) ON DELETE SET NULL NULL;
+
+CREATE INDEX IF NOT EXISTS email_contributor_id_idx ON "email" (contributor_id);
+CREATE INDEX IF NOT EXISTS contributor_name_idx ON "contributor" (name, id);
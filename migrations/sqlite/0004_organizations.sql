@@ -0,0 +1,17 @@
+-- Organization of the emails of a domain and of its subdomains
+CREATE TABLE IF NOT EXISTS "organization_domain" (
+    domain varchar(255) PRIMARY KEY NOT NULL,
+    organization varchar(120) NOT NULL
+);
+
+-- Organization of an email between two dates, which replaces the one of its
+-- domain. The email may not have committed yet
+CREATE TABLE IF NOT EXISTS "affiliation" (
+    id text PRIMARY KEY NOT NULL,
+    email varchar(120) NOT NULL,
+    organization varchar(120) NOT NULL,
+    since text NULL,
+    until text NULL
+);
+
+CREATE INDEX IF NOT EXISTS affiliation_email_idx ON "affiliation" (email, id);
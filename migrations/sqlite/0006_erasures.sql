@@ -0,0 +1,9 @@
+-- Emails erased on request of their authors, replaced by a pseudonym. Only
+-- their hash is kept, so the next imports replace them too
+CREATE TABLE IF NOT EXISTS "erasure" (
+    hash_sha256 varchar(64) PRIMARY KEY NOT NULL,
+    pseudonym varchar(120) UNIQUE NOT NULL,
+    created_at text NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS erasure_created_at_idx ON "erasure" (created_at DESC, hash_sha256 DESC);
@@ -0,0 +1,4 @@
+-- Lines added and deleted by each commit, compared with its first parent.
+-- They are unknown for the commits imported before
+ALTER TABLE "commit" ADD COLUMN additions integer NULL;
+ALTER TABLE "commit" ADD COLUMN deletions integer NULL;
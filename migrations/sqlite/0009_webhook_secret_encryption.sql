@@ -0,0 +1,5 @@
+-- The webhook secrets are encrypted like the credentials, bound to the id of
+-- their repository. It needs SECRET_KEY when a secret is saved
+UPDATE "repository"
+SET webhook_secret = encrypt_webhook_secret(id, webhook_secret)
+WHERE webhook_secret IS NOT NULL;
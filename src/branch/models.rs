@@ -1,7 +1,8 @@
 use crate::db::get_client;
 use crate::errors::{AppError, AppErrorType};
+use crate::pagination::{Key, Page, Pagination};
 
-use deadpool_postgres::Pool;
+use deadpool_postgres::{Client, Pool};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
@@ -25,19 +26,40 @@ pub struct BranchData {
 }
 
 impl Branch {
-    /// Find all branches
-    pub async fn find_all(pool: Pool) -> Result<Vec<Branch>, AppError> {
+    /// Find a page of branches, ordered by name
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
-        let statement = client.prepare("SELECT * FROM branch").await?;
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+        let statement = client
+            .prepare(
+                "
+                SELECT * FROM branch
+                WHERE $1::text IS NULL OR (name, id) > ($1, $2::text::uuid)
+                ORDER BY name, id
+                LIMIT $3
+                ",
+            )
+            .await?;
 
         let branches = client
-            .query(&statement, &[])
+            .query(&statement, &[&after[0], &after[1], &pagination.fetch()])
             .await?
             .iter()
             .map(|row| Branch::from_row_ref(row).unwrap())
             .collect::<Vec<Branch>>();
 
-        Ok(branches)
+        let count = if pagination.count {
+            let statement =
+                client.prepare("SELECT COUNT(*) FROM branch").await?;
+            Some(client.query_one(&statement, &[]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(branches, pagination, count, Branch::keys))
     }
 
     /// Find a branch with an `id` equals to an Uuid element
@@ -61,24 +83,73 @@ impl Branch {
         }
     }
 
-    /// Find all branches of a repository
+    /// Find the branch of a repository called `name`
+    pub async fn find_by_name(
+        pool: Pool,
+        repo: &Uuid,
+        name: &str,
+    ) -> Result<Branch, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare("SELECT * FROM branch WHERE repository_id=$1 AND name=$2")
+            .await?;
+
+        let branch = client
+            .query_opt(&statement, &[&repo, &name])
+            .await?
+            .map(|row| Branch::from_row_ref(&row).unwrap());
+
+        match branch {
+            Some(branch) => Ok(branch),
+            None => Err(AppError {
+                error_type: AppErrorType::NotFoundError,
+                cause: None,
+                message: Some("Branch not found".to_string()),
+            }),
+        }
+    }
+
+    /// Find a page of the branches of a repository, ordered by name
     pub async fn find_by_repo(
         pool: Pool,
         repo: &Uuid,
-    ) -> Result<Vec<Branch>, AppError> {
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
         let statement = client
-            .prepare("SELECT * FROM branch WHERE repository_id=$1")
+            .prepare(
+                "
+                SELECT * FROM branch
+                WHERE repository_id=$1
+                    AND ($2::text IS NULL
+                        OR (name, id) > ($2, $3::text::uuid))
+                ORDER BY name, id
+                LIMIT $4
+                ",
+            )
             .await?;
 
         let branches = client
-            .query(&statement, &[&repo])
+            .query(
+                &statement,
+                &[&repo, &after[0], &after[1], &pagination.fetch()],
+            )
             .await?
             .iter()
             .map(|row| Branch::from_row_ref(row).unwrap())
             .collect::<Vec<Branch>>();
 
-        Ok(branches)
+        let count = if pagination.count {
+            let statement = client
+                .prepare("SELECT COUNT(*) FROM branch WHERE repository_id=$1")
+                .await?;
+            Some(client.query_one(&statement, &[&repo]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(branches, pagination, count, Branch::keys))
     }
 
     /// Find a branch and delete it, but before check if "Authorization"
@@ -110,13 +181,12 @@ impl Branch {
         }
     }
 
-    /// Create a new branch
+    /// Create a new branch, using a `client` which can be inside a
+    /// transaction
     pub async fn create(
-        pool: Pool,
+        client: &Client,
         data: &BranchData,
     ) -> Result<Branch, AppError> {
-        let client = get_client(pool.clone()).await.unwrap();
-
         let statement = client
             .prepare(
                 "INSERT INTO branch(id, name, repository_id, head)
@@ -145,4 +215,35 @@ impl Branch {
             }),
         }
     }
+
+    /// Values used to sort the branches, saved in the pagination cursor
+    pub fn keys(&self) -> Vec<String> {
+        vec![self.name.clone(), self.id.to_string()]
+    }
+
+    /// Move the head of a branch to the `head` commit, using a `client`
+    /// which can be inside a transaction
+    pub async fn update_head(
+        client: &Client,
+        id: &Uuid,
+        head: &str,
+    ) -> Result<Branch, AppError> {
+        let statement = client
+            .prepare("UPDATE branch SET head=$1 WHERE id=$2 RETURNING *")
+            .await?;
+
+        let branch = client
+            .query_opt(&statement, &[&head, &id])
+            .await?
+            .map(|row| Branch::from_row_ref(&row).unwrap());
+
+        match branch {
+            Some(branch) => Ok(branch),
+            None => Err(AppError {
+                error_type: AppErrorType::NotFoundError,
+                cause: None,
+                message: Some("Branch not found".to_string()),
+            }),
+        }
+    }
 }
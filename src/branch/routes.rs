@@ -1,21 +1,26 @@
-use crate::branch::models::Branch;
 use crate::config::AppState;
-use crate::errors::{AppError, AppErrorResponse, AppErrorType};
-use crate::helpers::uuid_from_string;
+use crate::errors::AppErrorResponse;
+use crate::helpers::{check_authorization, uuid_from_string};
+use crate::pagination::Pagination;
 
-use actix_web::http::header;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use slog::info;
-use std::env;
 use uuid::Uuid;
 
-/// Endpoint used for getting all commits
-async fn index(state: web::Data<AppState>) -> impl Responder {
+/// Endpoint used for getting a page of branches
+async fn index(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
     info!(state.log, "GET /branch/");
-    let result = Branch::find_all(state.pool.clone()).await;
+    let pagination = match Pagination::from_request(&req) {
+        Ok(pagination) => pagination,
+        Err(e) => return HttpResponse::from_error(e.into()),
+    };
+    let result = state.storage.find_branches(&pagination).await;
 
     match result {
-        Ok(branches) => HttpResponse::Ok().json(branches),
+        Ok(branches) => HttpResponse::Ok().json(branches.link(&req)),
         _ => HttpResponse::BadRequest().json(AppErrorResponse {
             detail: "Error trying to read all branches from database"
                 .to_string(),
@@ -23,19 +28,22 @@ async fn index(state: web::Data<AppState>) -> impl Responder {
     }
 }
 
-// Endpoint used for getting branches of a repository
+// Endpoint used for getting a page of the branches of a repository
 async fn get_repo_branch(
+    req: HttpRequest,
     state: web::Data<AppState>,
     repo: web::Path<String>,
 ) -> impl Responder {
     let uuid: Uuid = uuid_from_string(&repo);
     info!(state.log, "GET /branch/repo/{}/", &uuid);
 
-    let result = Branch::find_by_repo(state.pool.clone(), &uuid).await;
+    let pagination = Pagination::from_request(&req)?;
+    let result = state
+        .storage
+        .find_branches_of_repository(&uuid, &pagination)
+        .await;
 
-    result
-        .map(|branches| HttpResponse::Ok().json(branches))
-        .map_err(|e| e)
+    result.map(|branches| HttpResponse::Ok().json(branches.link(&req)))
 }
 
 /// Endpoint used for retrieve a repository that matches with an `id`.
@@ -46,7 +54,7 @@ async fn get_branch(
 ) -> impl Responder {
     let uuid: Uuid = uuid_from_string(&id);
 
-    let result = Branch::find(state.pool.clone(), &uuid).await;
+    let result = state.storage.find_branch(&uuid).await;
     info!(state.log, "GET /branch/{}/", id);
 
     // `map_err` is also used when repo is not found
@@ -63,33 +71,15 @@ async fn delete_branch(
     id: web::Path<String>,
 ) -> impl Responder {
     let uuid: Uuid = uuid_from_string(&id);
-    match req.headers().get(header::AUTHORIZATION) {
-        Some(x)
-            if x.to_str().unwrap()
-                != env::var("SECRET_KEY").unwrap_or("".to_string()) =>
-        {
-            info!(state.log, "DELETE /branch/{}/ 401", id);
-            return Err(AppError {
-                error_type: AppErrorType::AuthorizationError,
-                message: Some(
-                    "You must provide a valid Authorization".to_string(),
-                ),
-                cause: None,
-            });
-        }
-        Some(_) => {}
-        None => {
-            info!(state.log, "DELETE /branch/{}/ 400", id);
-            return Ok(HttpResponse::BadRequest().body(""));
-        }
-    };
+    if let Err(e) = check_authorization(&req) {
+        info!(state.log, "DELETE /branch/{}/ 401", id);
+        return Err(e);
+    }
 
-    let result = Branch::delete(state.pool.clone(), &uuid).await;
+    let result = state.storage.delete_branch(&uuid).await;
     info!(state.log, "DELETE /branch/{}/", id);
 
-    result
-        .map(|_| HttpResponse::NoContent().body(""))
-        .map_err(|e| e)
+    result.map(|_| HttpResponse::NoContent().body(""))
 }
 
 /// Routes for branches
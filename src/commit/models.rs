@@ -1,11 +1,18 @@
+use crate::config::{AvatarConfig, EmailPrivacy};
 use crate::db::get_client;
+use crate::email::models::{Avatar, Email};
 use crate::errors::{AppError, AppErrorType};
+use crate::helpers::deserialize_flag;
+use crate::pagination::{Key, Page, Pagination};
 
-use chrono::{DateTime, Local};
-use deadpool_postgres::Pool;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+use deadpool_postgres::{Client, Pool};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
+use tokio_postgres::types::ToSql;
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "commit")]
@@ -20,80 +27,475 @@ pub struct Commit {
     pub committer_email: String, // Reference to Email
     pub committer_name: String,
     pub repository_url: String, // Reference to Repository
+    pub is_merge: bool,
+    /// Lines added, compared with the first parent. Unknown for the commits
+    /// imported before the lines were counted
+    pub additions: Option<i32>,
+    /// Lines deleted, compared with the first parent
+    pub deletions: Option<i32>,
 }
 
-/// Model used for 'most authors' function
+/// Filters of the commits list. Every filter is optional and all the filters
+/// sent must match
+#[derive(Deserialize)]
+pub struct CommitFilters {
+    /// Part of the hash
+    #[serde(rename = "q")]
+    pub hash: Option<String>,
+    pub repository_user: Option<String>,
+    pub repository_name: Option<String>,
+    pub author_email: Option<String>,
+    /// MD5 hash of the author email, used instead of `author_email` when the
+    /// emails are private
+    pub author_hash: Option<String>,
+    /// Part of the author name, case insensitive
+    pub author_name: Option<String>,
+    pub committer_email: Option<String>,
+    /// MD5 hash of the committer email
+    pub committer_hash: Option<String>,
+    /// Part of the committer name, case insensitive
+    pub committer_name: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Id of a branch: only the commits of its first-parent history match
+    pub branch: Option<Uuid>,
+    /// `true` for merge commits only, `false` to exclude them
+    pub merge: Option<bool>,
+    /// Part of the message, case insensitive
+    pub message: Option<String>,
+    /// `true` to skip the commits whose author is a bot
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    pub exclude_bots: bool,
+}
+
+/// Commits reachable from the head of the branch `$11`, following their first
+/// parent
+const BRANCH_HISTORY: &str = "
+    WITH RECURSIVE history AS (
+        SELECT commit.hash, commit.tree FROM commit
+        JOIN branch ON branch.head = commit.hash
+        WHERE branch.id = $11
+        UNION ALL
+        SELECT commit.hash, commit.tree FROM commit
+        JOIN history ON commit.hash = history.tree
+    )";
+
+/// Conditions of `CommitFilters`. A `NULL` parameter is a filter not sent
+const FILTERS: &str = "
+    ($1::text IS NULL OR hash LIKE $1)
+    AND ($2::varchar IS NULL OR repository_url = $2)
+    AND ($3::varchar IS NULL OR author_email = $3)
+    AND ($4::text IS NULL OR author_name ILIKE $4)
+    AND ($5::varchar IS NULL OR committer_email = $5)
+    AND ($6::text IS NULL OR committer_name ILIKE $6)
+    AND ($7::timestamptz IS NULL OR date >= $7)
+    AND ($8::timestamptz IS NULL OR date <= $8)
+    AND ($9::boolean IS NULL OR is_merge = $9)
+    AND ($10::text IS NULL OR text ILIKE $10)
+    AND ($11::uuid IS NULL OR hash IN (SELECT hash FROM history))
+    AND NOT ($12 AND author_email IN (SELECT email FROM email WHERE is_bot))";
+
+/// Parameters of the ranking of the authors. Their commits can be limited to
+/// a repository, to the history of a branch and to a period
+#[derive(Deserialize)]
+pub struct TopFilters {
+    pub repository_user: Option<String>,
+    pub repository_name: Option<String>,
+    /// Id of a branch: only the commits of its first-parent history count
+    pub branch: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Number of authors, all of them by default
+    pub limit: Option<i64>,
+    /// `commits` (the default), `lines` changed or active `days`
+    pub rank: Option<String>,
+}
+
+/// Parameters of the commit activity. The commits can be limited to a
+/// repository, to an author, to the history of a branch and to a period
+#[derive(Deserialize)]
+pub struct ActivityFilters {
+    /// `day`, `week`, `month` (the default) or `year`
+    pub period: Option<String>,
+    /// IANA name of the timezone of the periods, like "Europe/Rome". UTC by
+    /// default
+    pub timezone: Option<String>,
+    pub repository_user: Option<String>,
+    pub repository_name: Option<String>,
+    pub author_email: Option<String>,
+    /// MD5 hash of the author email, used instead of `author_email` when the
+    /// emails are private
+    pub author_hash: Option<String>,
+    /// Id of a branch: only the commits of its first-parent history count
+    pub branch: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// `true` to skip the commits whose author is a bot
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    pub exclude_bots: bool,
+}
+
+/// Number of commits in a period
+#[derive(Serialize)]
+pub struct CommitActivity {
+    /// First day of the period, in the timezone of the filters. Weeks start
+    /// on Monday
+    pub period: NaiveDate,
+    pub commits: i64,
+}
+
+/// Model used for 'most authors' function. The commits of the emails of a
+/// contributor are counted together, with its name and its first email
 #[derive(Serialize, Deserialize)]
 pub struct CommitNumAuthor {
     pub num: i64,
+    pub contributor_id: Option<Uuid>,
     pub author_email: String,
     pub author_name: String,
+    /// Lines added by the commits whose lines are known
+    pub additions: i64,
+    /// Lines deleted by the commits whose lines are known
+    pub deletions: i64,
+    /// Days with at least a commit, in UTC
+    pub days: i64,
+}
+
+/// Markers of the matches inside the snippets made by the database. They
+/// are private use characters, which the messages should not contain
+pub const MATCH_START: char = '\u{e000}';
+pub const MATCH_END: char = '\u{e001}';
+
+/// Commit found by a full-text search, with its rank and a snippet of its
+/// message, where the matches are between `MATCH_START` and `MATCH_END`.
+/// `with_avatars` makes the snippet HTML, highlighting them by `<mark>`
+#[derive(Serialize)]
+pub struct CommitSearchResult {
+    #[serde(flatten)]
+    pub commit: Commit,
+    pub rank: f64,
+    pub snippet: String,
+}
+
+/// Commit, or search result, with the avatars of its author and committer,
+/// as returned by the API
+#[derive(Serialize)]
+pub struct CommitWithAvatars<T> {
+    #[serde(flatten)]
+    pub commit: T,
+    pub author_avatar: Avatar,
+    pub committer_avatar: Avatar,
+}
+
+impl CommitFilters {
+    /// Url of the repository, when both its user and its name are sent
+    pub fn repository_url(&self) -> Option<String> {
+        match (&self.repository_user, &self.repository_name) {
+            (Some(user), Some(name)) => Some(format!("{}/{}", user, name)),
+            _ => None,
+        }
+    }
+}
+
+impl ActivityFilters {
+    /// Length of the periods
+    pub fn period(&self) -> Result<&str, AppError> {
+        match self.period.as_deref() {
+            None => Ok("month"),
+            Some(period @ "day")
+            | Some(period @ "week")
+            | Some(period @ "month")
+            | Some(period @ "year") => Ok(period),
+            Some(_) => Err(AppError {
+                message: Some(
+                    "Period must be \"day\", \"week\", \"month\" or \"year\""
+                        .to_string(),
+                ),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            }),
+        }
+    }
+
+    /// Name of the timezone, checked against the IANA database
+    pub fn timezone(&self) -> Result<String, AppError> {
+        match self.timezone.as_deref() {
+            None => Ok("UTC".to_string()),
+            Some(name) => match name.parse::<Tz>() {
+                Ok(timezone) => Ok(timezone.name().to_string()),
+                Err(_) => Err(AppError {
+                    message: Some("Invalid timezone".to_string()),
+                    cause: None,
+                    error_type: AppErrorType::BadRequestError,
+                }),
+            },
+        }
+    }
+
+    /// Url of the repository, when both its user and its name are sent
+    pub fn repository_url(&self) -> Option<String> {
+        match (&self.repository_user, &self.repository_name) {
+            (Some(user), Some(name)) => Some(format!("{}/{}", user, name)),
+            _ => None,
+        }
+    }
+}
+
+impl TopFilters {
+    /// What the authors are ranked by
+    pub fn rank(&self) -> Result<&str, AppError> {
+        match self.rank.as_deref() {
+            None => Ok("commits"),
+            Some(rank @ "commits")
+            | Some(rank @ "lines")
+            | Some(rank @ "days") => Ok(rank),
+            Some(_) => Err(AppError {
+                message: Some(
+                    "Rank must be \"commits\", \"lines\" or \"days\""
+                        .to_string(),
+                ),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            }),
+        }
+    }
+
+    /// Number of authors, when it is sent
+    pub fn limit(&self) -> Result<Option<i64>, AppError> {
+        match self.limit {
+            Some(limit) if limit < 1 => Err(AppError {
+                message: Some("Limit must be at least 1".to_string()),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            }),
+            limit => Ok(limit),
+        }
+    }
+
+    /// Url of the repository, when both its user and its name are sent
+    pub fn repository_url(&self) -> Option<String> {
+        match (&self.repository_user, &self.repository_name) {
+            (Some(user), Some(name)) => Some(format!("{}/{}", user, name)),
+            _ => None,
+        }
+    }
+}
+
+impl CommitSearchResult {
+    /// Add the avatars of the author and of the committer to the result,
+    /// then hide their emails, and the ones of its snippet, as required by
+    /// `privacy`
+    pub fn with_avatars(
+        mut self,
+        config: &AvatarConfig,
+        privacy: EmailPrivacy,
+    ) -> CommitWithAvatars<CommitSearchResult> {
+        let author_avatar =
+            Avatar::new(&self.commit.author_email, config, None);
+        let committer_avatar =
+            Avatar::new(&self.commit.committer_email, config, None);
+        self.commit.hide_emails(privacy);
+        self.snippet = highlight(&privacy.show_in(&self.snippet));
+
+        CommitWithAvatars {
+            commit: self,
+            author_avatar,
+            committer_avatar,
+        }
+    }
 }
 
 impl Commit {
-    /// Find all commits. Order them by descrescent `date` field
-    /// `commit_hash` is used to search commit that matches with some sha codes
+    /// Find a page of the commits which match all the `filters`. Order them
+    /// by descrescent `date` field
     pub async fn find_all(
         pool: Pool,
-        commit_hash: &String,
-    ) -> Result<Vec<Commit>, AppError> {
+        filters: &CommitFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<Commit>, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
 
-        let query;
-        let hash;
-        if commit_hash != "" {
-            hash = format!("%{}%", commit_hash);
-            query = "SELECT * FROM commit WHERE hash LIKE $1 ORDER BY date DESC LIMIT 300";
-        } else {
-            hash = String::new();
-            query = "SELECT * FROM commit ORDER BY date DESC LIMIT 300"
-        }
+        let hash = filters.hash.as_deref().map(like_pattern);
+        let author_name = filters.author_name.as_deref().map(like_pattern);
+        let committer_name =
+            filters.committer_name.as_deref().map(like_pattern);
+        let message = filters.message.as_deref().map(like_pattern);
+        let after = pagination.after(&[Key::Timestamp, Key::Text])?;
 
-        let statement = client.prepare(query).await?;
-
-        let commits;
-        if hash != "" {
-            commits = client.query(&statement, &[&hash]).await?;
-        } else {
-            commits = client.query(&statement, &[]).await?;
-        }
+        let statement = client
+            .prepare(&format!(
+                "
+                {}
+                SELECT * FROM commit
+                WHERE {}
+                    AND ($13::text IS NULL
+                        OR (date, hash) < ($13::text::timestamptz, $14))
+                ORDER BY date DESC, hash DESC
+                LIMIT $15
+                ",
+                BRANCH_HISTORY, FILTERS
+            ))
+            .await?;
 
-        let result = commits
+        let params: [&(dyn ToSql + Sync); 12] = [
+            &hash,
+            &filters.repository_url(),
+            &filters.author_email,
+            &author_name,
+            &filters.committer_email,
+            &committer_name,
+            &filters.since,
+            &filters.until,
+            &filters.merge,
+            &message,
+            &filters.branch,
+            &filters.exclude_bots,
+        ];
+        let fetch = pagination.fetch();
+        let commits = client
+            .query(
+                &statement,
+                &[&params[..], &[&after[0], &after[1], &fetch]].concat(),
+            )
+            .await?
             .iter()
             .map(|row| Commit::from_row_ref(row).unwrap())
             .collect::<Vec<Commit>>();
 
-        Ok(result)
+        let count = if pagination.count {
+            let statement = client
+                .prepare(&format!(
+                    "{} SELECT COUNT(*) FROM commit WHERE {}",
+                    BRANCH_HISTORY, FILTERS
+                ))
+                .await?;
+            Some(client.query_one(&statement, &params).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(commits, pagination, count, Commit::keys))
     }
 
-    /// Find all repository url' commits
-    pub async fn find_by_repository(
+    /// Search commits which messages match `query`. Quoted words are searched
+    /// as a phrase and words ending with `*` as a prefix. The search can be
+    /// limited to a repository or to an author email, and skip the bots
+    pub async fn search(
         pool: Pool,
-        repository_url: String,
-    ) -> Result<Vec<Commit>, AppError> {
-        let client = get_client(pool.clone()).await.unwrap();
+        query: &str,
+        repository_url: Option<String>,
+        author_email: Option<String>,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitSearchResult>, AppError> {
+        let tsquery = match tsquery(query) {
+            Some(tsquery) => tsquery,
+            None => {
+                return Err(AppError {
+                    message: Some("Search query is empty".to_string()),
+                    cause: None,
+                    error_type: AppErrorType::BadRequestError,
+                });
+            }
+        };
 
+        let client = get_client(pool.clone()).await.unwrap();
+        let after =
+            pagination.after(&[Key::Float, Key::Timestamp, Key::Text])?;
+        // The headline is made only for the commits of the page
         let statement = client
             .prepare(
-                "SELECT * FROM commit
-            WHERE repository_url = $1
-            ORDER BY date DESC
-            LIMIT 1000",
+                "
+                SELECT found.*,
+                    ts_headline(
+                        'english', text, to_tsquery('english', $1), $9
+                    ) AS snippet
+                FROM (
+                    SELECT commit.*, ts_rank(search, query)::float8 AS rank
+                    FROM commit, to_tsquery('english', $1) query
+                    WHERE search @@ query
+                        AND ($2::varchar IS NULL OR repository_url = $2)
+                        AND ($3::varchar IS NULL OR author_email = $3)
+                        AND NOT ($4 AND author_email IN (
+                            SELECT email FROM email WHERE is_bot
+                        ))
+                ) found
+                WHERE $5::text IS NULL
+                    OR (rank, date, hash)
+                        < ($5::text::float8, $6::text::timestamptz, $7)
+                ORDER BY rank DESC, date DESC, hash DESC
+                LIMIT $8
+                ",
             )
             .await?;
 
         let commits = client
-            .query(&statement, &[&repository_url])
+            .query(
+                &statement,
+                &[
+                    &tsquery,
+                    &repository_url,
+                    &author_email,
+                    &exclude_bots,
+                    &after[0],
+                    &after[1],
+                    &after[2],
+                    &pagination.fetch(),
+                    &format!(
+                        "StartSel={}, StopSel={}, MaxFragments=2",
+                        MATCH_START, MATCH_END
+                    ),
+                ],
+            )
             .await?
             .iter()
-            .map(|row| Commit::from_row_ref(row).unwrap())
-            .collect::<Vec<Commit>>();
+            .map(|row| CommitSearchResult {
+                commit: Commit::from_row_ref(row).unwrap(),
+                rank: row.get("rank"),
+                snippet: row.get("snippet"),
+            })
+            .collect::<Vec<CommitSearchResult>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare(
+                    "
+                    SELECT COUNT(*) FROM commit
+                    WHERE search @@ to_tsquery('english', $1)
+                        AND ($2::varchar IS NULL OR repository_url = $2)
+                        AND ($3::varchar IS NULL OR author_email = $3)
+                        AND NOT ($4 AND author_email IN (
+                            SELECT email FROM email WHERE is_bot
+                        ))
+                    ",
+                )
+                .await?;
+            Some(
+                client
+                    .query_one(
+                        &statement,
+                        &[
+                            &tsquery,
+                            &repository_url,
+                            &author_email,
+                            &exclude_bots,
+                        ],
+                    )
+                    .await?
+                    .get(0),
+            )
+        } else {
+            None
+        };
 
-        Ok(commits)
+        Ok(Page::new(commits, pagination, count, |result| {
+            let mut keys = vec![result.rank.to_string()];
+            keys.append(&mut result.commit.keys());
+            keys
+        }))
     }
 
     // Find a commit that it has an hash equals to `hash`
-    pub async fn find(pool: Pool, hash: String) -> Result<Commit, AppError> {
+    pub async fn find(pool: Pool, hash: &str) -> Result<Commit, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
         let statement = client
             .prepare("SELECT * FROM commit WHERE hash = $1")
@@ -114,30 +516,80 @@ impl Commit {
         }
     }
 
-    /// Find a commit and delete it, but before check if "Authorization"
-    /// matches with SECRET_KEY
-    pub async fn delete(
+    /// Hashes of the commits of a repository without their lines, which were
+    /// imported before the merges and the lines were read: the merges of
+    /// the oldest ones are unknown too
+    pub async fn find_incomplete(
         pool: Pool,
-        hash: &String,
-    ) -> Result<Commit, AppError> {
+        repository_url: &str,
+    ) -> Result<Vec<String>, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
         let statement = client
             .prepare(
+                "
+                SELECT hash FROM commit
+                WHERE repository_url = $1 AND additions IS NULL
+                ",
+            )
+            .await?;
+
+        let hashes = client
+            .query(&statement, &[&repository_url])
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect::<Vec<String>>();
+
+        Ok(hashes)
+    }
+
+    /// Find a commit and delete it, with the commits which descend from it,
+    /// counting again the names of their emails, but before check if
+    /// "Authorization" matches with SECRET_KEY
+    pub async fn delete(pool: Pool, hash: &str) -> Result<Commit, AppError> {
+        let mut client = get_client(pool.clone()).await.unwrap();
+        let transaction = client.transaction().await?;
+
+        let emails: Vec<String> = transaction
+            .query(
+                "
+                WITH RECURSIVE deleted AS (
+                    SELECT hash, author_email, committer_email FROM commit
+                    WHERE hash = $1
+                    UNION
+                    SELECT commit.hash, commit.author_email,
+                        commit.committer_email
+                    FROM commit JOIN deleted ON commit.tree = deleted.hash
+                )
+                SELECT author_email FROM deleted
+                UNION
+                SELECT committer_email FROM deleted
+                ",
+                &[&hash],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        let commit = transaction
+            .query_opt(
                 "
                 DELETE FROM commit
                 WHERE hash=$1
                 RETURNING *
                 ",
+                &[&hash],
             )
-            .await?;
-
-        let commit = client
-            .query_opt(&statement, &[&hash])
             .await?
             .map(|row| Commit::from_row_ref(&row).unwrap());
 
         match commit {
-            Some(commit) => Ok(commit),
+            Some(commit) => {
+                Email::refresh_names(&transaction, &emails).await?;
+                transaction.commit().await?;
+                Ok(commit)
+            }
             None => Err(AppError {
                 error_type: AppErrorType::NotFoundError,
                 cause: None,
@@ -146,77 +598,503 @@ impl Commit {
         }
     }
 
-    /// Create commits from an array
+    /// Create commits from an array, using a `client` which can be inside a
+    /// transaction, and count them in the names of their emails. Commits
+    /// already saved are skipped: returns the number of the new ones
     pub async fn create(
-        pool: Pool,
+        client: &Client,
         commits: Vec<Commit>,
-    ) -> Result<Vec<Commit>, AppError> {
-        let client = get_client(pool.clone()).await.unwrap();
-        let mut raw_query = "INSERT INTO commit VALUES".to_string();
+    ) -> Result<usize, AppError> {
+        // Every column is sent as an array parameter, so the values are
+        // never part of the query and a batch is saved with one statement
+        let mut hashes: Vec<String> = Vec::with_capacity(commits.len());
+        let mut trees: Vec<Option<String>> = Vec::with_capacity(commits.len());
+        let mut texts: Vec<String> = Vec::with_capacity(commits.len());
+        let mut dates: Vec<DateTime<Local>> =
+            Vec::with_capacity(commits.len());
+        let mut author_emails: Vec<String> = Vec::with_capacity(commits.len());
+        let mut author_names: Vec<String> = Vec::with_capacity(commits.len());
+        let mut committer_emails: Vec<String> =
+            Vec::with_capacity(commits.len());
+        let mut committer_names: Vec<String> =
+            Vec::with_capacity(commits.len());
+        let mut repository_urls: Vec<String> =
+            Vec::with_capacity(commits.len());
+        let mut merges: Vec<bool> = Vec::with_capacity(commits.len());
+        let mut additions: Vec<Option<i32>> =
+            Vec::with_capacity(commits.len());
+        let mut deletions: Vec<Option<i32>> =
+            Vec::with_capacity(commits.len());
 
         for commit in commits {
-            let tree = match commit.tree {
-                Some(t) => format!("'{}'", t),
-                None => "NULL".to_string(),
-            };
-            raw_query += &format!(
-                "('{}', {}, E'{}', '{}', '{}', E'{}', '{}', E'{}', '{}'),",
-                commit.hash,
-                tree,
-                commit.text.replace("\\'", "'").replace("'", "\\'"),
-                commit.date,
-                commit.author_email,
-                commit.author_name.replace("\\'", "'").replace("'", "\\'"),
-                commit.committer_email,
-                commit
-                    .committer_name
-                    .replace("\\'", "'")
-                    .replace("'", "\\'"),
-                commit.repository_url
-            )[..]
+            hashes.push(commit.hash);
+            trees.push(commit.tree);
+            texts.push(commit.text);
+            dates.push(commit.date);
+            author_emails.push(commit.author_email);
+            author_names.push(commit.author_name);
+            committer_emails.push(commit.committer_email);
+            committer_names.push(commit.committer_name);
+            repository_urls.push(commit.repository_url);
+            merges.push(commit.is_merge);
+            additions.push(commit.additions);
+            deletions.push(commit.deletions);
         }
 
-        // Remove the last `,`
-        let _ = raw_query.pop();
-        raw_query += " RETURNING *";
+        let statement = client
+            .prepare(
+                "
+                WITH created AS (
+                    INSERT INTO commit(
+                        hash, tree, text, date, author_email, author_name,
+                        committer_email, committer_name, repository_url,
+                        is_merge, additions, deletions
+                    )
+                    SELECT * FROM UNNEST(
+                        $1::varchar[], $2::varchar[], $3::text[],
+                        $4::timestamptz[], $5::varchar[], $6::varchar[],
+                        $7::varchar[], $8::varchar[], $9::varchar[],
+                        $10::boolean[], $11::integer[], $12::integer[]
+                    )
+                    ON CONFLICT (hash) DO NOTHING
+                    RETURNING *
+                ), completed AS (
+                    UPDATE commit SET
+                        is_merge = stats.is_merge,
+                        additions = stats.additions,
+                        deletions = stats.deletions
+                    FROM UNNEST(
+                        $1::varchar[], $10::boolean[], $11::integer[],
+                        $12::integer[]
+                    ) AS stats(hash, is_merge, additions, deletions)
+                    WHERE commit.hash = stats.hash
+                        AND commit.additions IS NULL
+                ), names AS (
+                    INSERT INTO email_name(
+                        email, name, commits, first_seen, last_seen
+                    )
+                    SELECT email, name, COUNT(*), MIN(date), MAX(date) FROM (
+                        SELECT hash, date, author_email AS email,
+                            author_name AS name
+                        FROM created
+                        UNION
+                        SELECT hash, date, committer_email, committer_name
+                        FROM created
+                    ) uses
+                    WHERE name <> ''
+                    GROUP BY email, name
+                    ON CONFLICT (email, name) DO UPDATE SET
+                        commits = email_name.commits + EXCLUDED.commits,
+                        first_seen = LEAST(
+                            email_name.first_seen, EXCLUDED.first_seen
+                        ),
+                        last_seen = GREATEST(
+                            email_name.last_seen, EXCLUDED.last_seen
+                        )
+                )
+                SELECT COUNT(*) FROM created
+                ",
+            )
+            .await?;
+        let created: i64 = client
+            .query_one(
+                &statement,
+                &[
+                    &hashes,
+                    &trees,
+                    &texts,
+                    &dates,
+                    &author_emails,
+                    &author_names,
+                    &committer_emails,
+                    &committer_names,
+                    &repository_urls,
+                    &merges,
+                    &additions,
+                    &deletions,
+                ],
+            )
+            .await?
+            .get(0);
+
+        Ok(created as usize)
+    }
 
-        // TODO: write query with &commits and parameter. Need to implement
-        // ToSql trait for `Commit` model
-        // let statement = client.prepare(&query[..]).await?;
-        // client.query(&statement, &[&commits]
+    /// Add the avatars of the author and of the committer to the commit,
+    /// then hide their emails as required by `privacy`
+    pub fn with_avatars(
+        mut self,
+        config: &AvatarConfig,
+        privacy: EmailPrivacy,
+    ) -> CommitWithAvatars<Commit> {
+        let author_avatar = Avatar::new(&self.author_email, config, None);
+        let committer_avatar =
+            Avatar::new(&self.committer_email, config, None);
+        self.hide_emails(privacy);
+
+        CommitWithAvatars {
+            commit: self,
+            author_avatar,
+            committer_avatar,
+        }
+    }
 
-        let statement = client.prepare(&raw_query[..]).await?;
-        let result = client
-            .query(&statement, &[])
+    /// Replace the emails of the author and of the committer, and the ones
+    /// inside the message, by what `privacy` shows of them
+    pub fn hide_emails(&mut self, privacy: EmailPrivacy) {
+        self.author_email = privacy.show(&self.author_email);
+        self.committer_email = privacy.show(&self.committer_email);
+        self.text = privacy.show_in(&self.text);
+    }
+
+    /// Values used to sort the commits, saved in the pagination cursor
+    pub fn keys(&self) -> Vec<String> {
+        vec![self.date.to_rfc3339(), self.hash.clone()]
+    }
+
+    /// Find a page of the number of commits in each period, the oldest
+    /// first, from the period of `since`, or of the first commit, to the one
+    /// of `until`, or of the last commit. The periods without commits are
+    /// included
+    pub async fn activity(
+        pool: Pool,
+        filters: &ActivityFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitActivity>, AppError> {
+        let period = filters.period()?;
+        let timezone = filters.timezone()?;
+        let after = pagination.after(&[Key::Date])?;
+
+        // `$13` is the period and `$14` the timezone
+        let activity = format!(
+            "
+            {}, counts AS (
+                SELECT date_trunc($13, date AT TIME ZONE $14)::date AS period,
+                    COUNT(*) AS commits
+                FROM commit
+                WHERE {}
+                GROUP BY 1
+            ), bounds AS (
+                SELECT COALESCE(
+                        date_trunc($13, $7 AT TIME ZONE $14)::date,
+                        MIN(period)
+                    ) AS first,
+                    COALESCE(
+                        date_trunc($13, $8 AT TIME ZONE $14)::date,
+                        MAX(period)
+                    ) AS last
+                FROM counts
+            )
+            SELECT series.period::date AS period,
+                COALESCE(counts.commits, 0) AS commits
+            FROM bounds,
+                generate_series(first, last, ('1 ' || $13)::interval)
+                    AS series(period)
+            LEFT JOIN counts ON counts.period = series.period::date
+            ",
+            BRANCH_HISTORY, FILTERS
+        );
+
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(&format!(
+                "
+                SELECT * FROM ({}) activity
+                WHERE $15::text IS NULL OR period > $15::text::date
+                ORDER BY period
+                LIMIT $16
+                ",
+                activity
+            ))
+            .await?;
+
+        // Only the filters of a repository, an author, a period and a branch
+        // are sent
+        let none: Option<String> = None;
+        let params: [&(dyn ToSql + Sync); 14] = [
+            &none,
+            &filters.repository_url(),
+            &filters.author_email,
+            &none,
+            &none,
+            &none,
+            &filters.since,
+            &filters.until,
+            &None::<bool>,
+            &none,
+            &filters.branch,
+            &filters.exclude_bots,
+            &period,
+            &timezone,
+        ];
+        let fetch = pagination.fetch();
+        let periods = client
+            .query(&statement, &[&params[..], &[&after[0], &fetch]].concat())
             .await?
             .iter()
-            .map(|row| Commit::from_row_ref(row).unwrap())
-            .collect::<Vec<Commit>>();
+            .map(|row| CommitActivity {
+                period: row.get(0),
+                commits: row.get(1),
+            })
+            .collect::<Vec<CommitActivity>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare(&format!(
+                    "SELECT COUNT(*) FROM ({}) activity",
+                    activity
+                ))
+                .await?;
+            Some(client.query_one(&statement, &params).await?.get(0))
+        } else {
+            None
+        };
 
-        Ok(result)
+        Ok(Page::new(periods, pagination, count, |activity| {
+            vec![activity.period.to_string()]
+        }))
     }
 
-    /// Returns a ranking of authors of his commits number
+    /// Rank the authors of the commits which match `filters` by their
+    /// commits, their lines changed or their active days
     pub async fn most_authors(
         pool: Pool,
+        filters: &TopFilters,
+        exclude_bots: bool,
     ) -> Result<Vec<CommitNumAuthor>, AppError> {
+        // The authors whose lines are all unknown are not ranked by lines
+        let (having, order) = match filters.rank()? {
+            "lines" => (
+                "HAVING COUNT(additions) > 0",
+                "SUM(COALESCE(additions, 0) + COALESCE(deletions, 0)) DESC,",
+            ),
+            "days" => {
+                ("", "COUNT(DISTINCT (date AT TIME ZONE 'UTC')::date) DESC,")
+            }
+            _ => ("", ""),
+        };
+        let limit = filters.limit()?;
+
         let client = get_client(pool.clone()).await.unwrap();
-        let statement = client.prepare(
-                "SELECT COUNT(hash) as num, author_email, author_name FROM commit
-                GROUP BY author_email, author_name ORDER BY COUNT(hash) DESC"
-            ).await?;
+        let statement = client
+            .prepare(&format!(
+                "
+                {}
+                SELECT COUNT(hash) AS num, email.contributor_id,
+                    MIN(author_email) AS author_email,
+                    COALESCE(MIN(contributor.name), MIN(author_name))
+                        AS author_name,
+                    COALESCE(SUM(additions), 0) AS additions,
+                    COALESCE(SUM(deletions), 0) AS deletions,
+                    COUNT(DISTINCT (date AT TIME ZONE 'UTC')::date) AS days
+                FROM commit
+                JOIN email ON email.email = commit.author_email
+                LEFT JOIN contributor ON contributor.id = email.contributor_id
+                WHERE {}
+                GROUP BY email.contributor_id,
+                    CASE WHEN email.contributor_id IS NULL
+                        THEN author_email END
+                {}
+                ORDER BY {} COUNT(hash) DESC, MIN(author_email)
+                LIMIT $13
+                ",
+                BRANCH_HISTORY, FILTERS, having, order
+            ))
+            .await?;
 
+        // Only the filters of a repository, a period and a branch are sent
+        let none: Option<String> = None;
         let authors = client
-            .query(&statement, &[])
+            .query(
+                &statement,
+                &[
+                    &none,
+                    &filters.repository_url(),
+                    &none,
+                    &none,
+                    &none,
+                    &none,
+                    &filters.since,
+                    &filters.until,
+                    &None::<bool>,
+                    &none,
+                    &filters.branch,
+                    &exclude_bots,
+                    &limit,
+                ],
+            )
             .await?
             .iter()
             .map(|row| CommitNumAuthor {
                 num: row.get(0),
-                author_email: row.get(1),
-                author_name: row.get(2),
+                contributor_id: row.get(1),
+                author_email: row.get(2),
+                author_name: row.get(3),
+                additions: row.get(4),
+                deletions: row.get(5),
+                days: row.get(6),
             })
             .collect::<Vec<CommitNumAuthor>>();
 
         Ok(authors)
     }
 }
+
+/// A term of a search query: a word, or a phrase when it has more words.
+/// The last word of a `prefix` term matches every word which starts with it
+pub struct SearchTerm {
+    pub words: Vec<String>,
+    pub prefix: bool,
+}
+
+/// Split a search query into terms: a quoted text is a phrase and a term
+/// ending with `*` is a prefix. Everything but letters and digits is
+/// dropped, so the terms are always safe to use in a full-text query
+pub fn search_terms(query: &str) -> Vec<SearchTerm> {
+    let words = |text: &str| -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_string())
+            .collect()
+    };
+
+    let mut terms: Vec<SearchTerm> = vec![];
+    // Odd parts are inside quotes
+    for (i, part) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            let phrase = words(part);
+            if !phrase.is_empty() {
+                terms.push(SearchTerm {
+                    words: phrase,
+                    prefix: false,
+                });
+            }
+            continue;
+        }
+
+        for term in part.split_whitespace() {
+            let term_words = words(term);
+            if !term_words.is_empty() {
+                terms.push(SearchTerm {
+                    words: term_words,
+                    prefix: term.ends_with('*'),
+                });
+            }
+        }
+    }
+
+    terms
+}
+
+/// Convert a search query into a `tsquery` where every term must match.
+/// Returns `None` when there is nothing to search
+fn tsquery(query: &str) -> Option<String> {
+    let terms = search_terms(query)
+        .into_iter()
+        .map(|term| {
+            let mut tsquery = term.words.join(" <-> ");
+            if term.prefix {
+                tsquery += ":*";
+            }
+            tsquery
+        })
+        .collect::<Vec<String>>();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" & "))
+    }
+}
+
+/// Escape the HTML of a snippet made by the database, then replace the
+/// markers of its matches with `<mark>` tags, so the message cannot add its
+/// own markup
+pub fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+
+    html
+}
+
+/// Pattern used by `LIKE` to find `text` inside a string. Its wildcards are
+/// escaped, so they match themselves
+pub fn like_pattern(text: &str) -> String {
+    format!("%{}%", like_escape(text))
+}
+
+/// Escape the wildcards of `LIKE` in `text` with a backslash
+pub fn like_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_terms_keep_only_letters_and_digits() {
+        let terms = search_terms(r#"fix' | !bug "<b>memory leak</b>" pars*"#);
+        let words: Vec<&Vec<String>> =
+            terms.iter().map(|term| &term.words).collect();
+
+        assert_eq!(
+            words,
+            vec![
+                &vec!["fix".to_string()],
+                &vec!["bug".to_string()],
+                &vec![
+                    "b".to_string(),
+                    "memory".to_string(),
+                    "leak".to_string(),
+                    "b".to_string()
+                ],
+                &vec!["pars".to_string()],
+            ]
+        );
+        assert_eq!(
+            terms.iter().map(|term| term.prefix).collect::<Vec<bool>>(),
+            vec![false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn tsquery_joins_the_terms() {
+        assert_eq!(
+            tsquery(r#"fix "memory leak" pars*"#),
+            Some("fix & memory <-> leak & pars:*".to_string())
+        );
+        assert_eq!(
+            tsquery("a&b|c:* !d"),
+            Some("a <-> b <-> c:* & d".to_string())
+        );
+        assert_eq!(tsquery(r#" & | "" ' "#), None);
+    }
+
+    #[test]
+    fn highlight_escapes_the_html() {
+        let snippet = format!(
+            "<script>'x' & \"y\"</script> {}fix{}",
+            MATCH_START, MATCH_END
+        );
+
+        assert_eq!(
+            highlight(&snippet),
+            "&lt;script&gt;&#39;x&#39; &amp; &quot;y&quot;&lt;/script&gt; \
+            <mark>fix</mark>"
+        );
+    }
+}
@@ -1,54 +1,72 @@
-use crate::commit::models::Commit;
-use crate::config::AppState;
+use crate::commit::models::{ActivityFilters, CommitFilters, TopFilters};
+use crate::config::{AppState, EmailPrivacy};
+use crate::email::models::normalize;
 use crate::errors::{AppError, AppErrorResponse, AppErrorType};
-use actix_web::http::header;
+use crate::helpers::{
+    bad_request, check_authorization, email_privacy, exclude_bots,
+};
+use crate::pagination::Pagination;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use slog::info;
 use std::collections::HashMap;
-use std::env;
 
-/// Endpoint used for getting all commits
+/// Endpoint used for getting a page of commits. See `CommitFilters` for the
+/// filters which can be sent
 async fn index(
     req: HttpRequest,
     state: web::Data<AppState>,
 ) -> impl Responder {
-    let query =
-        web::Query::<HashMap<String, String>>::from_query(req.query_string())
-            .unwrap();
+    info!(state.log, "GET /commit/?{}", req.query_string());
 
-    let hash = match query.get("q") {
-        Some(x) => x.clone(),
-        None => String::new(),
+    let mut filters =
+        match web::Query::<CommitFilters>::from_query(req.query_string()) {
+            Ok(filters) => filters.into_inner(),
+            Err(e) => {
+                return HttpResponse::BadRequest().json(AppErrorResponse {
+                    detail: format!("Invalid filters: {}", e),
+                })
+            }
+        };
+    let pagination = match Pagination::from_request(&req) {
+        Ok(pagination) => pagination,
+        Err(e) => return HttpResponse::from_error(e.into()),
     };
+    let privacy = email_privacy(&req, state.email_privacy);
 
-    let repo_user = match query.get("repository_user") {
-        Some(x) => x.clone(),
-        None => String::new(),
-    };
-    let repo_name = match query.get("repository_name") {
-        Some(x) => x.clone(),
-        None => String::new(),
-    };
-
-    let result;
-    if repo_user != "" && repo_name != "" {
-        info!(
-            state.log,
-            "GET /commit/?repository_user={}&repository_name={}",
-            &repo_user,
-            &repo_name
-        );
-        let repository_url = format!("{}/{}", repo_user, repo_name);
-        result =
-            Commit::find_by_repository(state.pool.clone(), repository_url)
-                .await;
-    } else {
-        info!(state.log, "GET /commit/?q={}", &hash);
-        result = Commit::find_all(state.pool.clone(), &hash).await;
+    let emails = (
+        email_filter(
+            &state,
+            privacy,
+            filters.author_email.take(),
+            filters.author_hash.take(),
+        )
+        .await,
+        email_filter(
+            &state,
+            privacy,
+            filters.committer_email.take(),
+            filters.committer_hash.take(),
+        )
+        .await,
+    );
+    match emails {
+        (Ok(author), Ok(committer)) => {
+            filters.author_email = author;
+            filters.committer_email = committer;
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            return HttpResponse::from_error(e.into())
+        }
     }
 
+    let result = state.storage.find_commits(&filters, &pagination).await;
+
     match result {
-        Ok(commits) => HttpResponse::Ok().json(commits),
+        Ok(commits) => HttpResponse::Ok().json(
+            commits
+                .map(|commit| commit.with_avatars(&state.avatar, privacy))
+                .link(&req),
+        ),
         _ => HttpResponse::BadRequest().json(AppErrorResponse {
             detail: "Error trying to read all commits from database"
                 .to_string(),
@@ -56,17 +74,80 @@ async fn index(
     }
 }
 
+/// Endpoint used for getting the number of commits in each period, for the
+/// charts. See `ActivityFilters` for the filters which can be sent
+async fn get_activity(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    info!(state.log, "GET /commit/activity/?{}", req.query_string());
+
+    let mut filters =
+        web::Query::<ActivityFilters>::from_query(req.query_string())
+            .map_err(|e| AppError {
+                message: Some(format!("Invalid filters: {}", e)),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            })?
+            .into_inner();
+    let pagination = Pagination::from_request(&req)?;
+    let privacy = email_privacy(&req, state.email_privacy);
+    filters.author_email = email_filter(
+        &state,
+        privacy,
+        filters.author_email.take(),
+        filters.author_hash.take(),
+    )
+    .await?;
+
+    let activity =
+        state.storage.commit_activity(&filters, &pagination).await?;
+
+    Ok(HttpResponse::Ok().json(activity.link(&req)))
+}
+
+/// Email of a filter, sent as an address or as its MD5 hash. Addresses are
+/// refused when the emails are private to the request
+async fn email_filter(
+    state: &AppState,
+    privacy: EmailPrivacy,
+    email: Option<String>,
+    hash: Option<String>,
+) -> Result<Option<String>, AppError> {
+    if email.is_some() && privacy != EmailPrivacy::Public {
+        return Err(AppError {
+            error_type: AppErrorType::AuthorizationError,
+            message: Some(
+                "Emails are private: filter them by their hash".to_string(),
+            ),
+            cause: None,
+        });
+    }
+
+    match hash {
+        Some(hash) => {
+            Ok(Some(state.storage.find_email_by_hash(&hash).await?.email))
+        }
+        None => Ok(email.map(|email| normalize(&email))),
+    }
+}
+
 // Endpoint used for getting one commit
 async fn get_commit(
+    req: HttpRequest,
     state: web::Data<AppState>,
     hash: web::Path<String>,
 ) -> impl Responder {
     info!(state.log, "GET /commit/{}/", &hash);
 
-    let result = Commit::find(state.pool.clone(), hash.clone()).await;
+    let privacy = email_privacy(&req, state.email_privacy);
+    let result = state.storage.find_commit(&hash).await;
 
     result
-        .map(|commit| HttpResponse::Ok().json(commit))
+        .map(|commit| {
+            HttpResponse::Ok()
+                .json(commit.with_avatars(&state.avatar, privacy))
+        })
         .map_err(|e| e)
 }
 
@@ -77,42 +158,97 @@ async fn delete_commit(
     state: web::Data<AppState>,
     hash: web::Path<String>,
 ) -> impl Responder {
-    match req.headers().get(header::AUTHORIZATION) {
-        Some(x)
-            if x.to_str().unwrap()
-                != env::var("SECRET_KEY").unwrap_or("".to_string()) =>
-        {
-            info!(state.log, "DELETE /commit/{}/ 401", &hash);
-            return Err(AppError {
-                error_type: AppErrorType::AuthorizationError,
-                message: Some(
-                    "You must provide a valid Authorization".to_string(),
-                ),
-                cause: None,
-            });
-        }
-        Some(_) => {}
-        None => {
-            info!(state.log, "DELETE /commit/{}/ 400", &hash);
-            return Ok(HttpResponse::BadRequest().body(""));
-        }
-    };
+    if let Err(e) = check_authorization(&req) {
+        info!(state.log, "DELETE /commit/{}/ 401", &hash);
+        return Err(e);
+    }
 
-    let result = Commit::delete(state.pool.clone(), &hash).await;
+    let result = state.storage.delete_commit(&hash).await;
     info!(state.log, "DELETE /commit/{}/", &hash);
 
-    result
-        .map(|_| HttpResponse::NoContent().body(""))
-        .map_err(|e| e)
+    result.map(|_| HttpResponse::NoContent().body(""))
 }
 
-/// Endpoint used for getting a raking of the post authors by commit number
-async fn get_top_authors(state: web::Data<AppState>) -> impl Responder {
+/// Endpoint used for a full-text search of the commit messages. It can be
+/// limited to a repository (`repository_user` and `repository_name`) or to
+/// an `author` email, or to the MD5 hash of an email as `author_hash`, and
+/// skip the bots with `exclude_bots`
+async fn search_commits(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let query =
+        web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map_err(|_| bad_request("Invalid query string"))?;
+
+    let q = match query.get("q") {
+        Some(x) => x.clone(),
+        None => String::new(),
+    };
+    let repository_url =
+        match (query.get("repository_user"), query.get("repository_name")) {
+            (Some(user), Some(name)) => Some(format!("{}/{}", user, name)),
+            _ => None,
+        };
+    let privacy = email_privacy(&req, state.email_privacy);
+    let author_email = email_filter(
+        &state,
+        privacy,
+        query.get("author").cloned(),
+        query.get("author_hash").cloned(),
+    )
+    .await?;
+
+    info!(state.log, "GET /commit/search/?q={}", &q);
+    let pagination = Pagination::from_request(&req)?;
+    let exclude_bots = exclude_bots(&req)?;
+    let result = state
+        .storage
+        .search_commits(
+            &q,
+            repository_url,
+            author_email,
+            exclude_bots,
+            &pagination,
+        )
+        .await;
+
+    result.map(|commits| {
+        HttpResponse::Ok().json(
+            commits
+                .map(|result| result.with_avatars(&state.avatar, privacy))
+                .link(&req),
+        )
+    })
+}
+
+/// Endpoint used for getting a raking of the post authors by commit number,
+/// lines changed or active days. See `TopFilters` for the parameters. The
+/// bots are skipped with `exclude_bots`
+async fn get_top_authors(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
     info!(state.log, "GET /commit/top/");
-    let result = Commit::most_authors(state.pool.clone()).await;
+    let filters = web::Query::<TopFilters>::from_query(req.query_string())
+        .map_err(|e| AppError {
+            message: Some(format!("Invalid filters: {}", e)),
+            cause: None,
+            error_type: AppErrorType::BadRequestError,
+        })?;
+    let privacy = email_privacy(&req, state.email_privacy);
+    let result = state
+        .storage
+        .most_authors(&filters, exclude_bots(&req)?)
+        .await;
 
     result
-        .map(|authors| HttpResponse::Ok().json(authors))
+        .map(|mut authors| {
+            for author in authors.iter_mut() {
+                author.author_email = privacy.show(&author.author_email);
+            }
+            HttpResponse::Ok().json(authors)
+        })
         .map_err(|e| e)
 }
 
@@ -124,6 +260,12 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(
                 web::resource("/top/").route(web::get().to(get_top_authors)),
             )
+            .service(
+                web::resource("/activity/").route(web::get().to(get_activity)),
+            )
+            .service(
+                web::resource("/search/").route(web::get().to(search_commits)),
+            )
             .service(
                 web::resource("/{hash}/")
                     .route(web::get().to(get_commit))
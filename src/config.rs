@@ -1,5 +1,7 @@
+use crate::errors::{AppError, AppErrorType};
+use crate::storage::SharedStorage;
+use crate::sync::SyncQueue;
 pub use config::ConfigError;
-use deadpool_postgres::Pool;
 use serde::Deserialize;
 use slog::{o, Drain, Logger};
 use slog_async;
@@ -11,10 +13,168 @@ pub struct ServerConfig {
     pub port: u16,
 }
 
+/// Limits of the repository imports
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ImportConfig {
+    /// Maximum number of bytes received by a clone
+    pub max_bytes: usize,
+    /// Maximum seconds spent by a clone and the walk of its history
+    pub timeout: u64,
+    /// Maximum number of commits imported from a repository
+    pub max_commits: usize,
+    /// Number of commits saved together in the database
+    pub batch_size: usize,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        ImportConfig {
+            max_bytes: 500 * 1024 * 1024,
+            timeout: 300,
+            max_commits: 100_000,
+            batch_size: 1000,
+        }
+    }
+}
+
+/// Where the data is saved
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// "postgres" or "sqlite"
+    pub backend: String,
+    /// Path of the SQLite database file
+    pub path: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: "postgres".to_string(),
+            path: "gico.db".to_string(),
+        }
+    }
+}
+
+/// Avatars of the emails
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AvatarConfig {
+    /// Service used by the avatar endpoint: "gravatar" or "libravatar"
+    pub provider: String,
+    /// Image used when the email has no avatar: a keyword of the service,
+    /// like "identicon", or the URL of an image
+    pub default: String,
+    /// Size in pixels
+    pub size: u32,
+}
+
+impl Default for AvatarConfig {
+    fn default() -> Self {
+        AvatarConfig {
+            provider: "gravatar".to_string(),
+            default: "identicon".to_string(),
+            size: 80,
+        }
+    }
+}
+
+/// Detection of the emails of bots
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BotConfig {
+    /// Regular expressions separated by whitespace. An email is a bot when
+    /// one of them matches it, ignoring the case
+    pub patterns: String,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            // GitHub apps like dependabot[bot], Renovate, the old Dependabot,
+            // GitHub Actions and the project bots of GitLab
+            patterns: r"\[bot\]@ ^bot@renovateapp\.com$
+                ^renovate@whitesourcesoftware\.com$ ^support@dependabot\.com$
+                ^action@github\.com$ ^project_\d+_bot"
+                .to_string(),
+        }
+    }
+}
+
+/// How the email addresses are shown to the requests without a valid
+/// Authorization
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmailPrivacy {
+    /// Raw addresses
+    #[default]
+    Public,
+    /// Only the first character of the user and the domain, like
+    /// `a***@example.com`
+    Masked,
+    /// The MD5 hash of the address
+    Hashed,
+}
+
+/// TLS mode of the connections to PostgreSQL
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TlsMode {
+    /// Plain connections
+    Disable,
+    /// TLS when the server supports it, without checking its certificate
+    Prefer,
+    /// Always TLS, checking the certificate of the server only when
+    /// `ca_file` is set
+    Require,
+    /// Always TLS, checking the certificate of the server and its hostname
+    VerifyFull,
+}
+
+/// TLS of the connections to PostgreSQL
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct PgTlsConfig {
+    pub mode: TlsMode,
+    /// PEM file of the certificates which sign the server certificate. The
+    /// system ones are used when it is not set
+    pub ca_file: Option<String>,
+    /// PEM file of the client certificate, used with `key_file`
+    pub cert_file: Option<String>,
+    /// PEM file of the private key of the client certificate
+    pub key_file: Option<String>,
+}
+
+impl Default for PgTlsConfig {
+    fn default() -> Self {
+        PgTlsConfig {
+            mode: TlsMode::Disable,
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Used only by the "postgres" storage
+    #[serde(default)]
     pub pg: deadpool_postgres::Config,
+    #[serde(default)]
+    pub pg_tls: PgTlsConfig,
+    #[serde(default)]
+    pub import: ImportConfig,
+    #[serde(default)]
+    pub avatar: AvatarConfig,
+    #[serde(default)]
+    pub email_privacy: EmailPrivacy,
+    #[serde(default)]
+    pub bots: BotConfig,
 }
 
 impl Config {
@@ -24,6 +184,21 @@ impl Config {
         cfg.try_into()
     }
 
+    /// Check the values which are valid for their type but not for the
+    /// server
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.import.batch_size < 1 {
+            return Err(config_error("IMPORT__BATCH_SIZE must be at least 1"));
+        }
+        if !["gravatar", "libravatar"].contains(&&*self.avatar.provider) {
+            return Err(config_error(
+                "AVATAR__PROVIDER must be gravatar or libravatar",
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn logging() -> Logger {
         let decorator = slog_term::TermDecorator::new().build();
         let drain = slog_term::FullFormat::new(decorator).build().fuse();
@@ -35,6 +210,18 @@ impl Config {
 
 #[derive(Clone)]
 pub struct AppState {
-    pub pool: Pool,
+    pub storage: SharedStorage,
     pub log: slog::Logger,
+    pub sync: SyncQueue,
+    pub import: ImportConfig,
+    pub avatar: AvatarConfig,
+    pub email_privacy: EmailPrivacy,
+}
+
+fn config_error(message: &str) -> AppError {
+    AppError {
+        message: Some(message.to_string()),
+        cause: None,
+        error_type: AppErrorType::ConfigError,
+    }
 }
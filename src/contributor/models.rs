@@ -0,0 +1,397 @@
+use crate::config::EmailPrivacy;
+use crate::db::get_client;
+use crate::email::models::normalize;
+use crate::errors::{AppError, AppErrorType};
+use crate::helpers::bad_request;
+use crate::pagination::{Key, Page, Pagination};
+
+use chrono::NaiveDateTime;
+use deadpool_postgres::{Pool, Transaction};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "contributor")]
+/// Contributor model: a person who commits with one or more emails
+pub struct Contributor {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    /// Emails of the contributor, sorted
+    pub emails: Vec<String>,
+}
+
+/// Struct used to create a new contributor
+#[derive(Deserialize)]
+pub struct ContributorData {
+    pub name: String,
+    /// Emails moved to the contributor. At least one is required
+    pub emails: Vec<String>,
+}
+
+/// Struct used to merge contributors and emails into a contributor
+#[derive(Deserialize)]
+pub struct MergeData {
+    /// Contributors whose emails are moved, which are then deleted
+    #[serde(default)]
+    pub contributors: Vec<Uuid>,
+    /// Emails moved, even from other contributors
+    #[serde(default)]
+    pub emails: Vec<String>,
+}
+
+/// Struct used to remove emails from a contributor
+#[derive(Deserialize)]
+pub struct SplitData {
+    pub emails: Vec<String>,
+}
+
+/// Emails of different contributors which are probably the same person,
+/// because they share an author name or a noreply username
+#[derive(Serialize)]
+pub struct ContributorSuggestion {
+    /// Name or username shared by the emails, lowercase
+    pub name: String,
+    pub emails: Vec<String>,
+}
+
+/// Contributors with their emails
+const SELECT_CONTRIBUTOR: &str = "
+    SELECT contributor.*, ARRAY(
+        SELECT email FROM email
+        WHERE contributor_id = contributor.id
+        ORDER BY email
+    )::varchar[] AS emails
+    FROM contributor";
+
+/// Condition of the contributors which have an email of a person, when `$1`
+/// is true
+const NOT_BOTS: &str = "
+    NOT ($1 AND NOT EXISTS (
+        SELECT 1 FROM email
+        WHERE contributor_id = contributor.id AND NOT is_bot
+    ))";
+
+/// Names shared by the emails of different contributors, where an email
+/// without a contributor is a contributor by itself. `$1` is true to skip the
+/// bots
+const SUGGESTIONS: &str = "
+    WITH names AS (
+        SELECT DISTINCT lower(trim(author_name)) AS name, author_email AS email
+        FROM commit
+        UNION
+        SELECT username, email FROM email WHERE username IS NOT NULL
+    )
+    SELECT names.name, array_agg(DISTINCT names.email) AS emails
+    FROM names
+    JOIN email ON email.email = names.email
+    WHERE names.name <> '' AND NOT ($1 AND email.is_bot)
+    GROUP BY names.name
+    HAVING COUNT(DISTINCT COALESCE(email.contributor_id::text, email.email)) > 1";
+
+impl Contributor {
+    /// Find a page of contributors, ordered by name. With `exclude_bots`,
+    /// the contributors whose emails are all bots are skipped
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<Contributor>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+        let statement = client
+            .prepare(&format!(
+                "
+                {}
+                WHERE {}
+                    AND ($2::text IS NULL
+                        OR (name, id) > ($2, $3::text::uuid))
+                ORDER BY name, id
+                LIMIT $4
+                ",
+                SELECT_CONTRIBUTOR, NOT_BOTS
+            ))
+            .await?;
+
+        let contributors = client
+            .query(
+                &statement,
+                &[&exclude_bots, &after[0], &after[1], &pagination.fetch()],
+            )
+            .await?
+            .iter()
+            .map(|row| Contributor::from_row_ref(row).unwrap())
+            .collect::<Vec<Contributor>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare(&format!(
+                    "SELECT COUNT(*) FROM contributor WHERE {}",
+                    NOT_BOTS
+                ))
+                .await?;
+            Some(client.query_one(&statement, &[&exclude_bots]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(contributors, pagination, count, |contributor| {
+            vec![contributor.name.clone(), contributor.id.to_string()]
+        }))
+    }
+
+    /// Find a contributor with an `id` equals to an Uuid element
+    pub async fn find(pool: Pool, id: &Uuid) -> Result<Contributor, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(&format!("{} WHERE id = $1", SELECT_CONTRIBUTOR))
+            .await?;
+
+        let contributor = client
+            .query_opt(&statement, &[&id])
+            .await?
+            .map(|row| Contributor::from_row_ref(&row).unwrap());
+
+        contributor.ok_or_else(not_found)
+    }
+
+    /// Create a contributor which owns the emails of `data`, taking them
+    /// from their previous contributors
+    pub async fn create(
+        pool: Pool,
+        data: &ContributorData,
+    ) -> Result<Contributor, AppError> {
+        let mut client = get_client(pool.clone()).await.unwrap();
+        let transaction = client.transaction().await?;
+
+        let id = Uuid::new_v4();
+        transaction
+            .execute(
+                "INSERT INTO contributor(id, name) VALUES($1, $2)",
+                &[&id, &data.name],
+            )
+            .await?;
+        move_emails(&transaction, &id, &data.emails, &[]).await?;
+        transaction.commit().await?;
+
+        Contributor::find(pool, &id).await
+    }
+
+    /// Move the emails of the contributors of `data`, and its emails, to
+    /// the contributor `id`. The contributors left without emails are
+    /// deleted
+    pub async fn merge(
+        pool: Pool,
+        id: &Uuid,
+        data: &MergeData,
+    ) -> Result<Contributor, AppError> {
+        let mut client = get_client(pool.clone()).await.unwrap();
+        let transaction = client.transaction().await?;
+
+        let found = transaction
+            .query_opt(
+                "SELECT id FROM contributor WHERE id = $1 FOR UPDATE",
+                &[&id],
+            )
+            .await?;
+        if found.is_none() {
+            return Err(not_found());
+        }
+        move_emails(&transaction, id, &data.emails, &data.contributors)
+            .await?;
+        transaction.commit().await?;
+
+        Contributor::find(pool, id).await
+    }
+
+    /// Remove emails from the contributor `id`. It is deleted when it is
+    /// left without emails
+    pub async fn split(
+        pool: Pool,
+        id: &Uuid,
+        data: &SplitData,
+    ) -> Result<Contributor, AppError> {
+        let mut client = get_client(pool.clone()).await.unwrap();
+        let transaction = client.transaction().await?;
+
+        let mut contributor = transaction
+            .query_opt(
+                &*format!("{} WHERE id = $1 FOR UPDATE", SELECT_CONTRIBUTOR),
+                &[&id],
+            )
+            .await?
+            .map(|row| Contributor::from_row_ref(&row).unwrap())
+            .ok_or_else(not_found)?;
+
+        let emails = normalize_all(&data.emails);
+        transaction
+            .execute(
+                "
+                UPDATE email SET contributor_id = NULL
+                WHERE contributor_id = $1 AND email = ANY($2)
+                ",
+                &[&id, &emails],
+            )
+            .await?;
+        delete_empty(&transaction).await?;
+        transaction.commit().await?;
+
+        contributor.emails.retain(|email| !emails.contains(email));
+        Ok(contributor)
+    }
+
+    /// Delete a contributor. Its emails are kept, without a contributor
+    pub async fn delete(
+        pool: Pool,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(
+                "
+                DELETE FROM contributor
+                WHERE id = $1
+                RETURNING *, ARRAY[]::varchar[] AS emails
+                ",
+            )
+            .await?;
+
+        let contributor = client
+            .query_opt(&statement, &[&id])
+            .await?
+            .map(|row| Contributor::from_row_ref(&row).unwrap());
+
+        contributor.ok_or_else(not_found)
+    }
+
+    /// Find a page of merge suggestions, ordered by name
+    pub async fn suggestions(
+        pool: Pool,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<ContributorSuggestion>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Text])?;
+        let statement = client
+            .prepare(&format!(
+                "
+                SELECT * FROM ({}) AS suggestion
+                WHERE $2::text IS NULL OR name > $2
+                ORDER BY name
+                LIMIT $3
+                ",
+                SUGGESTIONS
+            ))
+            .await?;
+
+        let suggestions = client
+            .query(
+                &statement,
+                &[&exclude_bots, &after[0], &pagination.fetch()],
+            )
+            .await?
+            .iter()
+            .map(|row| ContributorSuggestion {
+                name: row.get(0),
+                emails: row.get(1),
+            })
+            .collect::<Vec<ContributorSuggestion>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare(&format!(
+                    "SELECT COUNT(*) FROM ({}) AS suggestion",
+                    SUGGESTIONS
+                ))
+                .await?;
+            Some(client.query_one(&statement, &[&exclude_bots]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(suggestions, pagination, count, |suggestion| {
+            vec![suggestion.name.clone()]
+        }))
+    }
+
+    /// Replace the emails as required by `privacy`
+    pub fn hide_emails(mut self, privacy: EmailPrivacy) -> Contributor {
+        for email in self.emails.iter_mut() {
+            *email = privacy.show(email);
+        }
+        self
+    }
+}
+
+/// Move `emails`, and the emails of `contributors`, to the contributor `id`,
+/// then delete the contributors left without emails. Every email must exist
+async fn move_emails(
+    transaction: &Transaction<'_>,
+    id: &Uuid,
+    emails: &[String],
+    contributors: &[Uuid],
+) -> Result<(), AppError> {
+    let emails = normalize_all(emails);
+    if emails.is_empty() && contributors.is_empty() {
+        return Err(bad_request("At least one email is required"));
+    }
+
+    let moved = transaction
+        .execute(
+            "UPDATE email SET contributor_id = $1 WHERE email = ANY($2)",
+            &[&id, &emails],
+        )
+        .await?;
+    if moved as usize != emails.len() {
+        return Err(bad_request("Some emails do not exist"));
+    }
+    transaction
+        .execute(
+            "
+            UPDATE email SET contributor_id = $1
+            WHERE contributor_id = ANY($2)
+            ",
+            &[&id, &contributors],
+        )
+        .await?;
+
+    delete_empty(transaction).await
+}
+
+/// Delete the contributors without emails
+pub async fn delete_empty(
+    transaction: &Transaction<'_>,
+) -> Result<(), AppError> {
+    transaction
+        .execute(
+            "
+            DELETE FROM contributor
+            WHERE NOT EXISTS (
+                SELECT 1 FROM email WHERE contributor_id = contributor.id
+            )
+            ",
+            &[],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Normalized emails, without duplicates
+pub fn normalize_all(emails: &[String]) -> Vec<String> {
+    let mut emails: Vec<String> =
+        emails.iter().map(|email| normalize(email)).collect();
+    emails.sort();
+    emails.dedup();
+    emails
+}
+
+fn not_found() -> AppError {
+    AppError {
+        error_type: AppErrorType::NotFoundError,
+        cause: None,
+        message: Some("Contributor not found".to_string()),
+    }
+}
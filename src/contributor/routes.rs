@@ -0,0 +1,162 @@
+use crate::config::AppState;
+use crate::contributor::models::{ContributorData, MergeData, SplitData};
+use crate::helpers::{
+    check_authorization, email_privacy, exclude_bots, uuid_from_string,
+};
+use crate::pagination::Pagination;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use slog::info;
+use uuid::Uuid;
+
+/// Endpoint used for retrieve a page of contributors
+async fn index(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "GET /contributor/");
+
+    let pagination = Pagination::from_request(&req)?;
+    let privacy = email_privacy(&req, state.email_privacy);
+    let result = state
+        .storage
+        .find_contributors(&pagination, exclude_bots(&req)?)
+        .await;
+
+    result.map(|contributors| {
+        HttpResponse::Ok().json(
+            contributors
+                .map(|contributor| contributor.hide_emails(privacy))
+                .link(&req),
+        )
+    })
+}
+
+/// Endpoint used for getting one contributor
+async fn get_contributor(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "GET /contributor/{}/", &id);
+
+    let uuid: Uuid = uuid_from_string(&id);
+    let privacy = email_privacy(&req, state.email_privacy);
+    let result = state.storage.find_contributor(&uuid).await;
+
+    result.map(|contributor| {
+        HttpResponse::Ok().json(contributor.hide_emails(privacy))
+    })
+}
+
+/// Endpoint used for create a contributor from some emails.
+/// It uses a SECRET_KEY used like an API key
+async fn create_contributor(
+    req: HttpRequest,
+    payload: web::Json<ContributorData>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "POST /contributor/");
+    check_authorization(&req)?;
+
+    let result = state.storage.create_contributor(&payload).await;
+
+    result.map(|contributor| HttpResponse::Created().json(contributor))
+}
+
+/// Endpoint used for merge other contributors and emails into a
+/// contributor. It uses a SECRET_KEY used like an API key
+async fn merge_contributors(
+    req: HttpRequest,
+    payload: web::Json<MergeData>,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "POST /contributor/{}/merge/", &id);
+    check_authorization(&req)?;
+
+    let uuid: Uuid = uuid_from_string(&id);
+    let result = state.storage.merge_contributors(&uuid, &payload).await;
+
+    result.map(|contributor| HttpResponse::Ok().json(contributor))
+}
+
+/// Endpoint used for remove emails from a contributor.
+/// It uses a SECRET_KEY used like an API key
+async fn split_contributor(
+    req: HttpRequest,
+    payload: web::Json<SplitData>,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "POST /contributor/{}/split/", &id);
+    check_authorization(&req)?;
+
+    let uuid: Uuid = uuid_from_string(&id);
+    let result = state.storage.split_contributor(&uuid, &payload).await;
+
+    result.map(|contributor| HttpResponse::Ok().json(contributor))
+}
+
+/// Endpoint used for delete a contributor, keeping its emails.
+/// It uses a SECRET_KEY used like an API key
+async fn delete_contributor(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "DELETE /contributor/{}/", &id);
+    check_authorization(&req)?;
+
+    let uuid: Uuid = uuid_from_string(&id);
+    let result = state.storage.delete_contributor(&uuid).await;
+
+    result.map(|_| HttpResponse::NoContent().body(""))
+}
+
+/// Endpoint used for retrieve a page of emails which are probably the same
+/// person. It uses a SECRET_KEY used like an API key
+async fn get_suggestions(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "GET /contributor/suggestions/");
+    check_authorization(&req)?;
+
+    let pagination = Pagination::from_request(&req)?;
+    let result = state
+        .storage
+        .contributor_suggestions(&pagination, exclude_bots(&req)?)
+        .await;
+
+    result.map(|suggestions| HttpResponse::Ok().json(suggestions.link(&req)))
+}
+
+/// Routes for contributors
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/contributor")
+            .service(
+                web::resource("/")
+                    .route(web::get().to(index))
+                    .route(web::post().to(create_contributor)),
+            )
+            .service(
+                web::resource("/suggestions/")
+                    .route(web::get().to(get_suggestions)),
+            )
+            .service(
+                web::resource("/{id}/")
+                    .route(web::get().to(get_contributor))
+                    .route(web::delete().to(delete_contributor)),
+            )
+            .service(
+                web::resource("/{id}/merge/")
+                    .route(web::post().to(merge_contributors)),
+            )
+            .service(
+                web::resource("/{id}/split/")
+                    .route(web::post().to(split_contributor)),
+            ),
+    );
+}
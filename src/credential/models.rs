@@ -0,0 +1,383 @@
+use crate::db::get_client;
+use crate::errors::{AppError, AppErrorType};
+use crate::git::GitCredential;
+use crate::pagination::{Key, Page, Pagination};
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use chrono::{NaiveDateTime, Utc};
+use deadpool_postgres::Pool;
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+use uuid::Uuid;
+
+use std::env;
+
+/// Length of the nonce saved before the encrypted secret
+const NONCE_LEN: usize = 12;
+
+#[derive(Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "credential")]
+/// Credential model. `secret` is encrypted and it is never serialized
+pub struct Credential {
+    pub id: Uuid,
+    pub kind: String,
+    pub username: String,
+    #[serde(skip)]
+    pub secret: Vec<u8>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Struct used to create a new credential
+#[derive(Deserialize)]
+pub struct CredentialData {
+    /// "token" for HTTPS, "ssh" for a private key
+    pub kind: String,
+    pub username: Option<String>,
+    /// HTTPS token or SSH private key
+    pub secret: String,
+    /// Passphrase of the SSH private key
+    pub passphrase: Option<String>,
+}
+
+/// Part of the credential saved encrypted
+#[derive(Serialize, Deserialize)]
+struct Secret {
+    secret: String,
+    passphrase: Option<String>,
+}
+
+impl Credential {
+    /// Find a page of credentials, without their secrets
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+    ) -> Result<Page<Credential>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Timestamp, Key::Uuid])?;
+        let statement = client
+            .prepare(
+                "
+                SELECT * FROM credential
+                WHERE $1::text IS NULL
+                    OR (created_at, id) < ($1::text::timestamp, $2::text::uuid)
+                ORDER BY created_at DESC, id DESC
+                LIMIT $3
+                ",
+            )
+            .await?;
+
+        let credentials = client
+            .query(&statement, &[&after[0], &after[1], &pagination.fetch()])
+            .await?
+            .iter()
+            .map(|row| Credential::from_row_ref(row).unwrap())
+            .collect::<Vec<Credential>>();
+
+        let count = if pagination.count {
+            let statement =
+                client.prepare("SELECT COUNT(*) FROM credential").await?;
+            Some(client.query_one(&statement, &[]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(credentials, pagination, count, |credential| {
+            vec![credential.created_at.to_string(), credential.id.to_string()]
+        }))
+    }
+
+    /// Find a credential with an `id` equals to an Uuid element
+    pub async fn find(pool: Pool, id: &Uuid) -> Result<Credential, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare("SELECT * FROM credential WHERE id = $1")
+            .await?;
+
+        let credential = client
+            .query_opt(&statement, &[&id])
+            .await?
+            .map(|row| Credential::from_row_ref(&row).unwrap());
+
+        match credential {
+            Some(credential) => Ok(credential),
+            None => Err(AppError {
+                error_type: AppErrorType::NotFoundError,
+                cause: None,
+                message: Some("Credential not found".to_string()),
+            }),
+        }
+    }
+
+    /// Find a credential and delete it. Repositories which use it are not
+    /// synchronized anymore
+    pub async fn delete(
+        pool: Pool,
+        id: &Uuid,
+    ) -> Result<Credential, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(
+                "
+                DELETE FROM credential
+                WHERE id=$1
+                RETURNING *
+                ",
+            )
+            .await?;
+
+        let credential = client
+            .query_opt(&statement, &[&id])
+            .await?
+            .map(|row| Credential::from_row_ref(&row).unwrap());
+
+        match credential {
+            Some(credential) => Ok(credential),
+            None => Err(AppError {
+                error_type: AppErrorType::NotFoundError,
+                cause: None,
+                message: Some("Credential not found".to_string()),
+            }),
+        }
+    }
+
+    /// Make a new credential, encrypting its secret. It still has to be
+    /// saved
+    pub fn new(data: &CredentialData) -> Result<Credential, AppError> {
+        if data.kind != "token" && data.kind != "ssh" {
+            return Err(AppError {
+                message: Some(
+                    "Credential kind must be \"token\" or \"ssh\"".to_string(),
+                ),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            });
+        }
+
+        // Create a new UUID v4
+        let uuid = Uuid::new_v4();
+
+        let username = match &data.username {
+            Some(username) => username.clone(),
+            None => "git".to_string(),
+        };
+        let secret = serde_json::to_vec(&Secret {
+            secret: data.secret.clone(),
+            passphrase: data.passphrase.clone(),
+        })
+        .unwrap();
+        let encrypted = encrypt(&uuid, &secret)?;
+
+        Ok(Credential {
+            id: uuid,
+            kind: data.kind.clone(),
+            username,
+            secret: encrypted,
+            created_at: Utc::now().naive_utc(),
+        })
+    }
+
+    /// Save a new credential. `created_at` is set by the database
+    pub async fn create(
+        pool: Pool,
+        credential: &Credential,
+    ) -> Result<Credential, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+
+        let statement = client
+            .prepare(
+                "INSERT INTO credential(id, kind, username, secret)
+                VALUES($1, $2, $3, $4)
+                RETURNING *",
+            )
+            .await?;
+
+        let credential = client
+            .query_opt(
+                &statement,
+                &[
+                    &credential.id,
+                    &credential.kind,
+                    &credential.username,
+                    &credential.secret,
+                ],
+            )
+            .await?
+            .map(|row| Credential::from_row_ref(&row).unwrap());
+
+        match credential {
+            Some(credential) => Ok(credential),
+            None => Err(AppError {
+                message: Some("Error creating a new credential".to_string()),
+                cause: Some("Unknown error".to_string()),
+                error_type: AppErrorType::DbError,
+            }),
+        }
+    }
+
+    /// Decrypt the secret and returns the credential used by git
+    pub fn git_credential(&self) -> Result<GitCredential, AppError> {
+        let secret: Secret =
+            match serde_json::from_slice(&decrypt(&self.id, &self.secret)?) {
+                Ok(secret) => secret,
+                Err(e) => {
+                    return Err(AppError {
+                        message: Some(
+                            "Credential cannot be decrypted".to_string(),
+                        ),
+                        cause: Some(e.to_string()),
+                        error_type: AppErrorType::DbError,
+                    });
+                }
+            };
+
+        if self.kind == "ssh" {
+            Ok(GitCredential::SshKey {
+                username: self.username.clone(),
+                private_key: secret.secret,
+                passphrase: secret.passphrase,
+            })
+        } else {
+            Ok(GitCredential::Token {
+                username: self.username.clone(),
+                token: secret.secret,
+            })
+        }
+    }
+}
+
+/// Returns the AES-256-GCM cipher with a key derived from `secret_key`
+fn cipher(secret_key: &str) -> Result<Aes256Gcm, AppError> {
+    if secret_key.is_empty() {
+        return Err(AppError {
+            message: Some(
+                "SECRET_KEY is required by credentials and webhook secrets"
+                    .to_string(),
+            ),
+            cause: None,
+            error_type: AppErrorType::DbError,
+        });
+    }
+
+    let hkdf = Hkdf::<Sha256>::new(None, secret_key.as_bytes());
+    let mut key = [0u8; 32];
+    hkdf.expand(b"gico credentials", &mut key).unwrap();
+
+    Ok(Aes256Gcm::new(GenericArray::from_slice(&key)))
+}
+
+/// Encrypt `secret`, binding it to `id`, the one of the credential or of
+/// the repository of a webhook secret. Returns the nonce followed by the
+/// ciphertext. The key is derived from SECRET_KEY
+pub fn encrypt(id: &Uuid, secret: &[u8]) -> Result<Vec<u8>, AppError> {
+    encrypt_with_key(&env::var("SECRET_KEY").unwrap_or_default(), id, secret)
+}
+
+/// `encrypt` with a key derived from `secret_key`
+fn encrypt_with_key(
+    secret_key: &str,
+    id: &Uuid,
+    secret: &[u8],
+) -> Result<Vec<u8>, AppError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let payload = Payload {
+        msg: secret,
+        aad: id.as_bytes(),
+    };
+    match cipher(secret_key)?
+        .encrypt(GenericArray::from_slice(&nonce), payload)
+    {
+        Ok(ciphertext) => Ok([&nonce[..], &ciphertext[..]].concat()),
+        Err(_) => Err(AppError {
+            message: Some("Secret cannot be encrypted".to_string()),
+            cause: None,
+            error_type: AppErrorType::DbError,
+        }),
+    }
+}
+
+/// Decrypt a secret made by `encrypt` for `id`
+pub fn decrypt(id: &Uuid, encrypted: &[u8]) -> Result<Vec<u8>, AppError> {
+    decrypt_with_key(
+        &env::var("SECRET_KEY").unwrap_or_default(),
+        id,
+        encrypted,
+    )
+}
+
+/// `decrypt` with a key derived from `secret_key`
+fn decrypt_with_key(
+    secret_key: &str,
+    id: &Uuid,
+    encrypted: &[u8],
+) -> Result<Vec<u8>, AppError> {
+    let error = AppError {
+        message: Some("Secret cannot be decrypted".to_string()),
+        cause: Some("SECRET_KEY could be changed".to_string()),
+        error_type: AppErrorType::DbError,
+    };
+    if encrypted.len() < NONCE_LEN {
+        return Err(error);
+    }
+
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: id.as_bytes(),
+    };
+
+    cipher(secret_key)?
+        .decrypt(GenericArray::from_slice(nonce), payload)
+        .map_err(|_| error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "topsecret";
+
+    #[test]
+    fn decrypt_returns_the_encrypted_secret() {
+        let id = Uuid::new_v4();
+        let encrypted = encrypt_with_key(KEY, &id, b"token").unwrap();
+
+        assert_ne!(&encrypted[NONCE_LEN..], b"token");
+        assert_eq!(decrypt_with_key(KEY, &id, &encrypted).unwrap(), b"token");
+        assert_ne!(encrypt_with_key(KEY, &id, b"token").unwrap(), encrypted);
+    }
+
+    #[test]
+    fn decrypt_needs_the_same_id_and_key() {
+        let id = Uuid::new_v4();
+        let encrypted = encrypt_with_key(KEY, &id, b"token").unwrap();
+
+        assert!(decrypt_with_key(KEY, &Uuid::new_v4(), &encrypted).is_err());
+        assert!(decrypt_with_key("other", &id, &encrypted).is_err());
+        assert!(decrypt_with_key("", &id, &encrypted).is_err());
+    }
+
+    #[test]
+    fn decrypt_rejects_tampered_secrets() {
+        let id = Uuid::new_v4();
+        let mut encrypted = encrypt_with_key(KEY, &id, b"token").unwrap();
+
+        assert!(decrypt_with_key(KEY, &id, &encrypted[..NONCE_LEN]).is_err());
+        assert!(decrypt_with_key(KEY, &id, &encrypted[..4]).is_err());
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(decrypt_with_key(KEY, &id, &encrypted).is_err());
+    }
+
+    #[test]
+    fn encrypt_needs_a_secret_key() {
+        assert!(encrypt_with_key("", &Uuid::new_v4(), b"token").is_err());
+    }
+}
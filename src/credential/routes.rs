@@ -0,0 +1,71 @@
+use crate::config::AppState;
+use crate::credential::models::{Credential, CredentialData};
+use crate::helpers::{check_authorization, uuid_from_string};
+use crate::pagination::Pagination;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use slog::info;
+use uuid::Uuid;
+
+/// Endpoint used for retrieve a page of credentials. Secrets are never
+/// returned. It uses a SECRET_KEY used like an API key
+async fn index(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "GET /credential/");
+    check_authorization(&req)?;
+
+    let pagination = Pagination::from_request(&req)?;
+    let result = state.storage.find_credentials(&pagination).await;
+
+    result.map(|credentials| HttpResponse::Ok().json(credentials.link(&req)))
+}
+
+/// Endpoint used for create new credential.
+/// It uses a SECRET_KEY used like an API key
+async fn create_credential(
+    req: HttpRequest,
+    payload: web::Json<CredentialData>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "POST /credential/");
+    check_authorization(&req)?;
+
+    let credential = Credential::new(&payload)?;
+    let result = state.storage.create_credential(&credential).await;
+
+    result.map(|credential| HttpResponse::Created().json(credential))
+}
+
+/// Endpoint used for delete credential.
+/// It uses a SECRET_KEY used like an API key
+async fn delete_credential(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "DELETE /credential/{}/", &id);
+    check_authorization(&req)?;
+
+    let uuid: Uuid = uuid_from_string(&id);
+    let result = state.storage.delete_credential(&uuid).await;
+
+    result.map(|_| HttpResponse::NoContent().body(""))
+}
+
+/// Routes for credentials
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/credential")
+            .service(
+                web::resource("/")
+                    .route(web::get().to(index))
+                    .route(web::post().to(create_credential)),
+            )
+            .service(
+                web::resource("/{id}/")
+                    .route(web::delete().to(delete_credential)),
+            ),
+    );
+}
@@ -1,13 +1,35 @@
+use crate::commit::models::{
+    like_escape, like_pattern, Commit, CommitWithAvatars, MATCH_END,
+    MATCH_START,
+};
+use crate::config::{AvatarConfig, BotConfig, EmailPrivacy};
 use crate::db::get_client;
 use crate::errors::{AppError, AppErrorType};
+use crate::pagination::{Key, Page, Pagination};
 
-use deadpool_postgres::Pool;
+use chrono::{DateTime, Local};
+use deadpool_postgres::{Client, Pool, Transaction};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex, RegexSet, RegexSetBuilder};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
+use uuid::Uuid;
 
 use hex;
 use md5::{Digest, Md5};
+use sha2::Sha256;
+
+/// Address-shaped tokens of a text, like the message of a commit. They can
+/// contain the markers of the search matches
+static ADDRESS: Lazy<Regex> = Lazy::new(|| {
+    let marks = format!("{}{}", MATCH_START, MATCH_END);
+    Regex::new(&format!(
+        r"[\w.+{m}-]+@[\w{m}-]+(?:\.[\w{m}-]+)+",
+        m = marks
+    ))
+    .unwrap()
+});
 
 #[derive(Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "email")]
@@ -15,6 +37,116 @@ use md5::{Digest, Md5};
 pub struct Email {
     pub email: String,
     pub hash_md5: String,
+    /// Username on the host of a noreply address
+    pub username: Option<String>,
+    pub contributor_id: Option<Uuid>,
+    /// Whether the email matches the patterns of `BotConfig`
+    pub is_bot: bool,
+}
+
+/// Compiled patterns of `BotConfig`
+#[derive(Clone)]
+pub struct Bots(RegexSet);
+
+/// Avatar URLs of an email. Gravatar uses the MD5 hash of the email,
+/// Libravatar its SHA-256 hash
+#[derive(Serialize)]
+pub struct Avatar {
+    pub gravatar: String,
+    pub libravatar: String,
+}
+
+/// Email, or search result, with its avatar, as returned by the API
+#[derive(Serialize)]
+pub struct EmailWithAvatar<T = Email> {
+    #[serde(flatten)]
+    pub email: T,
+    pub avatar: Avatar,
+}
+
+/// Email found by a search, with its rank: the similarity of the address,
+/// or of an author name used with it, to the query, plus 1 when the address
+/// contains the query
+#[derive(Serialize)]
+pub struct EmailSearchResult {
+    #[serde(flatten)]
+    pub email: Email,
+    pub rank: f64,
+}
+
+/// Kind of an email search, with the pattern of `LIKE` used to match the
+/// addresses
+pub enum EmailQuery {
+    /// Query which starts with "@": the emails of this domain
+    Domain(String),
+    /// Part of the address, or similar to the address or to an author name
+    Fuzzy(String),
+}
+
+/// Emails which match a search, with their rank. `$1` is the normalized
+/// query, `$2` the pattern of `EmailQuery`, `$3` true for a fuzzy search,
+/// which uses the similarity of pg_trgm, and `$4` true to skip the bots
+const EMAIL_SEARCH: &str = "
+    SELECT email.*, (
+        (email.email LIKE $2)::int
+        + GREATEST(similarity(email.email, $1), COALESCE(names.rank, 0))
+    )::float8 AS rank
+    FROM email
+    LEFT JOIN (
+        SELECT author_email, MAX(similarity(author_name, $1)) AS rank
+        FROM commit
+        WHERE $3 AND author_name % $1
+        GROUP BY author_email
+    ) names ON names.author_email = email.email
+    WHERE NOT ($4 AND email.is_bot)
+        AND (email.email LIKE $2
+            OR ($3 AND (email.email % $1 OR names.author_email IS NOT NULL)))";
+
+/// Number of recent commits of a profile
+pub const PROFILE_COMMITS: i64 = 10;
+
+/// Everything about the author who uses an email. `E` and `C` are the
+/// email and its commits, with their avatars in the API
+#[derive(Serialize)]
+pub struct Profile<E = Email, C = Commit> {
+    pub email: E,
+    /// Number of commits
+    pub commits: i64,
+    pub first_commit: Option<DateTime<Local>>,
+    pub last_commit: Option<DateTime<Local>>,
+    /// Names used with the email, the oldest first
+    pub names: Vec<EmailName>,
+    /// Repositories of the commits, the one with more commits first
+    pub repositories: Vec<ProfileRepository>,
+    /// The `PROFILE_COMMITS` newest commits
+    pub recent_commits: Vec<C>,
+}
+
+/// Name used with an email, as author or as committer
+#[derive(Serialize)]
+pub struct EmailName {
+    pub name: String,
+    /// Number of commits with the name
+    pub commits: i64,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+}
+
+/// Email with the names used with it
+#[derive(Serialize)]
+pub struct EmailWithNames<E = Email> {
+    #[serde(flatten)]
+    pub email: E,
+    pub names: Vec<EmailName>,
+}
+
+/// Repository the author of a profile contributed to
+#[derive(Serialize)]
+pub struct ProfileRepository {
+    pub repository_url: String,
+    pub commits: i64,
+    pub first_commit: DateTime<Local>,
+    pub last_commit: DateTime<Local>,
 }
 
 // Struct used to creare a new email
@@ -24,33 +156,261 @@ pub struct EmailData {
 }
 
 impl Email {
-    /// Find all emails, returns email and its MD5 hash
-    pub async fn find_all(pool: Pool) -> Result<Vec<Email>, AppError> {
+    /// Find a page of emails, returns email and its MD5 hash. They are
+    /// sorted by hash when `by_hash`. The bots are skipped when
+    /// `exclude_bots`
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+        by_hash: bool,
+        exclude_bots: bool,
+    ) -> Result<Page<Email>, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
-        let statement = client.prepare("SELECT * FROM email").await?;
+        let after = pagination.after(&[Key::Text])?;
+        let statement = client
+            .prepare(
+                "
+                SELECT * FROM email
+                WHERE NOT ($4 AND is_bot)
+                    AND ($1::text IS NULL
+                        OR (CASE WHEN $3 THEN hash_md5 ELSE email END) > $1)
+                ORDER BY CASE WHEN $3 THEN hash_md5 ELSE email END
+                LIMIT $2
+                ",
+            )
+            .await?;
 
         let emails = client
-            .query(&statement, &[])
+            .query(
+                &statement,
+                &[&after[0], &pagination.fetch(), &by_hash, &exclude_bots],
+            )
             .await?
             .iter()
             .map(|row| Email::from_row_ref(row).unwrap())
             .collect::<Vec<Email>>();
 
-        Ok(emails)
+        let count = if pagination.count {
+            let statement = client
+                .prepare(
+                    "SELECT COUNT(*) FROM email WHERE NOT ($1 AND is_bot)",
+                )
+                .await?;
+            Some(client.query_one(&statement, &[&exclude_bots]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(emails, pagination, count, |email| {
+            if by_hash {
+                vec![email.hash_md5.clone()]
+            } else {
+                vec![email.email.clone()]
+            }
+        }))
     }
 
-    /// Search an email
+    /// Find a page of the emails which match `query`, the best ranked
+    /// first. See `EmailQuery`
     pub async fn search(
         pool: Pool,
-        email: &String,
+        query: &str,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<EmailSearchResult>, AppError> {
+        let normalized = normalize(query);
+        let (pattern, fuzzy) = match EmailQuery::parse(&normalized)? {
+            EmailQuery::Domain(pattern) => (pattern, false),
+            EmailQuery::Fuzzy(pattern) => (pattern, true),
+        };
+
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Float, Key::Text])?;
+        let statement = client
+            .prepare(&format!(
+                "
+                SELECT * FROM ({}) found
+                WHERE $5::text IS NULL
+                    OR rank < $5::text::float8
+                    OR (rank = $5::text::float8 AND email > $6)
+                ORDER BY rank DESC, email
+                LIMIT $7
+                ",
+                EMAIL_SEARCH
+            ))
+            .await?;
+
+        let emails = client
+            .query(
+                &statement,
+                &[
+                    &normalized,
+                    &pattern,
+                    &fuzzy,
+                    &exclude_bots,
+                    &after[0],
+                    &after[1],
+                    &pagination.fetch(),
+                ],
+            )
+            .await?
+            .iter()
+            .map(|row| EmailSearchResult {
+                email: Email::from_row_ref(row).unwrap(),
+                rank: row.get("rank"),
+            })
+            .collect::<Vec<EmailSearchResult>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare(&format!(
+                    "SELECT COUNT(*) FROM ({}) found",
+                    EMAIL_SEARCH
+                ))
+                .await?;
+            Some(
+                client
+                    .query_one(
+                        &statement,
+                        &[&normalized, &pattern, &fuzzy, &exclude_bots],
+                    )
+                    .await?
+                    .get(0),
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(emails, pagination, count, |result| {
+            vec![result.rank.to_string(), result.email.email.clone()]
+        }))
+    }
+
+    /// Build the profile of the email whose MD5 hash is `hash`
+    pub async fn profile(pool: Pool, hash: &str) -> Result<Profile, AppError> {
+        let email = Email::find_by_hash(pool.clone(), hash).await?;
+        let client = get_client(pool.clone()).await.unwrap();
+
+        let totals = client
+            .query_one(
+                "
+                SELECT COUNT(*), MIN(date), MAX(date) FROM commit
+                WHERE author_email = $1
+                ",
+                &[&email.email],
+            )
+            .await?;
+
+        let names = find_names(&client, &email.email).await?;
+
+        let repositories = client
+            .query(
+                "
+                SELECT repository_url, COUNT(*), MIN(date), MAX(date)
+                FROM commit
+                WHERE author_email = $1
+                GROUP BY repository_url
+                ORDER BY COUNT(*) DESC, repository_url
+                ",
+                &[&email.email],
+            )
+            .await?
+            .iter()
+            .map(|row| ProfileRepository {
+                repository_url: row.get(0),
+                commits: row.get(1),
+                first_commit: row.get(2),
+                last_commit: row.get(3),
+            })
+            .collect::<Vec<ProfileRepository>>();
+
+        let recent_commits = client
+            .query(
+                "
+                SELECT * FROM commit
+                WHERE author_email = $1
+                ORDER BY date DESC, hash DESC
+                LIMIT $2
+                ",
+                &[&email.email, &PROFILE_COMMITS],
+            )
+            .await?
+            .iter()
+            .map(|row| Commit::from_row_ref(row).unwrap())
+            .collect::<Vec<Commit>>();
+
+        Ok(Profile {
+            email,
+            commits: totals.get(0),
+            first_commit: totals.get(1),
+            last_commit: totals.get(2),
+            names,
+            repositories,
+            recent_commits,
+        })
+    }
+
+    /// Find the names used with an email, the oldest first
+    pub async fn names(
+        pool: Pool,
+        email: &str,
+    ) -> Result<Vec<EmailName>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+
+        find_names(&client, email).await
+    }
+
+    /// Count again the names used with `emails` inside a transaction, after
+    /// some of their commits are deleted
+    pub async fn refresh_names(
+        transaction: &Transaction<'_>,
+        emails: &[String],
+    ) -> Result<(), AppError> {
+        transaction
+            .execute(
+                "DELETE FROM email_name WHERE email = ANY($1)",
+                &[&emails],
+            )
+            .await?;
+        transaction
+            .execute(
+                "
+                INSERT INTO email_name(
+                    email, name, commits, first_seen, last_seen
+                )
+                SELECT email, name, COUNT(*), MIN(date), MAX(date) FROM (
+                    SELECT hash, date, author_email AS email,
+                        author_name AS name
+                    FROM commit
+                    WHERE author_email = ANY($1)
+                    UNION
+                    SELECT hash, date, committer_email, committer_name
+                    FROM commit
+                    WHERE committer_email = ANY($1)
+                ) uses
+                WHERE name <> ''
+                GROUP BY email, name
+                ",
+                &[&emails],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Find the email whose MD5 hash is `hash`
+    pub async fn find_by_hash(
+        pool: Pool,
+        hash: &str,
     ) -> Result<Email, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
 
-        let statement =
-            client.prepare("SELECT * FROM email WHERE email=$1").await?;
+        let statement = client
+            .prepare("SELECT * FROM email WHERE hash_md5=$1")
+            .await?;
 
         let email = client
-            .query_opt(&statement, &[&email])
+            .query_opt(&statement, &[&hash])
             .await?
             .map(|row| Email::from_row_ref(&row).unwrap());
 
@@ -64,48 +424,425 @@ impl Email {
         }
     }
 
-    /// Create new email
+    /// Create new email, saved normalized. Creating an email which already
+    /// exists is not an error: the saved one is returned. The boolean is
+    /// true when the email is new
     pub async fn create(
         pool: Pool,
-        data: &EmailData,
-    ) -> Result<Email, AppError> {
-        // Search an email  that matches with that string, because if it's
-        // exists, the server cannot create a clone
-        match Email::search(pool.clone(), &data.email).await {
-            Ok(_) => {
-                return Err(AppError {
-                    message: Some("Email already exists".to_string()),
-                    cause: Some("".to_string()),
-                    error_type: AppErrorType::AuthorizationError,
-                });
-            }
-            Err(_) => {}
-        };
-
+        email: &str,
+        bots: &Bots,
+    ) -> Result<(Email, bool), AppError> {
         let client = get_client(pool.clone()).await.unwrap();
 
-        let mut hasher = Md5::new();
-        hasher.update(&data.email.as_bytes());
-        let hash_final = hasher.finalize();
+        let email = normalize(email);
+        let digest = hash(&email);
+        let username = noreply_username(&email);
+        let is_bot = bots.is_bot(&email);
+
+        let statement = client
+            .prepare(
+                "
+                INSERT INTO email(email, hash_md5, username, is_bot)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+                RETURNING *, (xmax = 0) AS created
+                ",
+            )
+            .await?;
+
+        // The update of a conflict always returns the row, even when it is
+        // inserted by a concurrent transaction
+        let row = client
+            .query_one(&statement, &[&email, &digest, &username, &is_bot])
+            .await?;
+
+        Ok((Email::from_row_ref(&row).unwrap(), row.get("created")))
+    }
 
-        let digest = hex::encode(&hash_final.as_slice());
+    /// Save emails with a single statement, using a `client` which can be
+    /// inside a transaction. Emails which already exist are skipped. The
+    /// emails must be normalized, as the ones of the commits
+    pub async fn create_all(
+        client: &Client,
+        emails: Vec<String>,
+        bots: &Bots,
+    ) -> Result<(), AppError> {
+        let hashes: Vec<String> =
+            emails.iter().map(|email| hash(email)).collect();
+        let usernames: Vec<Option<String>> =
+            emails.iter().map(|email| noreply_username(email)).collect();
+        let bot_flags: Vec<bool> =
+            emails.iter().map(|email| bots.is_bot(email)).collect();
 
         let statement = client
-            .prepare("INSERT INTO email VALUES ($1, $2) RETURNING *")
+            .prepare(
+                "
+                INSERT INTO email(email, hash_md5, username, is_bot)
+                SELECT * FROM UNNEST(
+                    $1::varchar[], $2::varchar[], $3::varchar[], $4::boolean[]
+                )
+                ON CONFLICT (email) DO NOTHING
+                ",
+            )
             .await?;
 
-        let email = client
-            .query_opt(&statement, &[&data.email, &digest])
+        client
+            .execute(&statement, &[&emails, &hashes, &usernames, &bot_flags])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Flag again every email with `bots`, whose patterns may have changed.
+    /// Returns the number of emails whose flag changed
+    pub async fn flag_bots(pool: Pool, bots: &Bots) -> Result<u64, AppError> {
+        let client = get_client(pool.clone()).await?;
+
+        let bot_emails: Vec<String> = client
+            .query("SELECT email FROM email", &[])
             .await?
-            .map(|row| Email::from_row_ref(&row).unwrap());
+            .iter()
+            .map(|row| row.get::<_, String>(0))
+            .filter(|email| bots.is_bot(email))
+            .collect();
 
-        match email {
-            Some(email) => Ok(email),
-            None => Err(AppError {
-                message: Some("Error creating a new email".to_string()),
-                cause: Some("Unknown error".to_string()),
-                error_type: AppErrorType::DbError,
-            }),
+        let changed = client
+            .execute(
+                "
+                UPDATE email SET is_bot = NOT is_bot
+                WHERE is_bot <> (email = ANY($1))
+                ",
+                &[&bot_emails],
+            )
+            .await?;
+
+        Ok(changed)
+    }
+
+    /// Add the avatar URLs to the email, then hide its address as required
+    /// by `privacy`
+    pub fn with_avatar(
+        mut self,
+        config: &AvatarConfig,
+        privacy: EmailPrivacy,
+    ) -> EmailWithAvatar {
+        let avatar = Avatar::new(&self.email, config, None);
+        self.email = privacy.show(&self.email);
+
+        EmailWithAvatar {
+            email: self,
+            avatar,
+        }
+    }
+}
+
+impl EmailWithNames {
+    /// Add the avatar to the email, then hide it as required by `privacy`
+    pub fn with_avatar(
+        self,
+        config: &AvatarConfig,
+        privacy: EmailPrivacy,
+    ) -> EmailWithNames<EmailWithAvatar> {
+        EmailWithNames {
+            email: self.email.with_avatar(config, privacy),
+            names: self.names,
+        }
+    }
+}
+
+impl EmailQuery {
+    /// Kind of a normalized query
+    pub fn parse(query: &str) -> Result<EmailQuery, AppError> {
+        if query.is_empty() || query == "@" {
+            return Err(AppError {
+                message: Some("Search query is empty".to_string()),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            });
         }
+
+        if query.starts_with('@') {
+            Ok(EmailQuery::Domain(format!("%{}", like_escape(query))))
+        } else {
+            Ok(EmailQuery::Fuzzy(like_pattern(query)))
+        }
+    }
+}
+
+impl EmailSearchResult {
+    /// Add the avatar URLs to the email, then hide its address as required
+    /// by `privacy`
+    pub fn with_avatar(
+        mut self,
+        config: &AvatarConfig,
+        privacy: EmailPrivacy,
+    ) -> EmailWithAvatar<EmailSearchResult> {
+        let avatar = Avatar::new(&self.email.email, config, None);
+        self.email.email = privacy.show(&self.email.email);
+
+        EmailWithAvatar {
+            email: self,
+            avatar,
+        }
+    }
+}
+
+impl Profile {
+    /// Add the avatars to the email and to the commits, hiding their emails
+    /// as required by `privacy`
+    pub fn with_avatars(
+        self,
+        config: &AvatarConfig,
+        privacy: EmailPrivacy,
+    ) -> Profile<EmailWithAvatar, CommitWithAvatars<Commit>> {
+        Profile {
+            email: self.email.with_avatar(config, privacy),
+            commits: self.commits,
+            first_commit: self.first_commit,
+            last_commit: self.last_commit,
+            names: self.names,
+            repositories: self.repositories,
+            recent_commits: self
+                .recent_commits
+                .into_iter()
+                .map(|commit| commit.with_avatars(config, privacy))
+                .collect(),
+        }
+    }
+}
+
+impl EmailPrivacy {
+    /// What is shown of `email`
+    pub fn show(self, email: &str) -> String {
+        match self {
+            EmailPrivacy::Public => email.to_string(),
+            EmailPrivacy::Masked => match email.rfind('@') {
+                Some(at) => {
+                    let first = email[..at].chars().next();
+                    format!(
+                        "{}***{}",
+                        first.map(String::from).unwrap_or_default(),
+                        &email[at..]
+                    )
+                }
+                None => "***".to_string(),
+            },
+            EmailPrivacy::Hashed => hash(email),
+        }
+    }
+
+    /// Replace the addresses inside `text` by what is shown of them. The
+    /// markers of the search matches inside an address are dropped
+    pub fn show_in(self, text: &str) -> String {
+        if self == EmailPrivacy::Public {
+            return text.to_string();
+        }
+
+        ADDRESS
+            .replace_all(text, |address: &Captures| {
+                let email =
+                    address[0].replace(&[MATCH_START, MATCH_END][..], "");
+                self.show(&normalize(&email))
+            })
+            .into_owned()
+    }
+}
+
+impl Avatar {
+    /// Make the avatar URLs of `email`. `size` replaces the size of the
+    /// configuration
+    pub fn new(
+        email: &str,
+        config: &AvatarConfig,
+        size: Option<u32>,
+    ) -> Avatar {
+        // Both services hash the trimmed lowercase email
+        let email = email.trim().to_lowercase();
+        let query = serde_urlencoded::to_string(&[
+            ("s", size.unwrap_or(config.size).to_string()),
+            ("d", config.default.clone()),
+        ])
+        .unwrap();
+
+        let mut sha256 = Sha256::new();
+        sha256.update(email.as_bytes());
+
+        Avatar {
+            gravatar: format!(
+                "https://www.gravatar.com/avatar/{}?{}",
+                hash(&email),
+                query
+            ),
+            libravatar: format!(
+                "https://seccdn.libravatar.org/avatar/{}?{}",
+                hex::encode(sha256.finalize().as_slice()),
+                query
+            ),
+        }
+    }
+
+    /// URL of the service selected by the configuration
+    pub fn url(self, config: &AvatarConfig) -> String {
+        if config.provider == "libravatar" {
+            self.libravatar
+        } else {
+            self.gravatar
+        }
+    }
+}
+
+impl Bots {
+    pub fn new(config: &BotConfig) -> Result<Bots, AppError> {
+        let patterns =
+            RegexSetBuilder::new(config.patterns.split_whitespace())
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| AppError {
+                    message: Some("Invalid bot patterns".to_string()),
+                    cause: Some(e.to_string()),
+                    error_type: AppErrorType::ConfigError,
+                })?;
+
+        Ok(Bots(patterns))
+    }
+
+    /// Whether the normalized `email` is a bot
+    pub fn is_bot(&self, email: &str) -> bool {
+        self.0.is_match(email)
+    }
+}
+
+/// Names used with an email, the oldest first
+async fn find_names(
+    client: &Client,
+    email: &str,
+) -> Result<Vec<EmailName>, AppError> {
+    let names = client
+        .query(
+            "
+            SELECT name, commits, first_seen, last_seen FROM email_name
+            WHERE email = $1
+            ORDER BY first_seen, name
+            ",
+            &[&email],
+        )
+        .await?
+        .iter()
+        .map(|row| EmailName {
+            name: row.get(0),
+            commits: row.get(1),
+            first_seen: row.get(2),
+            last_seen: row.get(3),
+        })
+        .collect::<Vec<EmailName>>();
+
+    Ok(names)
+}
+
+/// Normalized form of an email, used to save and hash it: without the
+/// surrounding whitespace and lowercase
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Username of a noreply address of GitHub, `[id+]username@users.noreply.
+/// github.com`, or of GitLab, `[id-]username@users.noreply.gitlab.com`.
+/// `email` must be normalized
+pub fn noreply_username(email: &str) -> Option<String> {
+    let username = if let Some(user) =
+        email.strip_suffix("@users.noreply.github.com")
+    {
+        without_id(user, '+')
+    } else if let Some(user) = email.strip_suffix("@users.noreply.gitlab.com")
+    {
+        without_id(user, '-')
+    } else {
+        return None;
+    };
+
+    if username.is_empty() {
+        None
+    } else {
+        Some(username.to_string())
+    }
+}
+
+/// User of a noreply address without its optional prefix, which is the
+/// numeric id of the user followed by `separator`
+fn without_id(user: &str, separator: char) -> &str {
+    match user.split_once(separator) {
+        Some((id, username))
+            if !id.is_empty()
+                && !username.is_empty()
+                && id.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            username
+        }
+        _ => user,
+    }
+}
+
+/// Returns the MD5 hash of an email, hex encoded
+pub fn hash(email: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(email.as_bytes());
+
+    hex::encode(hasher.finalize().as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_trims_and_lowercases() {
+        assert_eq!(normalize("  Ann@Example.COM\n"), "ann@example.com");
+        assert_eq!(
+            hash(&normalize(" Ann@Example.com")),
+            hash("ann@example.com")
+        );
+    }
+
+    #[test]
+    fn noreply_username_of_github_and_gitlab() {
+        let username = |email: &str| noreply_username(email);
+
+        assert_eq!(
+            username("123+octocat@users.noreply.github.com"),
+            Some("octocat".to_string())
+        );
+        assert_eq!(
+            username("octocat@users.noreply.github.com"),
+            Some("octocat".to_string())
+        );
+        assert_eq!(
+            username("42-ann-lee@users.noreply.gitlab.com"),
+            Some("ann-lee".to_string())
+        );
+        assert_eq!(
+            username("ann-lee@users.noreply.gitlab.com"),
+            Some("ann-lee".to_string())
+        );
+    }
+
+    #[test]
+    fn noreply_username_keeps_usernames_which_are_not_ids() {
+        let username = |email: &str| noreply_username(email);
+
+        assert_eq!(
+            username("a1+octocat@users.noreply.github.com"),
+            Some("a1+octocat".to_string())
+        );
+        assert_eq!(
+            username("+octocat@users.noreply.github.com"),
+            Some("+octocat".to_string())
+        );
+        assert_eq!(
+            username("123+@users.noreply.github.com"),
+            Some("123+".to_string())
+        );
+        assert_eq!(username("@users.noreply.github.com"), None);
+        assert_eq!(username("octocat@noreply.github.com"), None);
+        assert_eq!(username("octocat@users.noreply.github.com.evil"), None);
     }
 }
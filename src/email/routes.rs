@@ -1,18 +1,40 @@
 use std::collections::HashMap;
 
-use crate::config::AppState;
-use crate::email::models::{Email, EmailData};
-use crate::errors::AppErrorResponse;
+use crate::config::{AppState, EmailPrivacy};
+use crate::email::models::{Avatar, EmailData, EmailWithNames};
+use crate::errors::{AppError, AppErrorResponse, AppErrorType};
+use crate::helpers::{bad_request, email_privacy, exclude_bots};
+use crate::pagination::Pagination;
+use actix_web::http::header;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use slog::info;
 
-/// Endpoint used for retrieve all emails
-async fn index(state: web::Data<AppState>) -> impl Responder {
-    let result = Email::find_all(state.pool.clone()).await;
+/// Endpoint used for retrieve a page of emails
+async fn index(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (pagination, exclude_bots) =
+        match (Pagination::from_request(&req), exclude_bots(&req)) {
+            (Ok(pagination), Ok(exclude_bots)) => (pagination, exclude_bots),
+            (Err(e), _) | (_, Err(e)) => {
+                return HttpResponse::from_error(e.into())
+            }
+        };
+    let privacy = email_privacy(&req, state.email_privacy);
+    let by_hash = privacy != EmailPrivacy::Public;
+    let result = state
+        .storage
+        .find_emails(&pagination, by_hash, exclude_bots)
+        .await;
     info!(state.log, "GET /email/");
 
     match result {
-        Ok(emails) => HttpResponse::Ok().json(emails),
+        Ok(emails) => HttpResponse::Ok().json(
+            emails
+                .map(|email| email.with_avatar(&state.avatar, privacy))
+                .link(&req),
+        ),
         _ => HttpResponse::BadRequest().json(AppErrorResponse {
             detail: "Error trying to read all emails from database"
                 .to_string(),
@@ -20,45 +42,154 @@ async fn index(state: web::Data<AppState>) -> impl Responder {
     }
 }
 
-// Endpoint used for create new email
+// Endpoint used for create new email. An email already saved is returned
+// with 200 instead of 201. When the emails are private, both cases return
+// 200 without the username and the contributor, so the response does not
+// tell whether the address was already known
 async fn create_email(
+    req: HttpRequest,
     payload: web::Json<EmailData>,
     state: web::Data<AppState>,
 ) -> impl Responder {
     info!(state.log, "POST /email/");
-    let result = Email::create(state.pool.clone(), &payload).await;
+    let privacy = email_privacy(&req, state.email_privacy);
+    let result = state.storage.create_email(&payload.email).await;
 
-    result
-        .map(|email| HttpResponse::Created().json(email))
-        .map_err(|e| e)
+    result.map(|(mut email, created)| {
+        let private = privacy != EmailPrivacy::Public;
+        if private {
+            email.username = None;
+            email.contributor_id = None;
+        }
+        let email = email.with_avatar(&state.avatar, privacy);
+        if created && !private {
+            HttpResponse::Created().json(email)
+        } else {
+            HttpResponse::Ok().json(email)
+        }
+    })
 }
 
-// Endpoint used for email search
+// Endpoint used for email search: `q` is a part of the address, or a domain
+// like "@example.com", or it is similar to the address or to an author name.
+// When the emails are private, it needs an Authorization: emails are looked
+// up by their hash instead
 async fn search_email(
     req: HttpRequest,
     state: web::Data<AppState>,
 ) -> impl Responder {
+    if email_privacy(&req, state.email_privacy) != EmailPrivacy::Public {
+        return HttpResponse::Unauthorized().json(AppErrorResponse {
+            detail: "Emails are private: look them up by their hash"
+                .to_string(),
+        });
+    }
+
+    let query = match web::Query::<HashMap<String, String>>::from_query(
+        req.query_string(),
+    ) {
+        Ok(query) => query,
+        Err(_) => {
+            return HttpResponse::from_error(
+                bad_request("Invalid query string").into(),
+            )
+        }
+    };
+    let q = match query.get("q") {
+        Some(x) => x.clone(),
+        None => String::new(),
+    };
+    info!(state.log, "GET /email/search/?q={}", &q);
+
+    let (pagination, exclude_bots) =
+        match (Pagination::from_request(&req), exclude_bots(&req)) {
+            (Ok(pagination), Ok(exclude_bots)) => (pagination, exclude_bots),
+            (Err(e), _) | (_, Err(e)) => {
+                return HttpResponse::from_error(e.into())
+            }
+        };
+    let result = state
+        .storage
+        .search_emails(&q, exclude_bots, &pagination)
+        .await;
+
+    match result {
+        Ok(emails) => HttpResponse::Ok().json(
+            emails
+                .map(|result| {
+                    result.with_avatar(&state.avatar, EmailPrivacy::Public)
+                })
+                .link(&req),
+        ),
+        Err(e) => HttpResponse::from_error(e.into()),
+    }
+}
+
+/// Endpoint used for getting the email whose MD5 hash is `hash`, with the
+/// names used with it
+async fn get_email(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    info!(state.log, "GET /email/{}/", &hash);
+
+    let privacy = email_privacy(&req, state.email_privacy);
+    let email = state.storage.find_email_by_hash(&hash).await?;
+    let names = state.storage.find_email_names(&email.email).await?;
+    let email = EmailWithNames { email, names };
+
+    Ok(HttpResponse::Ok().json(email.with_avatar(&state.avatar, privacy)))
+}
+
+/// Endpoint used for getting the profile of the author who uses the email
+/// whose MD5 hash is `hash`
+async fn get_profile(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    info!(state.log, "GET /email/{}/profile/", &hash);
+
+    let privacy = email_privacy(&req, state.email_privacy);
+    let profile = state.storage.find_profile(&hash).await?;
+
+    Ok(HttpResponse::Ok().json(profile.with_avatars(&state.avatar, privacy)))
+}
+
+/// Endpoint used for redirecting to the avatar of the email whose MD5 hash
+/// is `hash`. The size of the image can be sent as `size`
+async fn get_avatar(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    hash: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    info!(state.log, "GET /email/{}/avatar/", &hash);
+
     let query =
         web::Query::<HashMap<String, String>>::from_query(req.query_string())
-            .unwrap();
-    let email = match query.get("q") {
-        Some(x) => x,
-        None => {
-            return HttpResponse::NotFound().json(AppErrorResponse {
-                detail: "No email found".to_string(),
+            .map_err(|_| bad_request("Invalid query string"))?;
+    let size = match query.get("size").map(|size| size.parse::<u32>()) {
+        Some(Ok(size)) if size > 0 && size <= 2048 => Some(size),
+        Some(_) => {
+            return Err(AppError {
+                error_type: AppErrorType::BadRequestError,
+                message: Some(
+                    "Avatar size must be between 1 and 2048".to_string(),
+                ),
+                cause: None,
             });
         }
+        None => None,
     };
-    let result = Email::search(state.pool.clone(), email).await;
-    info!(state.log, "GET /email/search?q={}", email);
 
-    match result {
-        Ok(email) => HttpResponse::Ok().json(email),
-        _ => HttpResponse::NotFound().json(AppErrorResponse {
-            detail: "No email found".to_string(),
-        }),
-    }
+    let email = state.storage.find_email_by_hash(&hash).await?;
+    let url =
+        Avatar::new(&email.email, &state.avatar, size).url(&state.avatar);
+
+    Ok(HttpResponse::Found().header(header::LOCATION, url).finish())
 }
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/email")
@@ -69,6 +200,15 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             )
             .service(
                 web::resource("/search/").route(web::get().to(search_email)),
+            )
+            .service(web::resource("/{hash}/").route(web::get().to(get_email)))
+            .service(
+                web::resource("/{hash}/profile/")
+                    .route(web::get().to(get_profile)),
+            )
+            .service(
+                web::resource("/{hash}/avatar/")
+                    .route(web::get().to(get_avatar)),
             ),
     );
 }
@@ -0,0 +1,430 @@
+use crate::commit::models::{like_pattern, Commit};
+use crate::contributor::models::delete_empty;
+use crate::db::get_client;
+use crate::email::models::{hash, normalize};
+use crate::errors::AppError;
+use crate::helpers::bad_request;
+use crate::pagination::{Key, Page, Pagination};
+
+use chrono::NaiveDateTime;
+use deadpool_postgres::{Client, Pool};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::env;
+
+/// Addresses written between angle brackets, as in the trailers like
+/// "Co-authored-by: Name <email>"
+static BRACKETED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<([^<>\s]+@[^<>\s]+)>").unwrap());
+
+/// Address-shaped tokens of a message
+static ADDRESS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").unwrap());
+
+#[derive(Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "erasure")]
+/// An erased email, replaced everywhere by its pseudonym. Only the `digest`
+/// of the address is kept, to erase it again from the next imports
+pub struct Erasure {
+    pub hash_sha256: String,
+    pub pseudonym: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// Struct used to erase an email
+#[derive(Deserialize)]
+pub struct ErasureData {
+    pub email: String,
+}
+
+impl Erasure {
+    /// Find a page of erasures, the newest first
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+    ) -> Result<Page<Erasure>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Timestamp, Key::Text])?;
+        let statement = client
+            .prepare(
+                "
+                SELECT * FROM erasure
+                WHERE $1::text IS NULL
+                    OR (created_at, hash_sha256) < ($1::text::timestamp, $2)
+                ORDER BY created_at DESC, hash_sha256 DESC
+                LIMIT $3
+                ",
+            )
+            .await?;
+
+        let erasures = client
+            .query(&statement, &[&after[0], &after[1], &pagination.fetch()])
+            .await?
+            .iter()
+            .map(|row| Erasure::from_row_ref(row).unwrap())
+            .collect::<Vec<Erasure>>();
+
+        let count = if pagination.count {
+            let statement =
+                client.prepare("SELECT COUNT(*) FROM erasure").await?;
+            Some(client.query_one(&statement, &[]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(erasures, pagination, count, |erasure| {
+            vec![erasure.created_at.to_string(), erasure.hash_sha256.clone()]
+        }))
+    }
+
+    /// Replace an email by its pseudonym in the commits, as author, as
+    /// committer and inside their messages, and in the affiliations, blanking
+    /// the names of its commits. Then the email is deleted. Erasing an email
+    /// again keeps its pseudonym
+    pub async fn erase(pool: Pool, email: &str) -> Result<Erasure, AppError> {
+        let email = normalize(email);
+        if email.is_empty() {
+            return Err(bad_request("Email is required"));
+        }
+        let digest = digest(&email);
+
+        let mut client = get_client(pool.clone()).await.unwrap();
+        let transaction = client.transaction().await?;
+
+        let row = transaction
+            .query_one(
+                "
+                WITH inserted AS (
+                    INSERT INTO erasure(hash_sha256, pseudonym)
+                    VALUES ($1, $2)
+                    ON CONFLICT (hash_sha256) DO NOTHING
+                    RETURNING *
+                )
+                SELECT * FROM inserted
+                UNION ALL
+                SELECT * FROM erasure WHERE hash_sha256 = $1
+                ",
+                &[&digest, &pseudonym()],
+            )
+            .await?;
+        let erasure = Erasure::from_row_ref(&row).unwrap();
+        let pseudonym = &erasure.pseudonym;
+
+        transaction
+            .execute(
+                "
+                INSERT INTO email(email, hash_md5) VALUES ($1, $2)
+                ON CONFLICT (email) DO NOTHING
+                ",
+                &[pseudonym, &hash(pseudonym)],
+            )
+            .await?;
+        transaction
+            .execute(
+                "
+                UPDATE commit SET author_email = $2, author_name = ''
+                WHERE author_email = $1
+                ",
+                &[&email, pseudonym],
+            )
+            .await?;
+        transaction
+            .execute(
+                "
+                UPDATE commit SET committer_email = $2, committer_name = ''
+                WHERE committer_email = $1
+                ",
+                &[&email, pseudonym],
+            )
+            .await?;
+
+        let messages = transaction
+            .query(
+                "SELECT hash, text FROM commit WHERE text ILIKE $1",
+                &[&like_pattern(&email)],
+            )
+            .await?;
+        for row in messages {
+            let text: String = row.get(1);
+            transaction
+                .execute(
+                    "UPDATE commit SET text = $2 WHERE hash = $1",
+                    &[
+                        &row.get::<_, String>(0),
+                        &erase_text(&text, &email, pseudonym),
+                    ],
+                )
+                .await?;
+        }
+
+        transaction
+            .execute(
+                "UPDATE affiliation SET email = $2 WHERE email = $1",
+                &[&email, pseudonym],
+            )
+            .await?;
+        transaction
+            .execute("DELETE FROM email WHERE email = $1", &[&email])
+            .await?;
+        delete_empty(&transaction).await?;
+        transaction.commit().await?;
+
+        Ok(erasure)
+    }
+
+    /// Pseudonyms of the erased emails among `emails`, using a `client`
+    /// which can be inside a transaction
+    pub async fn pseudonyms(
+        client: &Client,
+        emails: Vec<String>,
+    ) -> Result<HashMap<String, String>, AppError> {
+        let mut emails: HashMap<String, String> = emails
+            .into_iter()
+            .map(|email| (digest(&email), email))
+            .collect();
+        let digests: Vec<&String> = emails.keys().collect();
+
+        let statement = client
+            .prepare(
+                "
+                SELECT hash_sha256, pseudonym FROM erasure
+                WHERE hash_sha256 = ANY($1)
+                ",
+            )
+            .await?;
+        let rows = client.query(&statement, &[&digests]).await?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let email = emails.remove(&row.get::<_, String>(0))?;
+                Some((email, row.get(1)))
+            })
+            .collect())
+    }
+}
+
+/// Replace the erased emails of new commits by their pseudonyms, as `erase`
+/// does for the saved ones. `pseudonyms` maps the emails to them
+pub fn pseudonymize(
+    commits: &mut [Commit],
+    pseudonyms: &HashMap<String, String>,
+) {
+    if pseudonyms.is_empty() {
+        return;
+    }
+    let addresses: Vec<(&String, &String, Regex)> = pseudonyms
+        .iter()
+        .map(|(email, pseudonym)| (email, pseudonym, address_regex(email)))
+        .collect();
+
+    for commit in commits.iter_mut() {
+        if let Some(pseudonym) = pseudonyms.get(&commit.author_email) {
+            commit.author_email = pseudonym.clone();
+            commit.author_name = String::new();
+        }
+        if let Some(pseudonym) = pseudonyms.get(&commit.committer_email) {
+            commit.committer_email = pseudonym.clone();
+            commit.committer_name = String::new();
+        }
+        for (email, pseudonym, address) in &addresses {
+            if !occurrences(&commit.text, address).is_empty() {
+                commit.text = erase_text(&commit.text, email, pseudonym);
+            }
+        }
+    }
+}
+
+/// Normalized emails of a message: the ones between angle brackets and the
+/// address-shaped tokens
+pub fn message_emails(text: &str) -> Vec<String> {
+    BRACKETED
+        .captures_iter(text)
+        .map(|captures| normalize(&captures[1]))
+        .chain(
+            ADDRESS
+                .find_iter(text)
+                .map(|found| normalize(found.as_str())),
+        )
+        .collect()
+}
+
+/// Replace `email` by `pseudonym` in a message, ignoring the case, but not
+/// inside a longer address. The trailers of the email lose their name too
+pub fn erase_text(text: &str, email: &str, pseudonym: &str) -> String {
+    let trailer = Regex::new(&format!(
+        r"(?im)^([\w-]+):[^\n<]*<{}>[ \t]*$",
+        regex::escape(email)
+    ))
+    .unwrap();
+    let replacement = format!("${{1}}: <{}>", pseudonym);
+    let text = trailer.replace_all(text, replacement.as_str());
+
+    let mut erased = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end) in occurrences(&text, &address_regex(email)) {
+        erased.push_str(&text[last..start]);
+        erased.push_str(pseudonym);
+        last = end;
+    }
+    erased.push_str(&text[last..]);
+
+    erased
+}
+
+/// Regex of `email`, ignoring the case
+fn address_regex(email: &str) -> Regex {
+    Regex::new(&format!("(?i){}", regex::escape(email))).unwrap()
+}
+
+/// Ranges of the matches of `address` in `text` which are a whole address:
+/// not preceded by `[\w.+-]` and not followed by `[\w-]`, or by a dot and
+/// one of them. The dot which ends a sentence is not part of the address
+fn occurrences(text: &str, address: &Regex) -> Vec<(usize, usize)> {
+    let is_local = |c: char| c.is_alphanumeric() || "_.+-".contains(c);
+    let is_domain = |c: char| c.is_alphanumeric() || "_-".contains(c);
+
+    address
+        .find_iter(text)
+        .filter(|found| {
+            let before = text[..found.start()].chars().next_back();
+            let mut after = text[found.end()..].chars();
+            let next = match after.next() {
+                Some('.') => after.next(),
+                next => next,
+            };
+
+            !before.is_some_and(is_local) && !next.is_some_and(is_domain)
+        })
+        .map(|found| (found.start(), found.end()))
+        .collect()
+}
+
+/// Hash saved for an erased email: its HMAC-SHA256 with a key derived from
+/// SECRET_KEY, so the erased addresses cannot be found by hashing the known
+/// ones
+pub fn digest(email: &str) -> String {
+    digest_with_key(&env::var("SECRET_KEY").unwrap_or_default(), email)
+}
+
+/// `digest` of `email` with the key derived from `secret_key`
+fn digest_with_key(secret_key: &str, email: &str) -> String {
+    let hkdf = Hkdf::<Sha256>::new(None, secret_key.as_bytes());
+    let mut key = [0u8; 32];
+    hkdf.expand(b"gico erasures", &mut key).unwrap();
+
+    let mut mac = Hmac::<Sha256>::new_varkey(&key).unwrap();
+    mac.update(email.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes().as_slice())
+}
+
+/// New pseudonym, which is a valid email of a reserved domain
+pub fn pseudonym() -> String {
+    format!("{}@erased.invalid", Uuid::new_v4().to_simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSEUDONYM: &str = "x@erased.invalid";
+
+    #[test]
+    fn erase_text_replaces_the_whole_address() {
+        assert_eq!(
+            erase_text(
+                "Thanks Bob@Example.com.",
+                "bob@example.com",
+                PSEUDONYM
+            ),
+            "Thanks x@erased.invalid."
+        );
+        assert_eq!(
+            erase_text(
+                "bob@example.com,bob@example.com",
+                "bob@example.com",
+                PSEUDONYM
+            ),
+            "x@erased.invalid,x@erased.invalid"
+        );
+    }
+
+    #[test]
+    fn erase_text_skips_longer_addresses() {
+        let text = "jbob@example.com bob@example.com.au bob@example.company \
+                    a.bob@example.com bob@example.com-x";
+        assert_eq!(erase_text(text, "bob@example.com", PSEUDONYM), text);
+    }
+
+    #[test]
+    fn erase_text_blanks_the_trailer_names() {
+        assert_eq!(
+            erase_text(
+                "Fix\n\nCo-authored-by: Bob <bob@example.com>",
+                "bob@example.com",
+                PSEUDONYM
+            ),
+            "Fix\n\nCo-authored-by: <x@erased.invalid>"
+        );
+    }
+
+    #[test]
+    fn pseudonymize_skips_longer_addresses() {
+        let mut pseudonyms = HashMap::new();
+        pseudonyms
+            .insert("bob@example.com".to_string(), PSEUDONYM.to_string());
+        let mut commits = vec![commit("Cc jbob@example.com, bob@example.com")];
+
+        pseudonymize(&mut commits, &pseudonyms);
+        assert_eq!(commits[0].text, "Cc jbob@example.com, x@erased.invalid");
+
+        let mut commits = vec![commit("Cc jbob@example.com")];
+        pseudonymize(&mut commits, &pseudonyms);
+        assert_eq!(commits[0].text, "Cc jbob@example.com");
+    }
+
+    #[test]
+    fn message_emails_finds_every_address() {
+        assert_eq!(
+            message_emails("Reported-by: Bob@Example.com\nAck <ann@x.org>"),
+            vec!["ann@x.org", "bob@example.com", "ann@x.org"]
+        );
+    }
+
+    #[test]
+    fn digest_depends_on_the_secret_key() {
+        let one = digest_with_key("one", "bob@example.com");
+
+        assert_eq!(one, digest_with_key("one", "bob@example.com"));
+        assert_ne!(one, digest_with_key("two", "bob@example.com"));
+        assert_ne!(one, digest_with_key("one", "ann@example.com"));
+        assert_eq!(one.len(), 64);
+    }
+
+    fn commit(text: &str) -> Commit {
+        Commit {
+            hash: String::new(),
+            tree: None,
+            text: text.to_string(),
+            date: chrono::Local::now(),
+            author_email: "ann@example.com".to_string(),
+            author_name: "Ann".to_string(),
+            committer_email: "ann@example.com".to_string(),
+            committer_name: "Ann".to_string(),
+            repository_url: "octo/demo".to_string(),
+            is_merge: false,
+            additions: None,
+            deletions: None,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::AppState;
+use crate::erasure::models::ErasureData;
+use crate::helpers::check_authorization;
+use crate::pagination::Pagination;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use slog::info;
+
+/// Endpoint used for retrieve a page of erasures.
+/// It uses a SECRET_KEY used like an API key
+async fn index(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "GET /erasure/");
+    check_authorization(&req)?;
+
+    let pagination = Pagination::from_request(&req)?;
+    let result = state.storage.find_erasures(&pagination).await;
+
+    result.map(|erasures| HttpResponse::Ok().json(erasures.link(&req)))
+}
+
+/// Endpoint used for erase an email, replacing it by a pseudonym.
+/// It uses a SECRET_KEY used like an API key
+async fn create_erasure(
+    req: HttpRequest,
+    payload: web::Json<ErasureData>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "POST /erasure/");
+    check_authorization(&req)?;
+
+    let result = state.storage.erase_email(&payload.email).await;
+
+    result.map(|erasure| HttpResponse::Created().json(erasure))
+}
+
+/// Routes for erasures
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/erasure/")
+            .route(web::get().to(index))
+            .route(web::post().to(create_erasure)),
+    );
+}
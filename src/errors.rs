@@ -10,6 +10,8 @@ pub enum AppErrorType {
     NotFoundError,
     AuthorizationError,
     GitError,
+    BadRequestError,
+    ConfigError,
 }
 
 #[derive(Debug)]
@@ -56,6 +58,16 @@ impl From<Error> for AppError {
     }
 }
 
+impl From<rusqlite::Error> for AppError {
+    fn from(error: rusqlite::Error) -> AppError {
+        AppError {
+            message: None,
+            cause: Some(error.to_string()),
+            error_type: AppErrorType::DbError,
+        }
+    }
+}
+
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         write!(f, "{:?}", self)
@@ -74,6 +86,8 @@ impl ResponseError for AppError {
             AppErrorType::NotFoundError => StatusCode::NOT_FOUND,
             AppErrorType::AuthorizationError => StatusCode::UNAUTHORIZED,
             AppErrorType::GitError => StatusCode::BAD_REQUEST,
+            AppErrorType::BadRequestError => StatusCode::BAD_REQUEST,
+            AppErrorType::ConfigError => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
@@ -1,8 +1,92 @@
 use crate::commit::models::Commit;
-use chrono::{DateTime, Local};
-use git2::{BranchType, Error, Repository, Time};
+use crate::config::ImportConfig;
+use crate::email::models::normalize;
+use chrono::{DateTime, Local, Utc};
+use futures::executor::block_on;
+use git2::build::RepoBuilder;
+use git2::{
+    BranchType, Cred, DiffOptions, Error, FetchOptions, Oid, RemoteCallbacks,
+    Repository, Revwalk, Sort, Time,
+};
+use tokio::sync::mpsc::Sender;
 
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::fs::remove_dir_all;
+use std::mem::replace;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Credential used to clone a private repository
+pub enum GitCredential {
+    /// Username and token used over HTTPS
+    Token { username: String, token: String },
+    /// SSH private key and its passphrase
+    SshKey {
+        username: String,
+        private_key: String,
+        passphrase: Option<String>,
+    },
+}
+
+/// Limits checked while a repository is imported
+#[derive(Clone)]
+pub struct ImportLimits {
+    /// Maximum number of bytes received by the clone
+    pub max_bytes: usize,
+    /// Maximum time spent by the clone and the walk of the history
+    pub timeout: Duration,
+    /// Maximum number of commits imported
+    pub max_commits: usize,
+    /// Commits older than this date are not imported
+    pub since: Option<DateTime<Utc>>,
+    /// Maximum number of commits walked from the head
+    pub depth: Option<usize>,
+    /// Number of commits sent together to be saved
+    pub batch_size: usize,
+}
+
+/// What a walk of the history reads, within the `limits`
+pub struct WalkInput<'a> {
+    /// Head of the previous import, skipped with its ancestors
+    pub last_head: Option<&'a String>,
+    /// Commits already imported, read again to be completed
+    pub incomplete: &'a [String],
+    pub limits: &'a ImportLimits,
+    /// When the import started, checked against the timeout
+    pub started: Instant,
+}
+
+/// Result of a walk of the history
+pub struct Walk {
+    /// Hash of the head of the branch
+    pub head: String,
+    /// Number of commits walked
+    pub commits: usize,
+}
+
+impl ImportLimits {
+    /// Limits of the server configuration, for the whole history
+    pub fn from_config(config: &ImportConfig) -> ImportLimits {
+        ImportLimits {
+            max_bytes: config.max_bytes,
+            timeout: Duration::from_secs(config.timeout),
+            max_commits: config.max_commits,
+            since: None,
+            depth: None,
+            batch_size: config.batch_size,
+        }
+    }
+
+    /// Error returned when the import takes more than `timeout`
+    pub fn timeout_error(&self) -> Error {
+        Error::from_str(&format!(
+            "Repository import exceeds the timeout of {} seconds",
+            self.timeout.as_secs()
+        ))
+    }
+}
 
 /// Return the temporary folder of the git repository
 fn get_tmp_dir(repo_name: &String) -> String {
@@ -11,14 +95,103 @@ fn get_tmp_dir(repo_name: &String) -> String {
 
 /// Clone a repository `repo` in a temporary folder. It uses only GitHub at
 /// this moment.
-pub fn clone_repo(repo_name: &String) -> Result<Repository, Error> {
-    let url: &str =
-        &format!("https://github.com/{}", &repo_name).to_owned()[..];
+/// The clone is bare because the working tree is not needed to read the
+/// history. It is stopped when it exceeds the size or the time of `limits`,
+/// or when `cancelled` is set: libgit2 does not check them while the
+/// transfer is stalled, so the caller also waits it with a timeout.
+/// A private repository needs a `credential`: an SSH key clones over SSH.
+/// The folder is removed when the clone fails
+pub fn clone_repo(
+    repo_name: &String,
+    credential: Option<&GitCredential>,
+    limits: &ImportLimits,
+    started: Instant,
+    cancelled: &AtomicBool,
+) -> Result<Repository, Error> {
+    let url: &str = &match credential {
+        Some(GitCredential::SshKey { .. }) => {
+            format!("ssh://git@github.com/{}.git", &repo_name)
+        }
+        _ => format!("https://github.com/{}", &repo_name),
+    }[..];
     let tmp_dir: String = get_tmp_dir(&repo_name);
 
-    let cloned = Repository::clone(url, tmp_dir)?;
+    // Remove a possible already cloned repository
+    let _ = remove_dir_all(&tmp_dir);
+
+    // libgit2 asks again the credential when it is refused
+    let authenticated = Cell::new(false);
+
+    // libgit2 only reports that the clone has been stopped by the callback,
+    // so the reason is saved here
+    let exceeded: Cell<Option<Error>> = Cell::new(None);
+
+    let cloned = {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.transfer_progress(|progress| {
+            if progress.received_bytes() > limits.max_bytes {
+                exceeded.set(Some(Error::from_str(&format!(
+                    "Repository exceeds the maximum size of {} bytes",
+                    limits.max_bytes
+                ))));
+                return false;
+            }
+            if started.elapsed() > limits.timeout
+                || cancelled.load(Ordering::Relaxed)
+            {
+                exceeded.set(Some(limits.timeout_error()));
+                return false;
+            }
+
+            true
+        });
+
+        if let Some(credential) = credential {
+            let authenticated = &authenticated;
+            callbacks.credentials(move |_url, username_from_url, _allowed| {
+                if authenticated.replace(true) {
+                    return Err(Error::from_str("Credential refused"));
+                }
 
-    Ok(cloned)
+                match credential {
+                    GitCredential::Token { username, token } => {
+                        Cred::userpass_plaintext(username, token)
+                    }
+                    GitCredential::SshKey {
+                        username,
+                        private_key,
+                        passphrase,
+                    } => Cred::ssh_key_from_memory(
+                        username_from_url.unwrap_or(username),
+                        None,
+                        private_key,
+                        passphrase.as_deref(),
+                    ),
+                }
+            });
+        }
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+
+        RepoBuilder::new()
+            .bare(true)
+            .fetch_options(fetch_options)
+            .clone(url, Path::new(&tmp_dir))
+    };
+
+    match cloned {
+        // Nobody waits the clone anymore
+        Ok(_) if cancelled.load(Ordering::Relaxed) => {
+            let _ = remove_dir_all(&tmp_dir);
+            Err(limits.timeout_error())
+        }
+        Ok(repo) => Ok(repo),
+        Err(e) => {
+            let _ = remove_dir_all(&tmp_dir);
+            Err(exceeded.take().unwrap_or(e))
+        }
+    }
 }
 
 /// Check if a `branch` exists inside the repository and then set the head of
@@ -54,8 +227,43 @@ pub fn get_date(commit: &git2::Commit) -> String {
     result
 }
 
+/// Lines added and deleted by a commit, compared with its first parent.
+/// Merges have none, since their lines are counted in the merged commits
+fn get_stats(
+    repo: &Repository,
+    gcommit: &git2::Commit,
+) -> Result<(i32, i32), Error> {
+    if gcommit.parent_count() > 1 {
+        return Ok((0, 0));
+    }
+
+    let parent = match gcommit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    // Only the changed lines are needed, without the context around them.
+    // Binary files count no lines
+    let mut options = DiffOptions::new();
+    options
+        .context_lines(0)
+        .interhunk_lines(0)
+        .ignore_submodules(true);
+    let diff = repo.diff_tree_to_tree(
+        parent.as_ref(),
+        Some(&gcommit.tree()?),
+        Some(&mut options),
+    )?;
+    let stats = diff.stats()?;
+
+    Ok((stats.insertions() as i32, stats.deletions() as i32))
+}
+
 /// Get a `git2::Commit` and returns a valid `Commit` to upload to the database
-fn get_commit(gcommit: &git2::Commit, repo_name: &String) -> Commit {
+fn get_commit(
+    repo: &Repository,
+    gcommit: &git2::Commit,
+    repo_name: &String,
+) -> Result<Commit, Error> {
     let hash = gcommit.id().to_string();
     let tree = match gcommit.parent_id(0) {
         Ok(parent) => Some(parent.to_string()),
@@ -80,12 +288,14 @@ fn get_commit(gcommit: &git2::Commit, repo_name: &String) -> Commit {
                 panic!(e)
             }
         };
-    let author_email = gcommit.author().email().unwrap().to_string();
+    let author_email = normalize(gcommit.author().email().unwrap());
     let author_name = gcommit.author().name().unwrap().to_string();
-    let committer_email = gcommit.committer().email().unwrap().to_string();
+    let committer_email = normalize(gcommit.committer().email().unwrap());
     let committer_name = gcommit.committer().name().unwrap().to_string();
+    let is_merge = gcommit.parent_count() > 1;
+    let (additions, deletions) = get_stats(repo, gcommit)?;
 
-    Commit {
+    Ok(Commit {
         hash,
         tree,
         text,
@@ -95,49 +305,209 @@ fn get_commit(gcommit: &git2::Commit, repo_name: &String) -> Commit {
         committer_email,
         committer_name,
         repository_url: repo_name.clone(),
-    }
+        is_merge,
+        additions: Some(additions),
+        deletions: Some(deletions),
+    })
 }
 
-/// Get all commits from a Git repository. Returns an array of `Commit`.
-/// First, clone the repo into a temporary folder.
-/// Then, open the repository.
-/// Then, get commits
+/// Get the commits of a repository cloned by `clone_repo`, sending them to
+/// `sender` in batches: parents are always sent before their children.
+/// If `input.last_head` is a commit already imported, it and its ancestors
+/// are skipped, so only the new commits are sent.
+/// Then the `input.incomplete` commits, already imported, are sent again to
+/// be completed.
 /// Finally, remove the temporary folder
 pub fn repo_commits(
+    repo: Repository,
     repo_name: &String,
     branch: &String,
-) -> Result<Vec<Commit>, Error> {
-    // Remove a possible already cloned repository
+    input: WalkInput,
+    mut sender: Sender<Vec<Commit>>,
+) -> Result<Walk, Error> {
+    let walk = walk_commits(
+        &repo,
+        repo_name,
+        branch,
+        input.last_head,
+        input.limits,
+        input.started,
+        &mut sender,
+    )
+    .and_then(|walk| {
+        complete_commits(
+            &repo,
+            repo_name,
+            input.incomplete,
+            input.limits,
+            input.started,
+            &mut sender,
+        )?;
+        Ok(walk)
+    });
+
+    // Remove the cloned repository folder
+    drop(repo);
     let _ = remove_dir_all(get_tmp_dir(&repo_name));
 
-    // Try to clone the repo. If it returns an error, it's useless to go ahead:
-    // raises an error.
-    let repo = match clone_repo(&repo_name) {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(e);
-        }
-    };
+    walk
+}
 
-    if let Err(e) = get_branch(&repo, branch) {
+/// Walk the history of `branch` within the `limits`, from the oldest commit
+/// to the head
+fn walk_commits(
+    repo: &Repository,
+    repo_name: &String,
+    branch: &String,
+    last_head: Option<&String>,
+    limits: &ImportLimits,
+    started: Instant,
+    sender: &mut Sender<Vec<Commit>>,
+) -> Result<Walk, Error> {
+    if let Err(e) = get_branch(repo, branch) {
         return Err(e);
     }
 
-    let mut revwalk = repo.revwalk()?;
     let head = repo.head().unwrap().target().unwrap();
 
+    // `depth` counts the commits from the head, but the walk starts from the
+    // oldest one: so the commits in depth are found before
+    let in_depth: Option<HashSet<Oid>> = match limits.depth {
+        Some(depth) => {
+            let mut revwalk = repo.revwalk()?;
+            revwalk.push(head)?;
+            hide_last_head(&mut revwalk, last_head);
+
+            Some(revwalk.take(depth).collect::<Result<_, Error>>()?)
+        }
+        None => None,
+    };
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+
     if let Err(e) = revwalk.push(head) {
         return Err(e);
     }
+    hide_last_head(&mut revwalk, last_head);
+
+    let in_limits = |commit: &git2::Commit| -> bool {
+        if let Some(since) = limits.since {
+            if commit.time().seconds() < since.timestamp() {
+                return false;
+            }
+        }
+
+        match &in_depth {
+            Some(oids) => oids.contains(&commit.id()),
+            None => true,
+        }
+    };
+
+    let mut batch: Vec<Commit> = Vec::with_capacity(limits.batch_size);
+    let mut walked: usize = 0;
+    for oid in revwalk {
+        if started.elapsed() > limits.timeout {
+            return Err(limits.timeout_error());
+        }
 
-    let mut commits: Vec<Commit> = vec![];
-    for commit in revwalk {
-        let hash = repo.find_commit(commit?)?;
-        commits.push(get_commit(&hash, &repo_name));
+        let hash = repo.find_commit(oid?)?;
+        if !in_limits(&hash) {
+            continue;
+        }
+
+        if walked == limits.max_commits {
+            return Err(Error::from_str(&format!(
+                "Repository has more than {} commits: use `since` or `depth` \
+                to import only a part of its history",
+                limits.max_commits
+            )));
+        }
+        walked += 1;
+
+        let mut commit = get_commit(repo, &hash, repo_name)?;
+
+        // The parent could be out of the limits: it is not imported, so it
+        // cannot be referenced
+        if commit.tree.is_some()
+            && (limits.since.is_some() || limits.depth.is_some())
+        {
+            match hash.parent(0) {
+                Ok(parent) if in_limits(&parent) => {}
+                _ => commit.tree = None,
+            }
+        }
+
+        batch.push(commit);
+        if batch.len() == limits.batch_size {
+            let full =
+                replace(&mut batch, Vec::with_capacity(limits.batch_size));
+            send_batch(sender, full)?;
+        }
     }
 
-    // Remove the cloned repository folder
-    let _ = remove_dir_all(get_tmp_dir(&repo_name));
+    if !batch.is_empty() {
+        send_batch(sender, batch)?;
+    }
 
-    Ok(commits)
+    Ok(Walk {
+        head: head.to_string(),
+        commits: walked,
+    })
+}
+
+/// Read again the commits with these `hashes`, sending them in batches. The
+/// commits missing from the repository are skipped
+fn complete_commits(
+    repo: &Repository,
+    repo_name: &String,
+    hashes: &[String],
+    limits: &ImportLimits,
+    started: Instant,
+    sender: &mut Sender<Vec<Commit>>,
+) -> Result<(), Error> {
+    for chunk in hashes.chunks(limits.batch_size) {
+        let mut batch: Vec<Commit> = Vec::with_capacity(chunk.len());
+        for hash in chunk {
+            if started.elapsed() > limits.timeout {
+                return Err(limits.timeout_error());
+            }
+
+            let gcommit = match Oid::from_str(hash)
+                .and_then(|oid| repo.find_commit(oid))
+            {
+                Ok(gcommit) => gcommit,
+                Err(_) => continue,
+            };
+            batch.push(get_commit(repo, &gcommit, repo_name)?);
+        }
+
+        if !batch.is_empty() {
+            send_batch(sender, batch)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Hide the commits already imported, up to `last_head`.
+/// The old head could be missing after a force push: in that case walk the
+/// whole history, already imported commits are ignored by the database.
+fn hide_last_head(revwalk: &mut Revwalk, last_head: Option<&String>) {
+    if let Some(last_head) = last_head {
+        if let Ok(oid) = Oid::from_str(last_head) {
+            let _ = revwalk.hide(oid);
+        }
+    }
+}
+
+/// Send a batch of commits to be saved, waiting when the receiver is busy
+fn send_batch(
+    sender: &mut Sender<Vec<Commit>>,
+    batch: Vec<Commit>,
+) -> Result<(), Error> {
+    match block_on(sender.send(batch)) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::from_str("Commits are not saved anymore")),
+    }
 }
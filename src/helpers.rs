@@ -1,4 +1,12 @@
+use crate::config::EmailPrivacy;
+use crate::errors::{AppError, AppErrorType};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest};
 use regex::Regex;
+use serde::de::{self, Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 /// Returns a valid Uuid if `id` is not a valid Uuid
@@ -24,3 +32,157 @@ pub fn name_of_git_repository(url: &String) -> Option<String> {
 
     Some(format!("{}/{}", name, repo))
 }
+
+/// Check if the "Authorization" header of the request matches with
+/// SECRET_KEY, in constant time. A missing SECRET_KEY never matches
+pub fn check_authorization(req: &HttpRequest) -> Result<(), AppError> {
+    authorization_with_key(req, &env::var("SECRET_KEY").unwrap_or_default())
+}
+
+/// `check_authorization` with the given `secret_key`
+fn authorization_with_key(
+    req: &HttpRequest,
+    secret_key: &str,
+) -> Result<(), AppError> {
+    match req.headers().get(header::AUTHORIZATION) {
+        Some(x)
+            if !secret_key.is_empty()
+                && bool::from(x.as_bytes().ct_eq(secret_key.as_bytes())) =>
+        {
+            Ok(())
+        }
+        _ => Err(AppError {
+            error_type: AppErrorType::AuthorizationError,
+            message: Some(
+                "You must provide a valid Authorization".to_string(),
+            ),
+            cause: None,
+        }),
+    }
+}
+
+/// How the emails are shown to a request: a request with a valid
+/// Authorization always gets the raw addresses
+pub fn email_privacy(
+    req: &HttpRequest,
+    privacy: EmailPrivacy,
+) -> EmailPrivacy {
+    if privacy == EmailPrivacy::Public || check_authorization(req).is_ok() {
+        EmailPrivacy::Public
+    } else {
+        privacy
+    }
+}
+
+/// Read the `exclude_bots` parameter of the query string, false by default
+pub fn exclude_bots(req: &HttpRequest) -> Result<bool, AppError> {
+    let query =
+        web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map_err(|_| bad_request("Invalid query string"))?;
+
+    match query.get("exclude_bots") {
+        Some(value) => {
+            flag(value).ok_or_else(|| bad_request("Invalid exclude_bots"))
+        }
+        None => Ok(false),
+    }
+}
+
+/// Deserialize a boolean of the query string as `exclude_bots` does, for
+/// the filters read by serde
+pub fn deserialize_flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+
+    flag(&value).ok_or_else(|| de::Error::custom("invalid boolean"))
+}
+
+/// Value of a boolean of the query string: "true" or "1", "false" or "0"
+fn flag(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Similarity of two strings from 0 to 1, the share of their trigrams which
+/// are common, as the `similarity` of the pg_trgm extension of PostgreSQL
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = trigrams(a);
+    let b = trigrams(b);
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// Trigrams of the alphanumeric words of `text`, lowercase, each one padded
+/// with two spaces before and one after
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    let mut trigrams = HashSet::new();
+
+    for word in text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let padded: Vec<char> = format!("  {} ", word).chars().collect();
+        for window in padded.windows(3) {
+            trigrams.insert([window[0], window[1], window[2]]);
+        }
+    }
+
+    trigrams
+}
+
+/// Error of an invalid request, with its `message`
+pub fn bad_request(message: &str) -> AppError {
+    AppError {
+        error_type: AppErrorType::BadRequestError,
+        cause: None,
+        message: Some(message.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn authorization_must_match_the_secret_key() {
+        let with = |value: &str| {
+            TestRequest::default()
+                .header(header::AUTHORIZATION, value)
+                .to_http_request()
+        };
+        let without = TestRequest::default().to_http_request();
+
+        assert!(
+            authorization_with_key(&with("topsecret"), "topsecret").is_ok()
+        );
+        assert!(
+            authorization_with_key(&with("topsecre"), "topsecret").is_err()
+        );
+        assert!(
+            authorization_with_key(&with("topsecret!"), "topsecret").is_err()
+        );
+        assert!(authorization_with_key(&without, "topsecret").is_err());
+    }
+
+    #[test]
+    fn authorization_fails_without_a_secret_key() {
+        let empty = TestRequest::default()
+            .header(header::AUTHORIZATION, "")
+            .to_http_request();
+        let without = TestRequest::default().to_http_request();
+
+        assert!(authorization_with_key(&empty, "").is_err());
+        assert!(authorization_with_key(&without, "").is_err());
+    }
+}
@@ -2,20 +2,28 @@ mod config;
 mod db;
 mod errors;
 mod helpers;
+mod migrations;
+mod pagination;
+mod storage;
 
 mod git;
+mod sync;
 
 mod branch;
 mod commit;
+mod contributor;
+mod credential;
 mod email;
+mod erasure;
+mod organization;
 mod repository;
+mod webhook;
 
 use actix_cors::Cors;
 use actix_web::{http::header, middleware, App, HttpServer};
 use dotenv::dotenv;
-use slog::info;
-use std::env;
-use tokio_postgres::NoTls;
+use slog::{error, info};
+use std::{env, io};
 
 use crate::config::{AppState, Config};
 
@@ -24,9 +32,30 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
     let config = Config::from_env().unwrap();
-    let pool = config.pg.create_pool(NoTls).unwrap();
     let log = Config::logging();
 
+    if let Err(e) = config.validate() {
+        error!(log, "Invalid configuration: {}", e.message());
+        return Err(io::Error::other(e.message()));
+    }
+
+    // Migrations run at every start. `gico migrate` only runs them
+    let storage = match storage::connect(&config, &log).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!(log, "Storage cannot be opened: {}", e);
+            return Err(io::Error::other(e.message()));
+        }
+    };
+    if env::args().nth(1).as_deref() == Some("migrate") {
+        return Ok(());
+    }
+
+    let import = config.import.clone();
+    let avatar = config.avatar.clone();
+    let email_privacy = config.email_privacy;
+    let sync = sync::start(storage.clone(), log.clone(), import.clone());
+
     info!(
         log,
         "Starting server at http://{}:{}",
@@ -37,8 +66,12 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         App::new()
             .data(AppState {
-                pool: pool.clone(),
+                storage: storage.clone(),
                 log: log.clone(),
+                sync: sync.clone(),
+                import: import.clone(),
+                avatar: avatar.clone(),
+                email_privacy,
             })
             .wrap(middleware::Logger::default())
             .wrap(
@@ -56,7 +89,12 @@ async fn main() -> std::io::Result<()> {
             .configure(repository::routes::config)
             .configure(email::routes::config)
             .configure(commit::routes::config)
+            .configure(contributor::routes::config)
+            .configure(organization::routes::config)
+            .configure(erasure::routes::config)
             .configure(branch::routes::config)
+            .configure(credential::routes::config)
+            .configure(webhook::routes::config)
     })
     .bind(format!("{}:{}", config.server.host, config.server.port))?
     .run()
@@ -0,0 +1,393 @@
+use crate::credential::models as credential;
+use crate::db::get_client;
+use crate::email::models as email;
+use crate::errors::{AppError, AppErrorType};
+
+use deadpool_postgres::{Pool, Transaction};
+use futures::future::{BoxFuture, FutureExt};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+use slog::{info, Logger};
+use uuid::Uuid;
+
+/// Code of a migration, run in its transaction before its SQL, for what
+/// only the models can compute
+type Step =
+    for<'a> fn(&'a Transaction<'_>) -> BoxFuture<'a, Result<(), AppError>>;
+
+/// Migrations of the database schema, embedded in the binary, with their
+/// optional `Step`. The version of a migration is its position, starting
+/// from 1: a migration already released must never be changed, only new
+/// ones can be appended
+const MIGRATIONS: &[(&str, &str, Option<Step>)] = &[
+    (
+        "initial",
+        include_str!("../migrations/postgres/0001_initial.sql"),
+        None,
+    ),
+    (
+        "webhooks and credentials",
+        include_str!(
+            "../migrations/postgres/0002_webhooks_and_credentials.sql"
+        ),
+        None,
+    ),
+    (
+        "commit search",
+        include_str!("../migrations/postgres/0003_commit_search.sql"),
+        None,
+    ),
+    (
+        "commit filters",
+        include_str!("../migrations/postgres/0004_commit_filters.sql"),
+        None,
+    ),
+    (
+        "email normalization",
+        include_str!("../migrations/postgres/0005_email_normalization.sql"),
+        Some(normalize_emails),
+    ),
+    (
+        "contributors",
+        include_str!("../migrations/postgres/0006_contributors.sql"),
+        None,
+    ),
+    (
+        "email search",
+        include_str!("../migrations/postgres/0007_email_search.sql"),
+        None,
+    ),
+    (
+        "organizations",
+        include_str!("../migrations/postgres/0008_organizations.sql"),
+        None,
+    ),
+    (
+        "bots",
+        include_str!("../migrations/postgres/0009_bots.sql"),
+        None,
+    ),
+    (
+        "erasures",
+        include_str!("../migrations/postgres/0010_erasures.sql"),
+        None,
+    ),
+    (
+        "email names",
+        include_str!("../migrations/postgres/0011_email_names.sql"),
+        None,
+    ),
+    (
+        "commit lines",
+        include_str!("../migrations/postgres/0012_commit_lines.sql"),
+        None,
+    ),
+    (
+        "webhook secret encryption",
+        include_str!(
+            "../migrations/postgres/0013_webhook_secret_encryption.sql"
+        ),
+        Some(encrypt_webhook_secrets),
+    ),
+];
+
+/// Migrations of the SQLite schema. They follow the same rules of
+/// `MIGRATIONS`, but their versions are not related
+const SQLITE_MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "initial",
+        include_str!("../migrations/sqlite/0001_initial.sql"),
+    ),
+    (
+        "email normalization",
+        include_str!("../migrations/sqlite/0002_email_normalization.sql"),
+    ),
+    (
+        "contributors",
+        include_str!("../migrations/sqlite/0003_contributors.sql"),
+    ),
+    (
+        "organizations",
+        include_str!("../migrations/sqlite/0004_organizations.sql"),
+    ),
+    ("bots", include_str!("../migrations/sqlite/0005_bots.sql")),
+    (
+        "erasures",
+        include_str!("../migrations/sqlite/0006_erasures.sql"),
+    ),
+    (
+        "email names",
+        include_str!("../migrations/sqlite/0007_email_names.sql"),
+    ),
+    (
+        "commit lines",
+        include_str!("../migrations/sqlite/0008_commit_lines.sql"),
+    ),
+    (
+        "webhook secret encryption",
+        include_str!(
+            "../migrations/sqlite/0009_webhook_secret_encryption.sql"
+        ),
+    ),
+];
+
+/// Key of the advisory lock which prevents two servers from migrating the
+/// same database at the same time
+const LOCK_KEY: i64 = 0x6769_636f;
+
+/// Apply the migrations not yet applied, each one in its own transaction.
+/// It fails when the database has been migrated by a newer binary
+pub async fn run(pool: Pool, log: &Logger) -> Result<(), AppError> {
+    let mut client = get_client(pool.clone()).await?;
+
+    client
+        .batch_execute(
+            "
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version integer PRIMARY KEY NOT NULL,
+                name varchar(120) NOT NULL,
+                applied_at timestamptz NOT NULL DEFAULT NOW()
+            )
+            ",
+        )
+        .await?;
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&LOCK_KEY])
+        .await?;
+
+    let result = async {
+        let current: i32 = client
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+                &[],
+            )
+            .await?
+            .get(0);
+        let latest = MIGRATIONS.len() as i32;
+
+        if current > latest {
+            return Err(AppError {
+                message: Some(format!(
+                    "Database schema is at version {} but this binary only \
+                    knows up to version {}",
+                    current, latest
+                )),
+                cause: None,
+                error_type: AppErrorType::DbError,
+            });
+        }
+
+        for (index, (name, sql, step)) in
+            MIGRATIONS.iter().enumerate().skip(current as usize)
+        {
+            let version = index as i32 + 1;
+            info!(log, "Applying migration {}: {}", version, name);
+
+            let transaction = client.transaction().await?;
+            if let Some(step) = step {
+                step(&transaction).await?;
+            }
+            transaction.batch_execute(sql).await?;
+            transaction
+                .execute(
+                    "INSERT INTO schema_migrations(version, name)
+                    VALUES($1, $2)",
+                    &[&version, name],
+                )
+                .await?;
+            transaction.commit().await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    client
+        .execute("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY])
+        .await?;
+
+    result
+}
+
+/// Step of the email normalization: make the temporary `normalized_email`
+/// table with every saved email, normalized by `email::normalize`, which
+/// PostgreSQL cannot follow exactly
+fn normalize_emails<'a>(
+    transaction: &'a Transaction<'_>,
+) -> BoxFuture<'a, Result<(), AppError>> {
+    async move {
+        transaction
+            .batch_execute(
+                "
+                CREATE TEMPORARY TABLE normalized_email (
+                    email varchar(120) PRIMARY KEY NOT NULL,
+                    normalized varchar(120) NOT NULL,
+                    hash_md5 varchar(32) NOT NULL,
+                    username varchar(120) NULL
+                ) ON COMMIT DROP
+                ",
+            )
+            .await?;
+
+        let mut emails: Vec<String> = vec![];
+        let mut normalized: Vec<String> = vec![];
+        let mut hashes: Vec<String> = vec![];
+        let mut usernames: Vec<Option<String>> = vec![];
+        for row in transaction.query("SELECT email FROM email", &[]).await? {
+            let address: String = row.get(0);
+            let normal = email::normalize(&address);
+            hashes.push(email::hash(&normal));
+            usernames.push(email::noreply_username(&normal));
+            normalized.push(normal);
+            emails.push(address);
+        }
+
+        transaction
+            .execute(
+                "
+                INSERT INTO normalized_email
+                SELECT * FROM UNNEST(
+                    $1::varchar[], $2::varchar[], $3::varchar[],
+                    $4::varchar[]
+                )
+                ",
+                &[&emails, &normalized, &hashes, &usernames],
+            )
+            .await?;
+
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Step of the webhook secret encryption: make the temporary
+/// `encrypted_webhook_secret` table with the saved secrets, encrypted by
+/// `credential::encrypt`
+fn encrypt_webhook_secrets<'a>(
+    transaction: &'a Transaction<'_>,
+) -> BoxFuture<'a, Result<(), AppError>> {
+    async move {
+        transaction
+            .batch_execute(
+                "
+                CREATE TEMPORARY TABLE encrypted_webhook_secret (
+                    id uuid PRIMARY KEY NOT NULL,
+                    secret bytea NOT NULL
+                ) ON COMMIT DROP
+                ",
+            )
+            .await?;
+
+        let rows = transaction
+            .query(
+                "
+                SELECT id, webhook_secret FROM repository
+                WHERE webhook_secret IS NOT NULL
+                ",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let id: Uuid = row.get(0);
+            let secret: String = row.get(1);
+            transaction
+                .execute(
+                    "INSERT INTO encrypted_webhook_secret VALUES($1, $2)",
+                    &[&id, &credential::encrypt(&id, secret.as_bytes())?],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Apply the SQLite migrations not yet applied, each one in its own
+/// transaction. The connection is not shared yet, so nobody else can
+/// migrate at the same time
+pub fn run_sqlite(
+    connection: &mut Connection,
+    log: &Logger,
+) -> Result<(), AppError> {
+    connection.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version integer PRIMARY KEY NOT NULL,
+            name varchar(120) NOT NULL,
+            applied_at text NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        ",
+    )?;
+
+    let current: i64 = connection.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        rusqlite::NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    let latest = SQLITE_MIGRATIONS.len() as i64;
+    register_functions(connection)?;
+
+    if current > latest {
+        return Err(AppError {
+            message: Some(format!(
+                "Database schema is at version {} but this binary only \
+                knows up to version {}",
+                current, latest
+            )),
+            cause: None,
+            error_type: AppErrorType::DbError,
+        });
+    }
+
+    for (index, (name, sql)) in
+        SQLITE_MIGRATIONS.iter().enumerate().skip(current as usize)
+    {
+        let version = index as i64 + 1;
+        info!(log, "Applying migration {}: {}", version, name);
+
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(sql)?;
+        transaction.execute(
+            "INSERT INTO schema_migrations(version, name) VALUES(?1, ?2)",
+            rusqlite::params![version, name],
+        )?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Register the functions of the models used by the SQLite migrations
+fn register_functions(connection: &Connection) -> Result<(), AppError> {
+    let flags =
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+
+    connection.create_scalar_function("normalize_email", 1, flags, |ctx| {
+        Ok(email::normalize(&ctx.get::<String>(0)?))
+    })?;
+    connection.create_scalar_function("hash_email", 1, flags, |ctx| {
+        Ok(email::hash(&ctx.get::<String>(0)?))
+    })?;
+    connection.create_scalar_function(
+        "noreply_username",
+        1,
+        flags,
+        |ctx| Ok(email::noreply_username(&ctx.get::<String>(0)?)),
+    )?;
+    connection.create_scalar_function(
+        "encrypt_webhook_secret",
+        2,
+        FunctionFlags::SQLITE_UTF8,
+        |ctx| {
+            let id = Uuid::parse_str(&ctx.get::<String>(0)?).map_err(|e| {
+                rusqlite::Error::UserFunctionError(Box::new(e))
+            })?;
+            credential::encrypt(&id, ctx.get::<String>(1)?.as_bytes()).map_err(
+                |e| rusqlite::Error::UserFunctionError(e.to_string().into()),
+            )
+        },
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,457 @@
+use crate::db::get_client;
+use crate::email::models::normalize;
+use crate::errors::{AppError, AppErrorType};
+use crate::helpers::{bad_request, deserialize_flag};
+use crate::pagination::{Key, Page, Pagination};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+use uuid::Uuid;
+
+/// Organization of the commits whose author matches neither an affiliation
+/// nor a domain
+pub const UNKNOWN: &str = "Unknown";
+
+#[derive(Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "organization_domain")]
+/// Organization of the emails of a domain and of its subdomains
+pub struct OrganizationDomain {
+    pub domain: String,
+    pub organization: String,
+}
+
+#[derive(Serialize, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "affiliation")]
+/// Organization of an email, which replaces the one of its domain between
+/// `since` and `until`, excluded. Missing dates are unbounded
+pub struct Affiliation {
+    pub id: Uuid,
+    pub email: String,
+    pub organization: String,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+/// Struct used to create a new affiliation
+#[derive(Deserialize)]
+pub struct AffiliationData {
+    pub email: String,
+    pub organization: String,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+/// Filters of the commits report. `period` is "day", "week", "month" (the
+/// default) or "year"
+#[derive(Deserialize)]
+pub struct OrganizationFilters {
+    pub period: Option<String>,
+    pub repository_user: Option<String>,
+    pub repository_name: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// `true` to skip the commits whose author is a bot
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    pub exclude_bots: bool,
+}
+
+/// Number of commits of an organization in a repository during a period
+#[derive(Serialize)]
+pub struct OrganizationCommits {
+    pub organization: String,
+    pub repository_url: String,
+    /// First day of the period. Weeks start on Monday
+    pub period: NaiveDate,
+    pub commits: i64,
+}
+
+/// Commits with the organization of their author. `$1` is the period, `$2`
+/// the repository url, `$3` and `$4` the dates and `$6` the bots filter of
+/// `OrganizationFilters`, `$5` the unknown organization.
+/// An affiliation wins over a domain, and a longer domain over a shorter one
+const AFFILIATED_COMMITS: &str = "
+    SELECT commit.repository_url,
+        date_trunc($1, commit.date AT TIME ZONE 'UTC')::date AS period,
+        COALESCE(
+            (
+                SELECT organization FROM affiliation
+                WHERE affiliation.email = commit.author_email
+                    AND (since IS NULL
+                        OR since <= (commit.date AT TIME ZONE 'UTC')::date)
+                    AND (until IS NULL
+                        OR until > (commit.date AT TIME ZONE 'UTC')::date)
+                ORDER BY since DESC NULLS LAST
+                LIMIT 1
+            ),
+            (
+                SELECT organization FROM organization_domain
+                WHERE substr(author_email, strpos(author_email, '@') + 1)
+                    = domain
+                    OR author_email LIKE '%@%.' || domain
+                ORDER BY length(domain) DESC
+                LIMIT 1
+            ),
+            $5
+        ) AS organization
+    FROM commit
+    WHERE ($2::varchar IS NULL OR repository_url = $2)
+        AND ($3::timestamptz IS NULL OR date >= $3)
+        AND ($4::timestamptz IS NULL OR date <= $4)
+        AND NOT ($6 AND author_email IN (
+            SELECT email FROM email WHERE is_bot
+        ))";
+
+impl OrganizationDomain {
+    /// Check the domain and normalize it as the emails
+    pub fn new(
+        data: &OrganizationDomain,
+    ) -> Result<OrganizationDomain, AppError> {
+        let domain = normalize(data.domain.trim_start_matches('@'));
+        if domain.is_empty() || domain.contains('@') {
+            return Err(bad_request("Invalid domain"));
+        }
+        if data.organization.trim().is_empty() {
+            return Err(bad_request("Organization is required"));
+        }
+
+        Ok(OrganizationDomain {
+            domain,
+            organization: data.organization.trim().to_string(),
+        })
+    }
+
+    /// Find a page of domains, ordered by domain
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationDomain>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Text])?;
+        let statement = client
+            .prepare(
+                "
+                SELECT * FROM organization_domain
+                WHERE $1::text IS NULL OR domain > $1
+                ORDER BY domain
+                LIMIT $2
+                ",
+            )
+            .await?;
+
+        let domains = client
+            .query(&statement, &[&after[0], &pagination.fetch()])
+            .await?
+            .iter()
+            .map(|row| OrganizationDomain::from_row_ref(row).unwrap())
+            .collect::<Vec<OrganizationDomain>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare("SELECT COUNT(*) FROM organization_domain")
+                .await?;
+            Some(client.query_one(&statement, &[]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(domains, pagination, count, |domain| {
+            vec![domain.domain.clone()]
+        }))
+    }
+
+    /// Save the organization of a domain, replacing the previous one
+    pub async fn save(
+        pool: Pool,
+        domain: &OrganizationDomain,
+    ) -> Result<OrganizationDomain, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(
+                "
+                INSERT INTO organization_domain(domain, organization)
+                VALUES($1, $2)
+                ON CONFLICT (domain) DO UPDATE
+                SET organization = excluded.organization
+                RETURNING *
+                ",
+            )
+            .await?;
+
+        let row = client
+            .query_one(&statement, &[&domain.domain, &domain.organization])
+            .await?;
+
+        Ok(OrganizationDomain::from_row_ref(&row).unwrap())
+    }
+
+    /// Find a domain and delete it
+    pub async fn delete(
+        pool: Pool,
+        domain: &str,
+    ) -> Result<OrganizationDomain, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(
+                "
+                DELETE FROM organization_domain
+                WHERE domain = $1
+                RETURNING *
+                ",
+            )
+            .await?;
+
+        let domain = client
+            .query_opt(&statement, &[&normalize(domain)])
+            .await?
+            .map(|row| OrganizationDomain::from_row_ref(&row).unwrap());
+
+        domain.ok_or_else(|| not_found("Domain not found"))
+    }
+}
+
+impl Affiliation {
+    /// Make a new affiliation, with its email normalized. It still has to
+    /// be saved
+    pub fn new(data: &AffiliationData) -> Result<Affiliation, AppError> {
+        let email = normalize(&data.email);
+        if email.is_empty() {
+            return Err(bad_request("Email is required"));
+        }
+        if data.organization.trim().is_empty() {
+            return Err(bad_request("Organization is required"));
+        }
+        if let (Some(since), Some(until)) = (data.since, data.until) {
+            if since >= until {
+                return Err(bad_request("`since` must be before `until`"));
+            }
+        }
+
+        Ok(Affiliation {
+            id: Uuid::new_v4(),
+            email,
+            organization: data.organization.trim().to_string(),
+            since: data.since,
+            until: data.until,
+        })
+    }
+
+    /// Find a page of affiliations, ordered by email and by date
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+    ) -> Result<Page<Affiliation>, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+        let statement = client
+            .prepare(
+                "
+                SELECT * FROM affiliation
+                WHERE $1::text IS NULL OR (email, id) > ($1, $2::text::uuid)
+                ORDER BY email, id
+                LIMIT $3
+                ",
+            )
+            .await?;
+
+        let affiliations = client
+            .query(&statement, &[&after[0], &after[1], &pagination.fetch()])
+            .await?
+            .iter()
+            .map(|row| Affiliation::from_row_ref(row).unwrap())
+            .collect::<Vec<Affiliation>>();
+
+        let count = if pagination.count {
+            let statement =
+                client.prepare("SELECT COUNT(*) FROM affiliation").await?;
+            Some(client.query_one(&statement, &[]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(affiliations, pagination, count, |affiliation| {
+            vec![affiliation.email.clone(), affiliation.id.to_string()]
+        }))
+    }
+
+    /// Save a new affiliation
+    pub async fn create(
+        pool: Pool,
+        affiliation: &Affiliation,
+    ) -> Result<Affiliation, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare(
+                "
+                INSERT INTO affiliation(id, email, organization, since, until)
+                VALUES($1, $2, $3, $4, $5)
+                RETURNING *
+                ",
+            )
+            .await?;
+
+        let row = client
+            .query_one(
+                &statement,
+                &[
+                    &affiliation.id,
+                    &affiliation.email,
+                    &affiliation.organization,
+                    &affiliation.since,
+                    &affiliation.until,
+                ],
+            )
+            .await?;
+
+        Ok(Affiliation::from_row_ref(&row).unwrap())
+    }
+
+    /// Find an affiliation and delete it
+    pub async fn delete(
+        pool: Pool,
+        id: &Uuid,
+    ) -> Result<Affiliation, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
+        let statement = client
+            .prepare("DELETE FROM affiliation WHERE id = $1 RETURNING *")
+            .await?;
+
+        let affiliation = client
+            .query_opt(&statement, &[&id])
+            .await?
+            .map(|row| Affiliation::from_row_ref(&row).unwrap());
+
+        affiliation.ok_or_else(|| not_found("Affiliation not found"))
+    }
+}
+
+impl OrganizationFilters {
+    /// Period of the report, checked
+    pub fn period(&self) -> Result<&str, AppError> {
+        match self.period.as_deref() {
+            None => Ok("month"),
+            Some(period @ "day")
+            | Some(period @ "week")
+            | Some(period @ "month")
+            | Some(period @ "year") => Ok(period),
+            Some(_) => Err(bad_request(
+                "Period must be \"day\", \"week\", \"month\" or \"year\"",
+            )),
+        }
+    }
+
+    /// Url of the repository, when both its user and its name are sent
+    pub fn repository_url(&self) -> Option<String> {
+        match (&self.repository_user, &self.repository_name) {
+            (Some(user), Some(name)) => Some(format!("{}/{}", user, name)),
+            _ => None,
+        }
+    }
+}
+
+impl OrganizationCommits {
+    /// Find a page of the number of commits of each organization in each
+    /// repository and period, the newest period first
+    pub async fn find_all(
+        pool: Pool,
+        filters: &OrganizationFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationCommits>, AppError> {
+        let period = filters.period()?;
+        let repository_url = filters.repository_url();
+
+        let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Date, Key::Text, Key::Text])?;
+        let statement = client
+            .prepare(&format!(
+                "
+                SELECT organization, repository_url, period, COUNT(*)
+                FROM ({}) affiliated
+                GROUP BY organization, repository_url, period
+                HAVING $7::text IS NULL
+                    OR period < $7::text::date
+                    OR (period = $7::text::date
+                        AND (organization, repository_url) > ($8, $9))
+                ORDER BY period DESC, organization, repository_url
+                LIMIT $10
+                ",
+                AFFILIATED_COMMITS
+            ))
+            .await?;
+
+        let reports = client
+            .query(
+                &statement,
+                &[
+                    &period,
+                    &repository_url,
+                    &filters.since,
+                    &filters.until,
+                    &UNKNOWN,
+                    &filters.exclude_bots,
+                    &after[0],
+                    &after[1],
+                    &after[2],
+                    &pagination.fetch(),
+                ],
+            )
+            .await?
+            .iter()
+            .map(|row| OrganizationCommits {
+                organization: row.get(0),
+                repository_url: row.get(1),
+                period: row.get(2),
+                commits: row.get(3),
+            })
+            .collect::<Vec<OrganizationCommits>>();
+
+        let count = if pagination.count {
+            let statement = client
+                .prepare(&format!(
+                    "
+                    SELECT COUNT(DISTINCT
+                        (organization, repository_url, period))
+                    FROM ({}) affiliated
+                    ",
+                    AFFILIATED_COMMITS
+                ))
+                .await?;
+            Some(
+                client
+                    .query_one(
+                        &statement,
+                        &[
+                            &period,
+                            &repository_url,
+                            &filters.since,
+                            &filters.until,
+                            &UNKNOWN,
+                            &filters.exclude_bots,
+                        ],
+                    )
+                    .await?
+                    .get(0),
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(reports, pagination, count, |report| {
+            vec![
+                report.period.to_string(),
+                report.organization.clone(),
+                report.repository_url.clone(),
+            ]
+        }))
+    }
+}
+
+fn not_found(message: &str) -> AppError {
+    AppError {
+        error_type: AppErrorType::NotFoundError,
+        cause: None,
+        message: Some(message.to_string()),
+    }
+}
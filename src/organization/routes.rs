@@ -0,0 +1,149 @@
+use crate::config::AppState;
+use crate::helpers::{check_authorization, uuid_from_string};
+use crate::organization::models::{
+    Affiliation, AffiliationData, OrganizationDomain, OrganizationFilters,
+};
+use crate::pagination::Pagination;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use slog::info;
+use uuid::Uuid;
+
+/// Endpoint used for retrieve a page of the number of commits of each
+/// organization in each repository and period
+async fn get_commits(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    filters: web::Query<OrganizationFilters>,
+) -> impl Responder {
+    info!(state.log, "GET /organization/commits/");
+
+    let pagination = Pagination::from_request(&req)?;
+    let result = state
+        .storage
+        .organization_commits(&filters, &pagination)
+        .await;
+
+    result.map(|reports| HttpResponse::Ok().json(reports.link(&req)))
+}
+
+/// Endpoint used for retrieve a page of organization domains.
+/// It uses a SECRET_KEY used like an API key
+async fn get_domains(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "GET /organization/domain/");
+    check_authorization(&req)?;
+
+    let pagination = Pagination::from_request(&req)?;
+    let result = state.storage.find_organization_domains(&pagination).await;
+
+    result.map(|domains| HttpResponse::Ok().json(domains.link(&req)))
+}
+
+/// Endpoint used for set the organization of a domain.
+/// It uses a SECRET_KEY used like an API key
+async fn save_domain(
+    req: HttpRequest,
+    payload: web::Json<OrganizationDomain>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "POST /organization/domain/");
+    check_authorization(&req)?;
+
+    let domain = OrganizationDomain::new(&payload)?;
+    let result = state.storage.save_organization_domain(&domain).await;
+
+    result.map(|domain| HttpResponse::Ok().json(domain))
+}
+
+/// Endpoint used for delete the organization of a domain.
+/// It uses a SECRET_KEY used like an API key
+async fn delete_domain(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    domain: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "DELETE /organization/domain/{}/", &domain);
+    check_authorization(&req)?;
+
+    let result = state.storage.delete_organization_domain(&domain).await;
+
+    result.map(|_| HttpResponse::NoContent().body(""))
+}
+
+/// Endpoint used for retrieve a page of affiliations.
+/// It uses a SECRET_KEY used like an API key
+async fn get_affiliations(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "GET /organization/affiliation/");
+    check_authorization(&req)?;
+
+    let pagination = Pagination::from_request(&req)?;
+    let result = state.storage.find_affiliations(&pagination).await;
+
+    result.map(|affiliations| HttpResponse::Ok().json(affiliations.link(&req)))
+}
+
+/// Endpoint used for create an affiliation of an email.
+/// It uses a SECRET_KEY used like an API key
+async fn create_affiliation(
+    req: HttpRequest,
+    payload: web::Json<AffiliationData>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    info!(state.log, "POST /organization/affiliation/");
+    check_authorization(&req)?;
+
+    let affiliation = Affiliation::new(&payload)?;
+    let result = state.storage.create_affiliation(&affiliation).await;
+
+    result.map(|affiliation| HttpResponse::Created().json(affiliation))
+}
+
+/// Endpoint used for delete an affiliation.
+/// It uses a SECRET_KEY used like an API key
+async fn delete_affiliation(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "DELETE /organization/affiliation/{}/", &id);
+    check_authorization(&req)?;
+
+    let uuid: Uuid = uuid_from_string(&id);
+    let result = state.storage.delete_affiliation(&uuid).await;
+
+    result.map(|_| HttpResponse::NoContent().body(""))
+}
+
+/// Routes for organizations
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/organization")
+            .service(
+                web::resource("/commits/").route(web::get().to(get_commits)),
+            )
+            .service(
+                web::resource("/domain/")
+                    .route(web::get().to(get_domains))
+                    .route(web::post().to(save_domain)),
+            )
+            .service(
+                web::resource("/domain/{domain}/")
+                    .route(web::delete().to(delete_domain)),
+            )
+            .service(
+                web::resource("/affiliation/")
+                    .route(web::get().to(get_affiliations))
+                    .route(web::post().to(create_affiliation)),
+            )
+            .service(
+                web::resource("/affiliation/{id}/")
+                    .route(web::delete().to(delete_affiliation)),
+            ),
+    );
+}
@@ -0,0 +1,284 @@
+use crate::errors::AppError;
+use crate::helpers::bad_request;
+
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+
+/// Number of items of a page when `limit` is not sent
+const DEFAULT_LIMIT: i64 = 100;
+
+/// Max number of items of a page
+const MAX_LIMIT: i64 = 1000;
+
+/// Type of a value of a cursor
+#[derive(Clone, Copy)]
+pub enum Key {
+    Text,
+    Uuid,
+    /// A timestamp, with or without its offset
+    Timestamp,
+    Date,
+    Float,
+}
+
+/// Keyset pagination sent as query parameters: `limit`, `cursor` (returned
+/// by the previous page as part of `next`) and `count`
+pub struct Pagination {
+    pub limit: i64,
+    cursor: Option<Vec<String>>,
+    pub count: bool,
+}
+
+/// A page of a list. `count` is the number of items of the whole list and
+/// it is only returned when requested, because it is slower to get
+#[derive(Serialize)]
+pub struct Page<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>,
+    pub next: Option<String>,
+    pub results: Vec<T>,
+}
+
+impl Pagination {
+    /// Read the pagination parameters from the query string
+    pub fn from_request(req: &HttpRequest) -> Result<Pagination, AppError> {
+        let query = web::Query::<HashMap<String, String>>::from_query(
+            req.query_string(),
+        )
+        .map_err(|_| bad_request("Invalid query string"))?;
+
+        let limit = match query.get("limit") {
+            Some(limit) => match limit.parse::<i64>() {
+                Ok(limit) if limit > 0 => limit.min(MAX_LIMIT),
+                _ => return Err(bad_request("Invalid limit")),
+            },
+            None => DEFAULT_LIMIT,
+        };
+
+        let cursor = match query.get("cursor") {
+            Some(cursor) => Some(decode(cursor)?),
+            None => None,
+        };
+
+        let count = match query.get("count").map(|x| x.as_str()) {
+            Some("true") | Some("1") => true,
+            Some("false") | Some("0") | None => false,
+            Some(_) => return Err(bad_request("Invalid count")),
+        };
+
+        Ok(Pagination {
+            limit,
+            cursor,
+            count,
+        })
+    }
+
+    /// Rows to fetch: one more than `limit`, to know if there is a next page
+    pub fn fetch(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// Returns the values of the last item of the previous page, used as
+    /// `$n::text` parameters of the query. Every value is checked against
+    /// its key, so a tampered cursor is a bad request instead of an error of
+    /// the query. They are all `None` on the first page
+    pub fn after(
+        &self,
+        keys: &[Key],
+    ) -> Result<Vec<Option<String>>, AppError> {
+        match &self.cursor {
+            Some(cursor) if cursor.len() == keys.len() => cursor
+                .iter()
+                .zip(keys)
+                .map(|(value, key)| key.check(value).map(Some))
+                .collect::<Option<Vec<Option<String>>>>()
+                .ok_or_else(|| bad_request("Invalid cursor")),
+            Some(_) => Err(bad_request("Invalid cursor")),
+            None => Ok(vec![None; keys.len()]),
+        }
+    }
+}
+
+impl Key {
+    /// Returns `value` written as the database reads it, or `None` when it
+    /// is not a value of this key
+    fn check(self, value: &str) -> Option<String> {
+        match self {
+            Key::Text if !value.contains('\0') => Some(value.to_string()),
+            Key::Text => None,
+            Key::Uuid => Uuid::parse_str(value).ok().map(|id| id.to_string()),
+            // A date with its offset, or a date in UTC
+            Key::Timestamp => match DateTime::parse_from_rfc3339(value) {
+                Ok(date) if in_range(date.naive_utc().date()) => {
+                    Some(date.to_rfc3339())
+                }
+                Ok(_) => None,
+                Err(_) => NaiveDateTime::parse_from_str(
+                    value,
+                    "%Y-%m-%d %H:%M:%S%.f",
+                )
+                .ok()
+                .filter(|date| in_range(date.date()))
+                .map(|date| date.format("%Y-%m-%d %H:%M:%S%.f"))
+                .map(|date| date.to_string()),
+            },
+            Key::Date => value
+                .parse::<NaiveDate>()
+                .ok()
+                .filter(|date| in_range(*date))
+                .map(|date| date.to_string()),
+            Key::Float => value
+                .parse::<f64>()
+                .ok()
+                .filter(|rank| rank.is_finite())
+                .map(|rank| rank.to_string()),
+        }
+    }
+}
+
+impl<T> Page<T> {
+    /// Make a page from the rows fetched with `Pagination::fetch`. `keys`
+    /// returns the values used to sort an item, saved inside the cursor
+    pub fn new<F>(
+        mut results: Vec<T>,
+        pagination: &Pagination,
+        count: Option<i64>,
+        keys: F,
+    ) -> Page<T>
+    where
+        F: Fn(&T) -> Vec<String>,
+    {
+        let next = if results.len() as i64 > pagination.limit {
+            results.truncate(pagination.limit as usize);
+            results.last().map(|item| encode(&keys(item)))
+        } else {
+            None
+        };
+
+        Page {
+            count,
+            next,
+            results,
+        }
+    }
+
+    /// Convert every item of the page with `f`
+    pub fn map<U, F>(self, f: F) -> Page<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        Page {
+            count: self.count,
+            next: self.next,
+            results: self.results.into_iter().map(f).collect(),
+        }
+    }
+
+    /// Replace the cursor of the next page with the URL of the next page,
+    /// keeping the other parameters of `req`
+    pub fn link(mut self, req: &HttpRequest) -> Page<T> {
+        if let Some(cursor) = self.next.take() {
+            let mut query: Vec<(String, String)> =
+                serde_urlencoded::from_str(req.query_string())
+                    .unwrap_or_default();
+            query.retain(|(key, _)| key != "cursor");
+            query.push(("cursor".to_string(), cursor));
+
+            let info = req.connection_info();
+            self.next = Some(format!(
+                "{}://{}{}?{}",
+                info.scheme(),
+                info.host(),
+                req.path(),
+                serde_urlencoded::to_string(&query).unwrap()
+            ));
+        }
+
+        self
+    }
+}
+
+/// Dates out of these years cannot be written by every database
+fn in_range(date: NaiveDate) -> bool {
+    (1..=9999).contains(&date.year())
+}
+
+/// Encode the values of a cursor in an opaque string
+fn encode(values: &[String]) -> String {
+    base64::encode_config(
+        serde_json::to_vec(values).unwrap(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Decode a cursor made by `encode`
+fn decode(cursor: &str) -> Result<Vec<String>, AppError> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| bad_request("Invalid cursor"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_returns_the_encoded_values() {
+        let values = vec!["2020-01-02 03:04:05".to_string(), "a".to_string()];
+        assert_eq!(decode(&encode(&values)).unwrap(), values);
+    }
+
+    #[test]
+    fn decode_rejects_invalid_cursors() {
+        let json =
+            |text: &str| base64::encode_config(text, base64::URL_SAFE_NO_PAD);
+
+        assert!(decode("not base64!").is_err());
+        assert!(decode("WyJhIl0+/w").is_err());
+        assert!(decode(&json("not json")).is_err());
+        assert!(decode(&json(r#"{"a": "b"}"#)).is_err());
+        assert!(decode(&json("[1, 2]")).is_err());
+    }
+
+    #[test]
+    fn after_checks_the_values_against_their_keys() {
+        let after = |values: &[&str], keys: &[Key]| {
+            Pagination {
+                limit: 10,
+                cursor: Some(values.iter().map(|x| x.to_string()).collect()),
+                count: false,
+            }
+            .after(keys)
+        };
+        let id = "5b3c4e7a-7d0e-4bb4-9a2b-6d1f0f6f0a11";
+
+        assert_eq!(
+            after(&["2.5", id], &[Key::Float, Key::Uuid]).unwrap(),
+            vec![Some("2.5".to_string()), Some(id.to_string())]
+        );
+        assert!(after(&[id, "2.5"], &[Key::Float, Key::Uuid]).is_err());
+        assert!(after(&["2.5"], &[Key::Float, Key::Uuid]).is_err());
+        assert!(after(&["NaN"], &[Key::Float]).is_err());
+        assert!(after(&["a\0"], &[Key::Text]).is_err());
+        assert!(after(&["2020-13-01"], &[Key::Date]).is_err());
+        assert!(after(&["10000-01-01"], &[Key::Date]).is_err());
+        assert!(after(&["yesterday"], &[Key::Timestamp]).is_err());
+    }
+
+    #[test]
+    fn timestamps_keep_their_offset() {
+        assert_eq!(
+            Key::Timestamp.check("2020-01-02T03:04:05+02:00"),
+            Some("2020-01-02T03:04:05+02:00".to_string())
+        );
+        assert_eq!(
+            Key::Timestamp.check("2020-01-02 03:04:05.5"),
+            Some("2020-01-02 03:04:05.500".to_string())
+        );
+    }
+}
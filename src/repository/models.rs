@@ -1,20 +1,33 @@
 use crate::branch::models::{Branch, BranchData};
 use crate::commit::models::Commit;
+use crate::config::ImportConfig;
+use crate::credential::models::encrypt;
 use crate::db::get_client;
-use crate::email::models::{Email, EmailData};
+use crate::email::models::Email;
+use crate::erasure::models::{message_emails, pseudonymize};
 use crate::errors::{AppError, AppErrorType};
-use crate::git;
+use crate::git::{self, GitCredential, ImportLimits, Walk, WalkInput};
 use crate::helpers::name_of_git_repository;
+use crate::pagination::{Key, Page, Pagination};
+use crate::storage::{Storage, StorageTransaction};
 
-use chrono::NaiveDateTime;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use deadpool_postgres::{Client, Pool};
+use futures::future::join;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::time::timeout;
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
 use uuid::Uuid;
 
 use std::collections::HashSet;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper(table = "repository")]
@@ -25,6 +38,11 @@ pub struct Repository {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub uploader_ip: String,
+    /// Encrypted by `credential::models::encrypt`
+    #[serde(skip_serializing)]
+    pub webhook_secret: Option<Vec<u8>>,
+    #[serde(skip_serializing)]
+    pub credential_id: Option<Uuid>,
 }
 
 /// Struct used to create a new repository
@@ -32,26 +50,56 @@ pub struct Repository {
 pub struct RepositoryData {
     pub url: String,
     pub branch: String,
+    /// Secret shared with the git host, used to verify push webhooks
+    pub webhook_secret: Option<String>,
+    /// Import only the commits made after this date
+    pub since: Option<DateTime<Utc>>,
+    /// Import only this number of commits, walking the history from the head
+    pub depth: Option<usize>,
+    /// Credential used to clone a private repository
+    pub credential_id: Option<Uuid>,
 }
 
 impl Repository {
-    /// Find all repositories inside the database.
-    /// Make a select query and order the repositories by descrescent updated
-    /// datetime
-    pub async fn find_all(pool: Pool) -> Result<Vec<Repository>, AppError> {
+    /// Find a page of the repositories inside the database.
+    /// Make a select query and order the repositories by descrescent created
+    /// datetime, which does not change when a repository is synced
+    pub async fn find_all(
+        pool: Pool,
+        pagination: &Pagination,
+    ) -> Result<Page<Repository>, AppError> {
         let client = get_client(pool.clone()).await.unwrap();
+        let after = pagination.after(&[Key::Timestamp, Key::Uuid])?;
         let statement = client
-            .prepare("SELECT * FROM repository ORDER BY updated_at DESC")
+            .prepare(
+                "
+                SELECT * FROM repository
+                WHERE $1::text IS NULL
+                    OR (created_at, id) < ($1::text::timestamp, $2::text::uuid)
+                ORDER BY created_at DESC, id DESC
+                LIMIT $3
+                ",
+            )
             .await?;
 
         let repos = client
-            .query(&statement, &[])
+            .query(&statement, &[&after[0], &after[1], &pagination.fetch()])
             .await?
             .iter()
             .map(|row| Repository::from_row_ref(row).unwrap())
             .collect::<Vec<Repository>>();
 
-        Ok(repos)
+        let count = if pagination.count {
+            let statement =
+                client.prepare("SELECT COUNT(*) FROM repository").await?;
+            Some(client.query_one(&statement, &[]).await?.get(0))
+        } else {
+            None
+        };
+
+        Ok(Page::new(repos, pagination, count, |repo| {
+            vec![repo.created_at.to_string(), repo.id.to_string()]
+        }))
     }
 
     /// Find a repository with an `id` equals to an Uuid element
@@ -82,24 +130,41 @@ impl Repository {
         pool: Pool,
         id: &Uuid,
     ) -> Result<Repository, AppError> {
-        let client = get_client(pool.clone()).await.unwrap();
-        let statement = client
-            .prepare(
+        let mut client = get_client(pool.clone()).await.unwrap();
+        let transaction = client.transaction().await?;
+
+        let emails: Vec<String> = transaction
+            .query(
                 "
-                DELETE FROM repository
-                WHERE id=$1
-                RETURNING *
+                SELECT author_email FROM commit
+                JOIN repository ON repository.url = commit.repository_url
+                WHERE repository.id = $1
+                UNION
+                SELECT committer_email FROM commit
+                JOIN repository ON repository.url = commit.repository_url
+                WHERE repository.id = $1
                 ",
+                &[&id],
             )
-            .await?;
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
 
-        let repo = client
-            .query_opt(&statement, &[&id])
+        let repo = transaction
+            .query_opt(
+                "DELETE FROM repository WHERE id=$1 RETURNING *",
+                &[&id],
+            )
             .await?
             .map(|row| Repository::from_row_ref(&row).unwrap());
 
         match repo {
-            Some(repo) => Ok(repo),
+            Some(repo) => {
+                Email::refresh_names(&transaction, &emails).await?;
+                transaction.commit().await?;
+                Ok(repo)
+            }
             None => Err(AppError {
                 error_type: AppErrorType::NotFoundError,
                 cause: None,
@@ -108,11 +173,12 @@ impl Repository {
         }
     }
 
-    /// Search a repository by its url
-    async fn search(
-        client: &Client,
-        url: String,
+    /// Find a repository by its url
+    pub async fn find_by_url(
+        pool: Pool,
+        url: &str,
     ) -> Result<Repository, AppError> {
+        let client = get_client(pool.clone()).await.unwrap();
         let statement = client
             .prepare("SELECT * FROM repository WHERE url=$1")
             .await?;
@@ -132,14 +198,67 @@ impl Repository {
         }
     }
 
-    /// Create a new repository. It uses RepositoryData as support struct
+    /// Save a repository, using a `client` which can be inside a
+    /// transaction. `created_at` and `updated_at` are set by the database
+    pub async fn insert(
+        client: &Client,
+        repo: &Repository,
+    ) -> Result<Repository, AppError> {
+        let statement = client
+            .prepare(
+                "
+                INSERT INTO repository(
+                    id, url, uploader_ip, webhook_secret, credential_id
+                )
+                VALUES($1, $2, $3, $4, $5) RETURNING *
+            ",
+            )
+            .await?;
+
+        let repo = client
+            .query_opt(
+                &statement,
+                &[
+                    &repo.id,
+                    &repo.url,
+                    &repo.uploader_ip,
+                    &repo.webhook_secret,
+                    &repo.credential_id,
+                ],
+            )
+            .await?
+            .map(|row| Repository::from_row_ref(&row).unwrap());
+
+        match repo {
+            Some(repo) => Ok(repo),
+            None => Err(AppError {
+                message: Some("Error creating a new repository".to_string()),
+                cause: Some("Unknown error".to_string()),
+                error_type: AppErrorType::DbError,
+            }),
+        }
+    }
+
+    /// Move `updated_at` to now, using a `client` which can be inside a
+    /// transaction
+    pub async fn touch(client: &Client, id: &Uuid) -> Result<(), AppError> {
+        let statement = client
+            .prepare("UPDATE repository SET updated_at = NOW() WHERE id = $1")
+            .await?;
+        client.execute(&statement, &[&id]).await?;
+
+        Ok(())
+    }
+
+    /// Create a new repository. It uses RepositoryData as support struct.
+    /// The repository, its commits and its branch are saved in a single
+    /// transaction, so nothing is left when the import fails
     pub async fn create(
-        pool: Pool,
+        storage: &dyn Storage,
         data: &RepositoryData,
         uploader_ip: Option<SocketAddr>,
+        config: &ImportConfig,
     ) -> Result<Repository, AppError> {
-        let client = get_client(pool.clone()).await.unwrap();
-
         let repo_name: String = match name_of_git_repository(&data.url) {
             Some(path) => path,
             None => {
@@ -153,25 +272,16 @@ impl Repository {
 
         // Search a repository that matches with that url, because if it's
         // exists, the server do not create a clone
-        let repo_search = Repository::search(&client, repo_name.clone()).await;
-        match repo_search {
-            Ok(_) => {
-                return Err(AppError {
-                    message: Some("Repository already exists".to_string()),
-                    cause: Some("".to_string()),
-                    error_type: AppErrorType::AuthorizationError,
-                });
-            }
-            Err(_) => {}
-        };
-
-        let statement = client
-            .prepare("
-                INSERT INTO repository(id, url, uploader_ip) VALUES($1, $2, $3) RETURNING *
-            ").await?;
+        if storage.find_repository_by_url(&repo_name).await.is_ok() {
+            return Err(AppError {
+                message: Some("Repository already exists".to_string()),
+                cause: Some("".to_string()),
+                error_type: AppErrorType::AuthorizationError,
+            });
+        }
 
-        // Create a new UUID v4
-        let uuid = Uuid::new_v4();
+        let credential =
+            Repository::git_credential(storage, &data.credential_id).await?;
 
         // Match the uploader ip
         let user_ip = match uploader_ip {
@@ -185,74 +295,282 @@ impl Repository {
             }
         };
 
-        let repo = client
-            .query_opt(&statement, &[&uuid, &repo_name, &user_ip])
-            .await?
-            .map(|row| Repository::from_row_ref(&row).unwrap());
+        // Create a new UUID v4
+        let id = Uuid::new_v4();
+        let webhook_secret = match &data.webhook_secret {
+            Some(secret) => Some(encrypt(&id, secret.as_bytes())?),
+            None => None,
+        };
 
-        match repo {
-            Some(repo) => {
-                let commits = match git::repo_commits(&repo_name, &data.branch)
-                {
-                    Ok(c) => c,
-                    Err(e) => {
-                        // It also need to remove the repository from the db
-                        let _ =
-                            Repository::delete(pool.clone(), &repo.id).await;
-                        return Err(AppError {
-                            message: Some(
-                                format!(
-                                    "Repository couldn't be created now: {:?}",
-                                    e
-                                )
-                                .to_string(),
-                            ),
-                            cause: Some("Repository clone".to_string()),
-                            error_type: AppErrorType::GitError,
-                        });
-                    }
-                };
-
-                let mut emails: HashSet<String> = HashSet::new();
-                for commit in &commits {
-                    emails.insert(commit.author_email.clone());
-                    emails.insert(commit.committer_email.clone());
-                }
-                for email in emails {
-                    if let Err(e) =
-                        Email::create(pool.clone(), &EmailData { email }).await
-                    {
-                        if e.error_type == AppErrorType::DbError {
-                            return Err(e);
-                        }
-                    }
-                }
-
-                let commits_result =
-                    Commit::create(pool.clone(), commits).await;
-
-                match commits_result {
-                    Ok(commits_res_vec) => {
-                        let branch_data = BranchData {
-                            name: data.branch.clone(),
-                            repository_id: repo.id,
-                            head: commits_res_vec[0].hash.clone(),
-                        };
-                        let _ =
-                            Branch::create(pool.clone(), &branch_data).await;
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
-                };
+        let now = Utc::now().naive_utc();
+        let repo = Repository {
+            id,
+            url: repo_name.clone(),
+            created_at: now,
+            updated_at: now,
+            uploader_ip: user_ip,
+            webhook_secret,
+            credential_id: data.credential_id,
+        };
 
-                Ok(repo)
+        // When it returns early the transaction is rolled back
+        let repository_id = repo.id;
+        let mut transaction = ImportTransaction::new(storage, Some(repo));
+
+        let limits = ImportLimits {
+            since: data.since,
+            depth: data.depth,
+            ..ImportLimits::from_config(config)
+        };
+        let (walk, _) = Repository::import(
+            &mut transaction,
+            &repo_name,
+            &data.branch,
+            None,
+            vec![],
+            credential,
+            limits,
+        )
+        .await?;
+        if walk.commits == 0 {
+            return Err(AppError {
+                message: Some(
+                    "Repository has no commits to import".to_string(),
+                ),
+                cause: Some("Repository clone".to_string()),
+                error_type: AppErrorType::GitError,
+            });
+        }
+
+        let branch_data = BranchData {
+            name: data.branch.clone(),
+            repository_id,
+            head: walk.head,
+        };
+        transaction.get().await?.create_branch(&branch_data).await?;
+
+        let repo = transaction.commit().await?;
+
+        Ok(repo.unwrap())
+    }
+
+    /// Import the commits pushed on `branch` after its current head, then
+    /// move the head forward, in a single transaction. The commits imported
+    /// before their merges and lines were read are completed too. Returns
+    /// the number of new commits
+    pub async fn sync(
+        storage: &dyn Storage,
+        branch: &Branch,
+        limits: ImportLimits,
+    ) -> Result<usize, AppError> {
+        let repo = storage.find_repository(&branch.repository_id).await?;
+
+        let credential =
+            Repository::git_credential(storage, &repo.credential_id).await?;
+        let incomplete = storage.find_incomplete_commits(&repo.url).await?;
+
+        let mut transaction = ImportTransaction::new(storage, None);
+
+        let (walk, imported) = Repository::import(
+            &mut transaction,
+            &repo.url,
+            &branch.name,
+            Some(&branch.head),
+            incomplete,
+            credential,
+            limits,
+        )
+        .await?;
+
+        // After a force push the head changes even without new commits
+        let saving = transaction.get().await?;
+        saving.update_branch_head(&branch.id, &walk.head).await?;
+        saving.touch_repository(&repo.id).await?;
+
+        transaction.commit().await?;
+
+        Ok(imported)
+    }
+
+    /// Returns the decrypted credential with id `credential_id`, if any
+    async fn git_credential(
+        storage: &dyn Storage,
+        credential_id: &Option<Uuid>,
+    ) -> Result<Option<GitCredential>, AppError> {
+        match credential_id {
+            Some(id) => {
+                let credential = storage.find_credential(id).await?;
+                Ok(Some(credential.git_credential()?))
             }
-            None => Err(AppError {
-                message: Some("Error creating a new repository".to_string()),
-                cause: Some("Unknown error".to_string()),
-                error_type: AppErrorType::DbError,
-            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Import the history of `branch` after `last_head`. The repository is
+    /// cloned in a thread, which is abandoned when it exceeds the timeout,
+    /// since a stalled transfer never stops by itself. Then the history is
+    /// walked in a thread, which sends the commits in batches while they are
+    /// saved, so they are never all in memory, followed by the `incomplete`
+    /// commits.
+    /// Returns the walk and the number of new commits
+    async fn import(
+        transaction: &mut ImportTransaction<'_>,
+        repo_name: &str,
+        branch: &str,
+        last_head: Option<&String>,
+        incomplete: Vec<String>,
+        credential: Option<GitCredential>,
+        limits: ImportLimits,
+    ) -> Result<(Walk, usize), AppError> {
+        let started = Instant::now();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let clone = {
+            let repo_name = repo_name.to_string();
+            let limits = limits.clone();
+            let cancelled = cancelled.clone();
+            web::block(move || {
+                git::clone_repo(
+                    &repo_name,
+                    credential.as_ref(),
+                    &limits,
+                    started,
+                    &cancelled,
+                )
+            })
+        };
+        let repo = match timeout(limits.timeout, clone).await {
+            Ok(repo) => repo.map_err(import_error)?,
+            Err(_) => {
+                cancelled.store(true, Ordering::Relaxed);
+                return Err(import_error(BlockingError::Error(
+                    limits.timeout_error(),
+                )));
+            }
+        };
+
+        // A batch is saved while the next one is read
+        let (sender, receiver) = mpsc::channel::<Vec<Commit>>(1);
+
+        let repo_name = repo_name.to_string();
+        let branch = branch.to_string();
+        let last_head = last_head.cloned();
+        let walk = web::block(move || {
+            let input = WalkInput {
+                last_head: last_head.as_ref(),
+                incomplete: &incomplete,
+                limits: &limits,
+                started,
+            };
+            git::repo_commits(repo, &repo_name, &branch, input, sender)
+        });
+
+        // When saving fails the receiver is dropped, so the walk stops too
+        let (walk, imported) =
+            join(walk, Repository::import_commits(transaction, receiver))
+                .await;
+        let imported = imported?;
+
+        Ok((walk.map_err(import_error)?, imported))
+    }
+
+    /// Save the batches of commits received, with their authors and
+    /// committers emails. The erased emails are replaced by their pseudonyms
+    /// first. Returns the number of new commits
+    async fn import_commits(
+        transaction: &mut ImportTransaction<'_>,
+        mut receiver: Receiver<Vec<Commit>>,
+    ) -> Result<usize, AppError> {
+        let mut imported: usize = 0;
+        while let Some(mut commits) = receiver.recv().await {
+            let transaction = transaction.get().await?;
+
+            let mut found: HashSet<String> = HashSet::new();
+            for commit in &commits {
+                found.insert(commit.author_email.clone());
+                found.insert(commit.committer_email.clone());
+                found.extend(message_emails(&commit.text));
+            }
+            let pseudonyms = transaction
+                .find_pseudonyms(found.into_iter().collect())
+                .await?;
+            pseudonymize(&mut commits, &pseudonyms);
+
+            let mut emails: HashSet<String> = HashSet::new();
+            for commit in &commits {
+                emails.insert(commit.author_email.clone());
+                emails.insert(commit.committer_email.clone());
+            }
+            transaction
+                .create_emails(emails.into_iter().collect())
+                .await?;
+
+            imported += transaction.create_commits(commits).await?;
         }
+
+        Ok(imported)
+    }
+}
+
+/// Transaction of an import, started when it is used the first time: the
+/// storage is not kept busy while the repository is cloned
+struct ImportTransaction<'a> {
+    storage: &'a dyn Storage,
+    /// Repository saved when the transaction starts, then the saved one
+    repository: Option<Repository>,
+    transaction: Option<Box<dyn StorageTransaction>>,
+}
+
+impl<'a> ImportTransaction<'a> {
+    fn new(
+        storage: &'a dyn Storage,
+        repository: Option<Repository>,
+    ) -> ImportTransaction<'a> {
+        ImportTransaction {
+            storage,
+            repository,
+            transaction: None,
+        }
+    }
+
+    /// Returns the transaction, starting it when it is not started yet
+    async fn get(&mut self) -> Result<&mut dyn StorageTransaction, AppError> {
+        if self.transaction.is_none() {
+            let mut transaction = self.storage.begin().await?;
+            if let Some(repository) = &self.repository {
+                self.repository =
+                    Some(transaction.create_repository(repository).await?);
+            }
+            self.transaction = Some(transaction);
+        }
+
+        Ok(self.transaction.as_mut().unwrap().as_mut())
+    }
+
+    /// Commit the transaction. Returns the saved repository, if any
+    async fn commit(mut self) -> Result<Option<Repository>, AppError> {
+        self.get().await?;
+        self.transaction.take().unwrap().commit().await?;
+
+        Ok(self.repository)
+    }
+}
+
+/// Error of a clone or of a walk of the history
+fn import_error(error: BlockingError<git2::Error>) -> AppError {
+    match error {
+        BlockingError::Error(e) => AppError {
+            message: Some(format!(
+                "Repository couldn't be imported now: {}",
+                e.message()
+            )),
+            cause: Some("Repository clone".to_string()),
+            error_type: AppErrorType::GitError,
+        },
+        BlockingError::Canceled => AppError {
+            message: Some("Repository couldn't be imported now".to_string()),
+            cause: Some("Repository clone canceled".to_string()),
+            error_type: AppErrorType::GitError,
+        },
     }
 }
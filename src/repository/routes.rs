@@ -1,21 +1,27 @@
 use crate::config::AppState;
-use crate::errors::{AppError, AppErrorResponse, AppErrorType};
-use crate::helpers::uuid_from_string;
+use crate::errors::AppErrorResponse;
+use crate::helpers::{check_authorization, uuid_from_string};
+use crate::pagination::Pagination;
 use crate::repository::models::{Repository, RepositoryData};
-use actix_web::http::header;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use slog::info;
-use std::env;
 use uuid::Uuid;
 
-/// Endpoint used for retrieve all repositories
-async fn index(state: web::Data<AppState>) -> impl Responder {
-    let result = Repository::find_all(state.pool.clone()).await;
+/// Endpoint used for retrieve a page of repositories
+async fn index(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let pagination = match Pagination::from_request(&req) {
+        Ok(pagination) => pagination,
+        Err(e) => return HttpResponse::from_error(e.into()),
+    };
+    let result = state.storage.find_repositories(&pagination).await;
     info!(state.log, "GET /repo/");
 
     // If raises an `Err`, returns an error in JSON format
     match result {
-        Ok(repos) => HttpResponse::Ok().json(repos),
+        Ok(repos) => HttpResponse::Ok().json(repos.link(&req)),
         _ => HttpResponse::BadRequest().json(AppErrorResponse {
             detail: "Error trying to read all repositories from database"
                 .to_string(),
@@ -34,7 +40,7 @@ async fn get_repo(
     // If I pass a not valid Uuid to Repository::find() it raises an error.
     let uuid: Uuid = uuid_from_string(&id);
 
-    let result = Repository::find(state.pool.clone(), &uuid).await;
+    let result = state.storage.find_repository(&uuid).await;
     info!(state.log, "GET /repo/{}/", id);
 
     // `map_err` is also used when repo is not found
@@ -51,46 +57,36 @@ async fn delete_repo(
     id: web::Path<String>,
 ) -> impl Responder {
     let uuid: Uuid = uuid_from_string(&id);
-    match req.headers().get(header::AUTHORIZATION) {
-        Some(x)
-            if x.to_str().unwrap()
-                != env::var("SECRET_KEY").unwrap_or("".to_string()) =>
-        {
-            info!(state.log, "DELETE /repo/{}/ 401", id.0);
-            return Err(AppError {
-                error_type: AppErrorType::AuthorizationError,
-                message: Some(
-                    "You must provide a valid Authorization".to_string(),
-                ),
-                cause: None,
-            });
-        }
-        Some(_) => {}
-        None => {
-            info!(state.log, "DELETE /repo/{}/ 400", id.0);
-            return Ok(HttpResponse::BadRequest().body(""));
-        }
-    };
+    if let Err(e) = check_authorization(&req) {
+        info!(state.log, "DELETE /repo/{}/ 401", id.0);
+        return Err(e);
+    }
 
-    let result = Repository::delete(state.pool.clone(), &uuid).await;
+    let result = state.storage.delete_repository(&uuid).await;
     info!(state.log, "DELETE /repo/{}/", id.0);
 
-    result
-        .map(|_| HttpResponse::NoContent().body(""))
-        .map_err(|e| e)
+    result.map(|_| HttpResponse::NoContent().body(""))
 }
 
-/// Endpoint used for create new repository
+/// Endpoint used for create new repository.
+/// A private repository, imported with a credential, needs the SECRET_KEY
 async fn create_repo(
     req: HttpRequest,
     payload: web::Json<RepositoryData>,
     state: web::Data<AppState>,
 ) -> impl Responder {
     info!(state.log, "POST /repo/");
+    if payload.credential_id.is_some() {
+        check_authorization(&req)?;
+    }
     let request_from_ip = HttpRequest::peer_addr(&req);
-    let result =
-        Repository::create(state.pool.clone(), &payload, request_from_ip)
-            .await;
+    let result = Repository::create(
+        state.storage.as_ref(),
+        &payload,
+        request_from_ip,
+        &state.import,
+    )
+    .await;
 
     result
         .map(|repo| HttpResponse::Created().json(repo))
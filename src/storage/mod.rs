@@ -0,0 +1,327 @@
+pub mod postgres;
+pub mod sqlite;
+mod tls;
+
+use crate::branch::models::{Branch, BranchData};
+use crate::commit::models::{
+    ActivityFilters, Commit, CommitActivity, CommitFilters, CommitNumAuthor,
+    CommitSearchResult, TopFilters,
+};
+use crate::config::Config;
+use crate::contributor::models::{
+    Contributor, ContributorData, ContributorSuggestion, MergeData, SplitData,
+};
+use crate::credential::models::Credential;
+use crate::email::models::{
+    Bots, Email, EmailName, EmailSearchResult, Profile,
+};
+use crate::erasure::models::Erasure;
+use crate::errors::{AppError, AppErrorType};
+use crate::organization::models::{
+    Affiliation, OrganizationCommits, OrganizationDomain, OrganizationFilters,
+};
+use crate::pagination::{Page, Pagination};
+use crate::repository::models::Repository;
+
+use async_trait::async_trait;
+use slog::Logger;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Storage shared by the workers of the server
+pub type SharedStorage = Arc<dyn Storage + Send + Sync>;
+
+/// Operations on the saved repositories, branches, commits, emails,
+/// contributors, organizations, erasures and credentials. Every backend
+/// implements them with its own SQL
+#[async_trait(?Send)]
+pub trait Storage {
+    /// Find a page of repositories, the last created first
+    async fn find_repositories(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Repository>, AppError>;
+    async fn find_repository(&self, id: &Uuid)
+        -> Result<Repository, AppError>;
+    async fn find_repository_by_url(
+        &self,
+        url: &str,
+    ) -> Result<Repository, AppError>;
+    /// Delete a repository with its branches and commits, counting again
+    /// the names of their emails
+    async fn delete_repository(
+        &self,
+        id: &Uuid,
+    ) -> Result<Repository, AppError>;
+
+    /// Find a page of branches, ordered by name
+    async fn find_branches(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError>;
+    async fn find_branches_of_repository(
+        &self,
+        repository_id: &Uuid,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError>;
+    async fn find_branch(&self, id: &Uuid) -> Result<Branch, AppError>;
+    async fn find_branch_by_name(
+        &self,
+        repository_id: &Uuid,
+        name: &str,
+    ) -> Result<Branch, AppError>;
+    async fn delete_branch(&self, id: &Uuid) -> Result<Branch, AppError>;
+
+    /// Find a page of the commits which match all the `filters`, the newest
+    /// first
+    async fn find_commits(
+        &self,
+        filters: &CommitFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<Commit>, AppError>;
+    /// Full-text search of the commit messages, the best ranked first
+    async fn search_commits(
+        &self,
+        query: &str,
+        repository_url: Option<String>,
+        author_email: Option<String>,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitSearchResult>, AppError>;
+    async fn find_commit(&self, hash: &str) -> Result<Commit, AppError>;
+    /// Hashes of the commits of a repository imported before their merges
+    /// and lines were read, which its next sync completes
+    async fn find_incomplete_commits(
+        &self,
+        repository_url: &str,
+    ) -> Result<Vec<String>, AppError>;
+    async fn delete_commit(&self, hash: &str) -> Result<Commit, AppError>;
+    /// Find a page of the number of commits in each period, the oldest
+    /// first, including the periods without commits
+    async fn commit_activity(
+        &self,
+        filters: &ActivityFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitActivity>, AppError>;
+    /// Rank the authors of the commits which match `filters`
+    async fn most_authors(
+        &self,
+        filters: &TopFilters,
+        exclude_bots: bool,
+    ) -> Result<Vec<CommitNumAuthor>, AppError>;
+
+    /// Find a page of emails, ordered by email, or by hash when `by_hash`
+    /// so that the cursors do not contain the addresses
+    async fn find_emails(
+        &self,
+        pagination: &Pagination,
+        by_hash: bool,
+        exclude_bots: bool,
+    ) -> Result<Page<Email>, AppError>;
+    /// Find a page of the emails which match a search, the best ranked
+    /// first. See `EmailQuery`
+    async fn search_emails(
+        &self,
+        query: &str,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<EmailSearchResult>, AppError>;
+    /// Find the email whose MD5 hash is `hash`
+    async fn find_email_by_hash(&self, hash: &str) -> Result<Email, AppError>;
+    /// Find the names used with an email, the oldest first
+    async fn find_email_names(
+        &self,
+        email: &str,
+    ) -> Result<Vec<EmailName>, AppError>;
+    /// Build the profile of the author of the email whose hash is `hash`
+    async fn find_profile(&self, hash: &str) -> Result<Profile, AppError>;
+    /// Create an email, or returns the saved one. The boolean is true when
+    /// the email is new
+    async fn create_email(
+        &self,
+        email: &str,
+    ) -> Result<(Email, bool), AppError>;
+
+    /// Find a page of credentials, the newest first
+    async fn find_credentials(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Credential>, AppError>;
+    async fn find_credential(&self, id: &Uuid)
+        -> Result<Credential, AppError>;
+    /// Save a credential, whose secret is already encrypted
+    async fn create_credential(
+        &self,
+        credential: &Credential,
+    ) -> Result<Credential, AppError>;
+    async fn delete_credential(
+        &self,
+        id: &Uuid,
+    ) -> Result<Credential, AppError>;
+
+    /// Find a page of contributors, ordered by name. With `exclude_bots`,
+    /// the contributors whose emails are all bots are skipped
+    async fn find_contributors(
+        &self,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<Contributor>, AppError>;
+    async fn find_contributor(
+        &self,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError>;
+    /// Create a contributor, taking its emails from their contributors
+    async fn create_contributor(
+        &self,
+        data: &ContributorData,
+    ) -> Result<Contributor, AppError>;
+    /// Move emails, and the emails of other contributors, to a contributor.
+    /// The contributors left without emails are deleted
+    async fn merge_contributors(
+        &self,
+        id: &Uuid,
+        data: &MergeData,
+    ) -> Result<Contributor, AppError>;
+    /// Remove emails from a contributor, which is deleted when it is left
+    /// without emails
+    async fn split_contributor(
+        &self,
+        id: &Uuid,
+        data: &SplitData,
+    ) -> Result<Contributor, AppError>;
+    async fn delete_contributor(
+        &self,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError>;
+    /// Find a page of emails to merge into a contributor, ordered by name
+    async fn contributor_suggestions(
+        &self,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<ContributorSuggestion>, AppError>;
+
+    /// Find a page of organization domains, ordered by domain
+    async fn find_organization_domains(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationDomain>, AppError>;
+    /// Save the organization of a domain, replacing the previous one
+    async fn save_organization_domain(
+        &self,
+        domain: &OrganizationDomain,
+    ) -> Result<OrganizationDomain, AppError>;
+    async fn delete_organization_domain(
+        &self,
+        domain: &str,
+    ) -> Result<OrganizationDomain, AppError>;
+    /// Find a page of affiliations, ordered by email
+    async fn find_affiliations(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Affiliation>, AppError>;
+    async fn create_affiliation(
+        &self,
+        affiliation: &Affiliation,
+    ) -> Result<Affiliation, AppError>;
+    async fn delete_affiliation(
+        &self,
+        id: &Uuid,
+    ) -> Result<Affiliation, AppError>;
+    /// Find a page of the number of commits of each organization in each
+    /// repository and period, the newest period first
+    async fn organization_commits(
+        &self,
+        filters: &OrganizationFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationCommits>, AppError>;
+    /// Find a page of erasures, the newest first
+    async fn find_erasures(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Erasure>, AppError>;
+    /// Replace an email by a pseudonym in the commits, their messages and
+    /// the affiliations, then delete it. The erasure is recorded, so it keeps
+    /// its pseudonym and it is applied to the next imports
+    async fn erase_email(&self, email: &str) -> Result<Erasure, AppError>;
+
+    /// Start a transaction, used to save an import
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError>;
+}
+
+/// Writes of an import. They are discarded when the transaction is dropped
+/// without calling `commit`
+#[async_trait(?Send)]
+pub trait StorageTransaction {
+    async fn create_repository(
+        &mut self,
+        repository: &Repository,
+    ) -> Result<Repository, AppError>;
+    /// Move `updated_at` of a repository to now
+    async fn touch_repository(&mut self, id: &Uuid) -> Result<(), AppError>;
+    /// Pseudonyms of the erased emails among `emails`, by email
+    async fn find_pseudonyms(
+        &mut self,
+        emails: Vec<String>,
+    ) -> Result<HashMap<String, String>, AppError>;
+    /// Save the emails which do not exist yet, flagging the bots
+    async fn create_emails(
+        &mut self,
+        emails: Vec<String>,
+    ) -> Result<(), AppError>;
+    /// Save the commits which do not exist yet, and complete the merges and
+    /// lines of the incomplete ones. Returns how many are new
+    async fn create_commits(
+        &mut self,
+        commits: Vec<Commit>,
+    ) -> Result<usize, AppError>;
+    async fn create_branch(
+        &mut self,
+        data: &BranchData,
+    ) -> Result<Branch, AppError>;
+    async fn update_branch_head(
+        &mut self,
+        id: &Uuid,
+        head: &str,
+    ) -> Result<Branch, AppError>;
+    async fn commit(self: Box<Self>) -> Result<(), AppError>;
+}
+
+/// Open the storage selected by the configuration, apply its migrations and
+/// flag the bots again, since their patterns may have changed
+pub async fn connect(
+    config: &Config,
+    log: &Logger,
+) -> Result<SharedStorage, AppError> {
+    let bots = Bots::new(&config.bots)?;
+
+    match config.storage.backend.as_str() {
+        "postgres" => {
+            let storage = postgres::PostgresStorage::new(
+                &config.pg,
+                &config.pg_tls,
+                bots,
+            )?;
+            storage.migrate(log).await?;
+            storage.flag_bots(log).await?;
+            Ok(Arc::new(storage))
+        }
+        "sqlite" => {
+            let storage =
+                sqlite::SqliteStorage::open(&config.storage.path, bots)?;
+            storage.migrate(log).await?;
+            storage.flag_bots(log).await?;
+            Ok(Arc::new(storage))
+        }
+        backend => Err(AppError {
+            message: Some(format!(
+                "Unknown storage backend \"{}\": use \"postgres\" or \
+                \"sqlite\"",
+                backend
+            )),
+            cause: None,
+            error_type: AppErrorType::ConfigError,
+        }),
+    }
+}
@@ -0,0 +1,499 @@
+use super::tls;
+use super::{Storage, StorageTransaction};
+use crate::branch::models::{Branch, BranchData};
+use crate::commit::models::{
+    ActivityFilters, Commit, CommitActivity, CommitFilters, CommitNumAuthor,
+    CommitSearchResult, TopFilters,
+};
+use crate::config::{PgTlsConfig, TlsMode};
+use crate::contributor::models::{
+    Contributor, ContributorData, ContributorSuggestion, MergeData, SplitData,
+};
+use crate::credential::models::Credential;
+use crate::db::get_client;
+use crate::email::models::{
+    Bots, Email, EmailName, EmailSearchResult, Profile,
+};
+use crate::erasure::models::Erasure;
+use crate::errors::{AppError, AppErrorType};
+use crate::migrations;
+use crate::organization::models::{
+    Affiliation, OrganizationCommits, OrganizationDomain, OrganizationFilters,
+};
+use crate::pagination::{Page, Pagination};
+use crate::repository::models::Repository;
+
+use async_trait::async_trait;
+use deadpool_postgres::config::ConfigError;
+use deadpool_postgres::{Client, Config, Manager, Pool};
+use slog::{info, Logger};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::NoTls;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+
+/// Storage on a PostgreSQL database. The queries are inside the models
+pub struct PostgresStorage {
+    pool: Pool,
+    bots: Bots,
+}
+
+impl PostgresStorage {
+    /// Make the pool of connections, using TLS as configured by `tls`.
+    /// Connections are opened when used
+    pub fn new(
+        config: &Config,
+        tls: &PgTlsConfig,
+        bots: Bots,
+    ) -> Result<PostgresStorage, AppError> {
+        let error = |e: ConfigError| AppError {
+            message: Some("Invalid PostgreSQL configuration".to_string()),
+            cause: Some(e.to_string()),
+            error_type: AppErrorType::ConfigError,
+        };
+
+        let pool = match tls.mode {
+            TlsMode::Disable => config.create_pool(NoTls).map_err(error)?,
+            mode => {
+                // deadpool ignores `ssl_mode` and, without `hosts`, it tries
+                // a Unix socket after `host`, whose error would hide the TLS
+                // error of `host`
+                let mut config = config.clone();
+                if config.hosts.is_none() {
+                    config.hosts = config.host.take().map(|host| vec![host]);
+                }
+                let mut pg_config = config.get_pg_config().map_err(error)?;
+                pg_config.ssl_mode(if mode == TlsMode::Prefer {
+                    SslMode::Prefer
+                } else {
+                    SslMode::Require
+                });
+
+                let manager = Manager::from_config(
+                    pg_config,
+                    tls::connector(tls)?,
+                    config.get_manager_config(),
+                );
+                Pool::from_config(manager, config.get_pool_config())
+            }
+        };
+
+        Ok(PostgresStorage { pool, bots })
+    }
+
+    /// Apply the migrations not yet applied
+    pub async fn migrate(&self, log: &Logger) -> Result<(), AppError> {
+        migrations::run(self.pool.clone(), log).await
+    }
+
+    /// Flag every email again with the patterns of the bots
+    pub async fn flag_bots(&self, log: &Logger) -> Result<(), AppError> {
+        let changed = Email::flag_bots(self.pool.clone(), &self.bots).await?;
+        if changed > 0 {
+            info!(log, "Bot flag changed for {} emails", changed);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for PostgresStorage {
+    async fn find_repositories(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Repository>, AppError> {
+        Repository::find_all(self.pool.clone(), pagination).await
+    }
+
+    async fn find_repository(
+        &self,
+        id: &Uuid,
+    ) -> Result<Repository, AppError> {
+        Repository::find(self.pool.clone(), id).await
+    }
+
+    async fn find_repository_by_url(
+        &self,
+        url: &str,
+    ) -> Result<Repository, AppError> {
+        Repository::find_by_url(self.pool.clone(), url).await
+    }
+
+    async fn delete_repository(
+        &self,
+        id: &Uuid,
+    ) -> Result<Repository, AppError> {
+        Repository::delete(self.pool.clone(), id).await
+    }
+
+    async fn find_branches(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError> {
+        Branch::find_all(self.pool.clone(), pagination).await
+    }
+
+    async fn find_branches_of_repository(
+        &self,
+        repository_id: &Uuid,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError> {
+        Branch::find_by_repo(self.pool.clone(), repository_id, pagination)
+            .await
+    }
+
+    async fn find_branch(&self, id: &Uuid) -> Result<Branch, AppError> {
+        Branch::find(self.pool.clone(), id).await
+    }
+
+    async fn find_branch_by_name(
+        &self,
+        repository_id: &Uuid,
+        name: &str,
+    ) -> Result<Branch, AppError> {
+        Branch::find_by_name(self.pool.clone(), repository_id, name).await
+    }
+
+    async fn delete_branch(&self, id: &Uuid) -> Result<Branch, AppError> {
+        Branch::delete(self.pool.clone(), id).await
+    }
+
+    async fn find_commits(
+        &self,
+        filters: &CommitFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<Commit>, AppError> {
+        Commit::find_all(self.pool.clone(), filters, pagination).await
+    }
+
+    async fn search_commits(
+        &self,
+        query: &str,
+        repository_url: Option<String>,
+        author_email: Option<String>,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitSearchResult>, AppError> {
+        Commit::search(
+            self.pool.clone(),
+            query,
+            repository_url,
+            author_email,
+            exclude_bots,
+            pagination,
+        )
+        .await
+    }
+
+    async fn find_commit(&self, hash: &str) -> Result<Commit, AppError> {
+        Commit::find(self.pool.clone(), hash).await
+    }
+
+    async fn delete_commit(&self, hash: &str) -> Result<Commit, AppError> {
+        Commit::delete(self.pool.clone(), hash).await
+    }
+
+    async fn find_incomplete_commits(
+        &self,
+        repository_url: &str,
+    ) -> Result<Vec<String>, AppError> {
+        Commit::find_incomplete(self.pool.clone(), repository_url).await
+    }
+
+    async fn commit_activity(
+        &self,
+        filters: &ActivityFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitActivity>, AppError> {
+        Commit::activity(self.pool.clone(), filters, pagination).await
+    }
+
+    async fn most_authors(
+        &self,
+        filters: &TopFilters,
+        exclude_bots: bool,
+    ) -> Result<Vec<CommitNumAuthor>, AppError> {
+        Commit::most_authors(self.pool.clone(), filters, exclude_bots).await
+    }
+
+    async fn find_emails(
+        &self,
+        pagination: &Pagination,
+        by_hash: bool,
+        exclude_bots: bool,
+    ) -> Result<Page<Email>, AppError> {
+        Email::find_all(self.pool.clone(), pagination, by_hash, exclude_bots)
+            .await
+    }
+
+    async fn search_emails(
+        &self,
+        query: &str,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<EmailSearchResult>, AppError> {
+        Email::search(self.pool.clone(), query, exclude_bots, pagination).await
+    }
+
+    async fn find_email_by_hash(&self, hash: &str) -> Result<Email, AppError> {
+        Email::find_by_hash(self.pool.clone(), hash).await
+    }
+
+    async fn find_email_names(
+        &self,
+        email: &str,
+    ) -> Result<Vec<EmailName>, AppError> {
+        Email::names(self.pool.clone(), email).await
+    }
+
+    async fn find_profile(&self, hash: &str) -> Result<Profile, AppError> {
+        Email::profile(self.pool.clone(), hash).await
+    }
+
+    async fn create_email(
+        &self,
+        email: &str,
+    ) -> Result<(Email, bool), AppError> {
+        Email::create(self.pool.clone(), email, &self.bots).await
+    }
+
+    async fn find_credentials(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Credential>, AppError> {
+        Credential::find_all(self.pool.clone(), pagination).await
+    }
+
+    async fn find_credential(
+        &self,
+        id: &Uuid,
+    ) -> Result<Credential, AppError> {
+        Credential::find(self.pool.clone(), id).await
+    }
+
+    async fn create_credential(
+        &self,
+        credential: &Credential,
+    ) -> Result<Credential, AppError> {
+        Credential::create(self.pool.clone(), credential).await
+    }
+
+    async fn delete_credential(
+        &self,
+        id: &Uuid,
+    ) -> Result<Credential, AppError> {
+        Credential::delete(self.pool.clone(), id).await
+    }
+
+    async fn find_contributors(
+        &self,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<Contributor>, AppError> {
+        Contributor::find_all(self.pool.clone(), pagination, exclude_bots)
+            .await
+    }
+
+    async fn find_contributor(
+        &self,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError> {
+        Contributor::find(self.pool.clone(), id).await
+    }
+
+    async fn create_contributor(
+        &self,
+        data: &ContributorData,
+    ) -> Result<Contributor, AppError> {
+        Contributor::create(self.pool.clone(), data).await
+    }
+
+    async fn merge_contributors(
+        &self,
+        id: &Uuid,
+        data: &MergeData,
+    ) -> Result<Contributor, AppError> {
+        Contributor::merge(self.pool.clone(), id, data).await
+    }
+
+    async fn split_contributor(
+        &self,
+        id: &Uuid,
+        data: &SplitData,
+    ) -> Result<Contributor, AppError> {
+        Contributor::split(self.pool.clone(), id, data).await
+    }
+
+    async fn delete_contributor(
+        &self,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError> {
+        Contributor::delete(self.pool.clone(), id).await
+    }
+
+    async fn contributor_suggestions(
+        &self,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<ContributorSuggestion>, AppError> {
+        Contributor::suggestions(self.pool.clone(), pagination, exclude_bots)
+            .await
+    }
+
+    async fn find_organization_domains(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationDomain>, AppError> {
+        OrganizationDomain::find_all(self.pool.clone(), pagination).await
+    }
+
+    async fn save_organization_domain(
+        &self,
+        domain: &OrganizationDomain,
+    ) -> Result<OrganizationDomain, AppError> {
+        OrganizationDomain::save(self.pool.clone(), domain).await
+    }
+
+    async fn delete_organization_domain(
+        &self,
+        domain: &str,
+    ) -> Result<OrganizationDomain, AppError> {
+        OrganizationDomain::delete(self.pool.clone(), domain).await
+    }
+
+    async fn find_affiliations(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Affiliation>, AppError> {
+        Affiliation::find_all(self.pool.clone(), pagination).await
+    }
+
+    async fn create_affiliation(
+        &self,
+        affiliation: &Affiliation,
+    ) -> Result<Affiliation, AppError> {
+        Affiliation::create(self.pool.clone(), affiliation).await
+    }
+
+    async fn delete_affiliation(
+        &self,
+        id: &Uuid,
+    ) -> Result<Affiliation, AppError> {
+        Affiliation::delete(self.pool.clone(), id).await
+    }
+
+    async fn organization_commits(
+        &self,
+        filters: &OrganizationFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationCommits>, AppError> {
+        OrganizationCommits::find_all(self.pool.clone(), filters, pagination)
+            .await
+    }
+
+    async fn find_erasures(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Erasure>, AppError> {
+        Erasure::find_all(self.pool.clone(), pagination).await
+    }
+
+    async fn erase_email(&self, email: &str) -> Result<Erasure, AppError> {
+        Erasure::erase(self.pool.clone(), email).await
+    }
+
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError> {
+        let client = get_client(self.pool.clone()).await?;
+        client.batch_execute("BEGIN").await?;
+
+        Ok(Box::new(PostgresTransaction {
+            client: Some(client),
+            bots: self.bots.clone(),
+        }))
+    }
+}
+
+/// Transaction on a connection taken from the pool. It owns the connection,
+/// so it can be kept across the awaits of an import
+pub struct PostgresTransaction {
+    /// Taken by `commit`
+    client: Option<Client>,
+    bots: Bots,
+}
+
+impl PostgresTransaction {
+    fn client(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageTransaction for PostgresTransaction {
+    async fn create_repository(
+        &mut self,
+        repository: &Repository,
+    ) -> Result<Repository, AppError> {
+        Repository::insert(self.client(), repository).await
+    }
+
+    async fn touch_repository(&mut self, id: &Uuid) -> Result<(), AppError> {
+        Repository::touch(self.client(), id).await
+    }
+
+    async fn find_pseudonyms(
+        &mut self,
+        emails: Vec<String>,
+    ) -> Result<HashMap<String, String>, AppError> {
+        Erasure::pseudonyms(self.client(), emails).await
+    }
+
+    async fn create_emails(
+        &mut self,
+        emails: Vec<String>,
+    ) -> Result<(), AppError> {
+        Email::create_all(self.client(), emails, &self.bots).await
+    }
+
+    async fn create_commits(
+        &mut self,
+        commits: Vec<Commit>,
+    ) -> Result<usize, AppError> {
+        Commit::create(self.client(), commits).await
+    }
+
+    async fn create_branch(
+        &mut self,
+        data: &BranchData,
+    ) -> Result<Branch, AppError> {
+        Branch::create(self.client(), data).await
+    }
+
+    async fn update_branch_head(
+        &mut self,
+        id: &Uuid,
+        head: &str,
+    ) -> Result<Branch, AppError> {
+        Branch::update_head(self.client(), id, head).await
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<(), AppError> {
+        let client = self.client.take().unwrap();
+        client.batch_execute("COMMIT").await?;
+
+        Ok(())
+    }
+}
+
+impl Drop for PostgresTransaction {
+    /// A transaction not committed is rolled back before its connection
+    /// goes back to the pool
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            actix_rt::spawn(async move {
+                let _ = client.batch_execute("ROLLBACK").await;
+            });
+        }
+    }
+}
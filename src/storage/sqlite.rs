@@ -0,0 +1,2369 @@
+use super::{Storage, StorageTransaction};
+use crate::branch::models::{Branch, BranchData};
+use crate::commit::models::{
+    like_pattern, search_terms, ActivityFilters, Commit, CommitActivity,
+    CommitFilters, CommitNumAuthor, CommitSearchResult, TopFilters, MATCH_END,
+    MATCH_START,
+};
+use crate::contributor::models::{
+    normalize_all, Contributor, ContributorData, ContributorSuggestion,
+    MergeData, SplitData,
+};
+use crate::credential::models::Credential;
+use crate::email::models::{
+    self, Bots, Email, EmailName, EmailQuery, EmailSearchResult, Profile,
+    ProfileRepository, PROFILE_COMMITS,
+};
+use crate::erasure::models::{digest, erase_text, pseudonym, Erasure};
+use crate::errors::{AppError, AppErrorType};
+use crate::helpers::{bad_request, similarity};
+use crate::migrations;
+use crate::organization::models::{
+    Affiliation, OrganizationCommits, OrganizationDomain, OrganizationFilters,
+    UNKNOWN,
+};
+use crate::pagination::{Key, Page, Pagination};
+use crate::repository::models::Repository;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ToSql;
+use rusqlite::{
+    params, Connection, OptionalExtension, Row, Transaction, NO_PARAMS,
+};
+use slog::{info, Logger};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Format of the timestamps saved as text, always in UTC
+const TIMESTAMP: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+/// Commits reachable from the head of the branch `?11`, following their first
+/// parent
+const BRANCH_HISTORY: &str = "
+    WITH RECURSIVE history(hash, tree) AS (
+        SELECT \"commit\".hash, \"commit\".tree FROM \"commit\"
+        JOIN branch ON branch.head = \"commit\".hash
+        WHERE branch.id = ?11
+        UNION ALL
+        SELECT \"commit\".hash, \"commit\".tree FROM \"commit\"
+        JOIN history ON \"commit\".hash = history.tree
+    )";
+
+/// Conditions of `CommitFilters`. A `NULL` parameter is a filter not sent.
+/// `LIKE` is already case insensitive
+const FILTERS: &str = "
+    (?1 IS NULL OR hash LIKE ?1 ESCAPE '\\')
+    AND (?2 IS NULL OR repository_url = ?2)
+    AND (?3 IS NULL OR author_email = ?3)
+    AND (?4 IS NULL OR author_name LIKE ?4 ESCAPE '\\')
+    AND (?5 IS NULL OR committer_email = ?5)
+    AND (?6 IS NULL OR committer_name LIKE ?6 ESCAPE '\\')
+    AND (?7 IS NULL OR date >= ?7)
+    AND (?8 IS NULL OR date <= ?8)
+    AND (?9 IS NULL OR is_merge = ?9)
+    AND (?10 IS NULL OR text LIKE ?10 ESCAPE '\\')
+    AND (?11 IS NULL OR hash IN (SELECT hash FROM history))
+    AND NOT (?12 AND author_email IN (SELECT email FROM email WHERE is_bot))";
+
+/// Contributors with their emails, as a JSON array
+const SELECT_CONTRIBUTOR: &str = "
+    SELECT contributor.*, (
+        SELECT json_group_array(email) FROM email
+        WHERE contributor_id = contributor.id
+    ) AS emails
+    FROM contributor";
+
+/// Condition of the contributors which have an email of a person, when `?1`
+/// is true
+const NOT_BOTS: &str = "
+    NOT (?1 AND NOT EXISTS (
+        SELECT 1 FROM email
+        WHERE contributor_id = contributor.id AND NOT is_bot
+    ))";
+
+/// Names shared by the emails of different contributors, where an email
+/// without a contributor is a contributor by itself. `?1` is true to skip the
+/// bots
+const SUGGESTIONS: &str = "
+    WITH names(name, email) AS (
+        SELECT DISTINCT lower(trim(author_name)), author_email FROM \"commit\"
+        UNION
+        SELECT username, email FROM email WHERE username IS NOT NULL
+    )
+    SELECT names.name, json_group_array(DISTINCT names.email) AS emails
+    FROM names
+    JOIN email ON email.email = names.email
+    WHERE names.name <> '' AND NOT (?1 AND email.is_bot)
+    GROUP BY names.name
+    HAVING COUNT(DISTINCT COALESCE(email.contributor_id, email.email)) > 1";
+
+/// Emails which match a search, with their rank. `?1` is the normalized
+/// query, `?2` the pattern of `EmailQuery`, `?3` true for a fuzzy search and
+/// `?4` true to skip the bots.
+/// 0.3 is the default threshold of the `%` operator of pg_trgm
+const EMAIL_SEARCH: &str = "
+    SELECT email.*, (
+        (email.email LIKE ?2 ESCAPE '\\')
+        + MAX(similarity(email.email, ?1), COALESCE(names.rank, 0))
+    ) AS rank
+    FROM email
+    LEFT JOIN (
+        SELECT author_email, MAX(similarity(author_name, ?1)) AS rank
+        FROM \"commit\"
+        WHERE ?3 AND similarity(author_name, ?1) >= 0.3
+        GROUP BY author_email
+    ) names ON names.author_email = email.email
+    WHERE NOT (?4 AND email.is_bot)
+        AND (email.email LIKE ?2 ESCAPE '\\'
+            OR (?3 AND (
+                similarity(email.email, ?1) >= 0.3
+                OR names.author_email IS NOT NULL
+            )))";
+
+/// Commits with the organization of their author, as `AFFILIATED_COMMITS` of
+/// the organization models. `?1` is the period, `?2` the repository url,
+/// `?3` and `?4` the dates and `?6` the bots filter of `OrganizationFilters`,
+/// `?5` the unknown organization. Weeks start on Monday
+const AFFILIATED_COMMITS: &str = "
+    SELECT repository_url,
+        CASE ?1
+            WHEN 'day' THEN date(date)
+            WHEN 'week' THEN date(date, 'weekday 0', '-6 days')
+            WHEN 'month' THEN strftime('%Y-%m-01', date)
+            ELSE strftime('%Y-01-01', date)
+        END AS period,
+        COALESCE(
+            (
+                SELECT organization FROM affiliation
+                WHERE affiliation.email = \"commit\".author_email
+                    AND (since IS NULL OR since <= date(\"commit\".date))
+                    AND (until IS NULL OR until > date(\"commit\".date))
+                ORDER BY since IS NULL, since DESC
+                LIMIT 1
+            ),
+            (
+                SELECT organization FROM organization_domain
+                WHERE substr(author_email, instr(author_email, '@') + 1)
+                    = domain
+                    OR author_email LIKE '%@%.' || domain
+                ORDER BY length(domain) DESC
+                LIMIT 1
+            ),
+            ?5
+        ) AS organization
+    FROM \"commit\"
+    WHERE (?2 IS NULL OR repository_url = ?2)
+        AND (?3 IS NULL OR date >= ?3)
+        AND (?4 IS NULL OR date <= ?4)
+        AND NOT (?6 AND author_email IN (
+            SELECT email FROM email WHERE is_bot
+        ))";
+
+/// Storage on a SQLite file, useful to run the server without a database
+/// server. The queries of a connection are serialized, so the imports have
+/// their own connection: with WAL the requests can still read while an
+/// import writes
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+    /// Connection of the import transactions
+    writer: Arc<Mutex<Connection>>,
+    bots: Bots,
+}
+
+impl SqliteStorage {
+    /// Open the database at `path`, creating it when it does not exist
+    pub fn open(path: &str, bots: Bots) -> Result<SqliteStorage, AppError> {
+        let connection = connect(path)?;
+        let writer = connect(path)?;
+
+        Ok(SqliteStorage {
+            connection: Arc::new(Mutex::new(connection)),
+            writer: Arc::new(Mutex::new(writer)),
+            bots,
+        })
+    }
+
+    /// Apply the migrations not yet applied
+    pub async fn migrate(&self, log: &Logger) -> Result<(), AppError> {
+        let mut connection = self.connection.lock().await;
+        migrations::run_sqlite(&mut connection, log)
+    }
+
+    /// Flag every email again with the patterns of the bots
+    pub async fn flag_bots(&self, log: &Logger) -> Result<(), AppError> {
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+
+        let flags = transaction
+            .prepare("SELECT email, is_bot FROM email")?
+            .query_map(NO_PARAMS, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+            })?
+            .collect::<Result<Vec<(String, bool)>, _>>()?;
+
+        let mut changed = 0;
+        for (address, is_bot) in flags {
+            if self.bots.is_bot(&address) != is_bot {
+                changed += transaction.execute(
+                    "UPDATE email SET is_bot = NOT is_bot WHERE email = ?1",
+                    params![address],
+                )?;
+            }
+        }
+        transaction.commit()?;
+
+        if changed > 0 {
+            info!(log, "Bot flag changed for {} emails", changed);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for SqliteStorage {
+    async fn find_repositories(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Repository>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Timestamp, Key::Uuid])?;
+        let created_at = cursor_timestamp(&after[0])?;
+
+        let repos = connection
+            .prepare_cached(
+                "
+                SELECT * FROM repository
+                WHERE ?1 IS NULL OR (created_at, id) < (?1, ?2)
+                ORDER BY created_at DESC, id DESC
+                LIMIT ?3
+                ",
+            )?
+            .query_map(
+                params![created_at, after[1], pagination.fetch()],
+                repository,
+            )?
+            .collect::<Result<Vec<Repository>, _>>()?;
+
+        let count = if pagination.count {
+            Some(count(&connection, "SELECT COUNT(*) FROM repository")?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(repos, pagination, count, |repo| {
+            vec![repo.created_at.to_string(), repo.id.to_string()]
+        }))
+    }
+
+    async fn find_repository(
+        &self,
+        id: &Uuid,
+    ) -> Result<Repository, AppError> {
+        let connection = self.connection.lock().await;
+        let repo = connection
+            .prepare_cached("SELECT * FROM repository WHERE id = ?1")?
+            .query_row(params![id.to_string()], repository)
+            .optional()?;
+
+        repo.ok_or_else(|| not_found("Repository not found"))
+    }
+
+    async fn find_repository_by_url(
+        &self,
+        url: &str,
+    ) -> Result<Repository, AppError> {
+        let connection = self.connection.lock().await;
+        let repo = connection
+            .prepare_cached("SELECT * FROM repository WHERE url = ?1")?
+            .query_row(params![url], repository)
+            .optional()?;
+
+        repo.ok_or_else(|| not_found("Repository not found"))
+    }
+
+    async fn delete_repository(
+        &self,
+        id: &Uuid,
+    ) -> Result<Repository, AppError> {
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+        let repo = transaction
+            .prepare_cached("SELECT * FROM repository WHERE id = ?1")?
+            .query_row(params![id.to_string()], repository)
+            .optional()?
+            .ok_or_else(|| not_found("Repository not found"))?;
+
+        let emails = transaction
+            .prepare_cached(
+                "
+                SELECT author_email FROM \"commit\" WHERE repository_url = ?1
+                UNION
+                SELECT committer_email FROM \"commit\"
+                WHERE repository_url = ?1
+                ",
+            )?
+            .query_map(params![repo.url], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        transaction.execute(
+            "DELETE FROM repository WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+        refresh_names(&transaction, &emails)?;
+        transaction.commit()?;
+
+        Ok(repo)
+    }
+
+    async fn find_branches(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+
+        let branches = connection
+            .prepare_cached(
+                "
+                SELECT * FROM branch
+                WHERE ?1 IS NULL OR (name, id) > (?1, ?2)
+                ORDER BY name, id
+                LIMIT ?3
+                ",
+            )?
+            .query_map(
+                params![after[0], after[1], pagination.fetch()],
+                branch,
+            )?
+            .collect::<Result<Vec<Branch>, _>>()?;
+
+        let count = if pagination.count {
+            Some(count(&connection, "SELECT COUNT(*) FROM branch")?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(branches, pagination, count, Branch::keys))
+    }
+
+    async fn find_branches_of_repository(
+        &self,
+        repository_id: &Uuid,
+        pagination: &Pagination,
+    ) -> Result<Page<Branch>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+        let repository_id = repository_id.to_string();
+
+        let branches = connection
+            .prepare_cached(
+                "
+                SELECT * FROM branch
+                WHERE repository_id = ?1
+                    AND (?2 IS NULL OR (name, id) > (?2, ?3))
+                ORDER BY name, id
+                LIMIT ?4
+                ",
+            )?
+            .query_map(
+                params![repository_id, after[0], after[1], pagination.fetch()],
+                branch,
+            )?
+            .collect::<Result<Vec<Branch>, _>>()?;
+
+        let count = if pagination.count {
+            Some(
+                connection
+                    .prepare_cached(
+                        "SELECT COUNT(*) FROM branch WHERE repository_id = ?1",
+                    )?
+                    .query_row(params![repository_id], |row| row.get(0))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(branches, pagination, count, Branch::keys))
+    }
+
+    async fn find_branch(&self, id: &Uuid) -> Result<Branch, AppError> {
+        let connection = self.connection.lock().await;
+        let branch = connection
+            .prepare_cached("SELECT * FROM branch WHERE id = ?1")?
+            .query_row(params![id.to_string()], branch)
+            .optional()?;
+
+        branch.ok_or_else(|| not_found("Branch not found"))
+    }
+
+    async fn find_branch_by_name(
+        &self,
+        repository_id: &Uuid,
+        name: &str,
+    ) -> Result<Branch, AppError> {
+        let connection = self.connection.lock().await;
+        let branch = connection
+            .prepare_cached(
+                "SELECT * FROM branch WHERE repository_id = ?1 AND name = ?2",
+            )?
+            .query_row(params![repository_id.to_string(), name], branch)
+            .optional()?;
+
+        branch.ok_or_else(|| not_found("Branch not found"))
+    }
+
+    async fn delete_branch(&self, id: &Uuid) -> Result<Branch, AppError> {
+        let connection = self.connection.lock().await;
+        let branch = connection
+            .prepare_cached("SELECT * FROM branch WHERE id = ?1")?
+            .query_row(params![id.to_string()], branch)
+            .optional()?
+            .ok_or_else(|| not_found("Branch not found"))?;
+
+        connection.execute(
+            "DELETE FROM branch WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+
+        Ok(branch)
+    }
+
+    async fn find_commits(
+        &self,
+        filters: &CommitFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<Commit>, AppError> {
+        let connection = self.connection.lock().await;
+
+        let hash = filters.hash.as_deref().map(like_pattern);
+        let author_name = filters.author_name.as_deref().map(like_pattern);
+        let committer_name =
+            filters.committer_name.as_deref().map(like_pattern);
+        let message = filters.message.as_deref().map(like_pattern);
+        let since = filters.since.map(|date| timestamp(&date.naive_utc()));
+        let until = filters.until.map(|date| timestamp(&date.naive_utc()));
+        let branch = filters.branch.map(|id| id.to_string());
+        let after = pagination.after(&[Key::Timestamp, Key::Text])?;
+        let date = cursor_timestamp(&after[0])?;
+
+        let params: [&dyn ToSql; 12] = [
+            &hash,
+            &filters.repository_url(),
+            &filters.author_email,
+            &author_name,
+            &filters.committer_email,
+            &committer_name,
+            &since,
+            &until,
+            &filters.merge,
+            &message,
+            &branch,
+            &filters.exclude_bots,
+        ];
+        let fetch = pagination.fetch();
+
+        let commits = connection
+            .prepare_cached(&format!(
+                "
+                {}
+                SELECT * FROM \"commit\"
+                WHERE {}
+                    AND (?13 IS NULL OR (date, hash) < (?13, ?14))
+                ORDER BY date DESC, hash DESC
+                LIMIT ?15
+                ",
+                BRANCH_HISTORY, FILTERS
+            ))?
+            .query_map(
+                [&params[..], &[&date, &after[1], &fetch]].concat(),
+                commit,
+            )?
+            .collect::<Result<Vec<Commit>, _>>()?;
+
+        let count = if pagination.count {
+            Some(
+                connection
+                    .prepare_cached(&format!(
+                        "{} SELECT COUNT(*) FROM \"commit\" WHERE {}",
+                        BRANCH_HISTORY, FILTERS
+                    ))?
+                    .query_row(&params, |row| row.get(0))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(commits, pagination, count, Commit::keys))
+    }
+
+    async fn search_commits(
+        &self,
+        query: &str,
+        repository_url: Option<String>,
+        author_email: Option<String>,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitSearchResult>, AppError> {
+        let query = match fts_query(query) {
+            Some(query) => query,
+            None => {
+                return Err(AppError {
+                    message: Some("Search query is empty".to_string()),
+                    cause: None,
+                    error_type: AppErrorType::BadRequestError,
+                });
+            }
+        };
+
+        let connection = self.connection.lock().await;
+        let after =
+            pagination.after(&[Key::Float, Key::Timestamp, Key::Text])?;
+        let rank = match &after[0] {
+            Some(rank) => Some(rank.parse::<f64>().map_err(|_| AppError {
+                message: Some("Invalid cursor".to_string()),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            })?),
+            None => None,
+        };
+        let date = cursor_timestamp(&after[1])?;
+
+        // bm25 is lower for better matches
+        let commits = connection
+            .prepare_cached(
+                "
+                SELECT * FROM (
+                    SELECT \"commit\".*,
+                        -bm25(commit_search) AS rank,
+                        snippet(commit_search, 0, ?9, ?10, '...', 32)
+                            AS snippet
+                    FROM commit_search
+                    JOIN \"commit\" ON \"commit\".id = commit_search.rowid
+                    WHERE commit_search MATCH ?1
+                        AND (?2 IS NULL OR repository_url = ?2)
+                        AND (?3 IS NULL OR author_email = ?3)
+                        AND NOT (?4 AND author_email IN (
+                            SELECT email FROM email WHERE is_bot
+                        ))
+                ) found
+                WHERE ?5 IS NULL OR (rank, date, hash) < (?5, ?6, ?7)
+                ORDER BY rank DESC, date DESC, hash DESC
+                LIMIT ?8
+                ",
+            )?
+            .query_map(
+                params![
+                    query,
+                    repository_url,
+                    author_email,
+                    exclude_bots,
+                    rank,
+                    date,
+                    after[2],
+                    pagination.fetch(),
+                    MATCH_START.to_string(),
+                    MATCH_END.to_string()
+                ],
+                |row| {
+                    Ok(CommitSearchResult {
+                        commit: commit(row)?,
+                        rank: row.get("rank")?,
+                        snippet: row.get("snippet")?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<CommitSearchResult>, _>>()?;
+
+        let count = if pagination.count {
+            Some(
+                connection
+                    .prepare_cached(
+                        "
+                        SELECT COUNT(*) FROM commit_search
+                        JOIN \"commit\" ON \"commit\".id = commit_search.rowid
+                        WHERE commit_search MATCH ?1
+                            AND (?2 IS NULL OR repository_url = ?2)
+                            AND (?3 IS NULL OR author_email = ?3)
+                            AND NOT (?4 AND author_email IN (
+                                SELECT email FROM email WHERE is_bot
+                            ))
+                        ",
+                    )?
+                    .query_row(
+                        params![
+                            query,
+                            repository_url,
+                            author_email,
+                            exclude_bots
+                        ],
+                        |row| row.get(0),
+                    )?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(commits, pagination, count, |result| {
+            let mut keys = vec![result.rank.to_string()];
+            keys.append(&mut result.commit.keys());
+            keys
+        }))
+    }
+
+    async fn find_commit(&self, hash: &str) -> Result<Commit, AppError> {
+        let connection = self.connection.lock().await;
+        let found = connection
+            .prepare_cached("SELECT * FROM \"commit\" WHERE hash = ?1")?
+            .query_row(params![hash], commit)
+            .optional()?;
+
+        found.ok_or_else(|| not_found("Commit not found"))
+    }
+
+    async fn find_incomplete_commits(
+        &self,
+        repository_url: &str,
+    ) -> Result<Vec<String>, AppError> {
+        let connection = self.connection.lock().await;
+        let hashes = connection
+            .prepare_cached(
+                "
+                SELECT hash FROM \"commit\"
+                WHERE repository_url = ?1 AND additions IS NULL
+                ",
+            )?
+            .query_map(params![repository_url], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(hashes)
+    }
+
+    async fn delete_commit(&self, hash: &str) -> Result<Commit, AppError> {
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+        let found = transaction
+            .prepare_cached("SELECT * FROM \"commit\" WHERE hash = ?1")?
+            .query_row(params![hash], commit)
+            .optional()?
+            .ok_or_else(|| not_found("Commit not found"))?;
+
+        // The commits which descend from it are deleted too
+        let emails = transaction
+            .prepare_cached(
+                "
+                WITH RECURSIVE deleted AS (
+                    SELECT hash, author_email, committer_email
+                    FROM \"commit\" WHERE hash = ?1
+                    UNION
+                    SELECT c.hash, c.author_email, c.committer_email
+                    FROM \"commit\" c JOIN deleted ON c.tree = deleted.hash
+                )
+                SELECT author_email FROM deleted
+                UNION
+                SELECT committer_email FROM deleted
+                ",
+            )?
+            .query_map(params![hash], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        transaction.execute(
+            "DELETE FROM \"commit\" WHERE hash = ?1",
+            params![hash],
+        )?;
+        refresh_names(&transaction, &emails)?;
+        transaction.commit()?;
+
+        Ok(found)
+    }
+
+    async fn commit_activity(
+        &self,
+        filters: &ActivityFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<CommitActivity>, AppError> {
+        // First day of the period of a local time, and the step to the next
+        let (start, step) = match filters.period()? {
+            "day" => ("", "+1 day"),
+            "week" => (", 'weekday 0', '-6 days'", "+7 days"),
+            "year" => (", 'start of year'", "+1 year"),
+            _ => (", 'start of month'", "+1 month"),
+        };
+        let timezone = filters.timezone()?;
+        let since = filters.since.map(|date| timestamp(&date.naive_utc()));
+        let until = filters.until.map(|date| timestamp(&date.naive_utc()));
+        let branch = filters.branch.map(|id| id.to_string());
+        let after = pagination.after(&[Key::Date])?;
+
+        // `?13` is the timezone and `?14` the step
+        let activity = format!(
+            "
+            {history}, counts(period, commits) AS (
+                SELECT date(local_time(date, ?13){start}), COUNT(*)
+                FROM \"commit\"
+                WHERE {filters}
+                GROUP BY 1
+            ), bounds(first, last) AS (
+                SELECT COALESCE(date(local_time(?7, ?13){start}), MIN(period)),
+                    COALESCE(date(local_time(?8, ?13){start}), MAX(period))
+                FROM counts
+            ), series(period) AS (
+                SELECT first FROM bounds WHERE first <= last
+                UNION ALL
+                SELECT date(period, ?14) FROM series, bounds
+                WHERE date(period, ?14) <= last
+            )
+            SELECT series.period AS period,
+                COALESCE(counts.commits, 0) AS commits
+            FROM series
+            LEFT JOIN counts ON counts.period = series.period
+            ",
+            history = BRANCH_HISTORY,
+            filters = FILTERS,
+            start = start
+        );
+
+        // Only the filters of a repository, an author, a period and a branch
+        // are sent
+        let none: Option<String> = None;
+        let params: [&dyn ToSql; 14] = [
+            &none,
+            &filters.repository_url(),
+            &filters.author_email,
+            &none,
+            &none,
+            &none,
+            &since,
+            &until,
+            &none,
+            &none,
+            &branch,
+            &filters.exclude_bots,
+            &timezone,
+            &step,
+        ];
+        let fetch = pagination.fetch();
+
+        let connection = self.connection.lock().await;
+        let periods = connection
+            .prepare_cached(&format!(
+                "
+                SELECT * FROM ({}) activity
+                WHERE ?15 IS NULL OR period > ?15
+                ORDER BY period
+                LIMIT ?16
+                ",
+                activity
+            ))?
+            .query_map([&params[..], &[&after[0], &fetch]].concat(), |row| {
+                Ok(CommitActivity {
+                    period: parse_date(&row.get::<_, String>(0)?)?,
+                    commits: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<CommitActivity>, _>>()?;
+
+        let count = if pagination.count {
+            Some(
+                connection
+                    .prepare_cached(&format!(
+                        "SELECT COUNT(*) FROM ({}) activity",
+                        activity
+                    ))?
+                    .query_row(&params, |row| row.get(0))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(periods, pagination, count, |activity| {
+            vec![activity.period.to_string()]
+        }))
+    }
+
+    async fn most_authors(
+        &self,
+        filters: &TopFilters,
+        exclude_bots: bool,
+    ) -> Result<Vec<CommitNumAuthor>, AppError> {
+        // The authors whose lines are all unknown are not ranked by lines
+        let (having, order) = match filters.rank()? {
+            "lines" => (
+                "HAVING COUNT(additions) > 0",
+                "SUM(COALESCE(additions, 0) + COALESCE(deletions, 0)) DESC,",
+            ),
+            "days" => ("", "COUNT(DISTINCT substr(date, 1, 10)) DESC,"),
+            _ => ("", ""),
+        };
+        // A negative limit is no limit
+        let limit = filters.limit()?.unwrap_or(-1);
+        let since = filters.since.map(|date| timestamp(&date.naive_utc()));
+        let until = filters.until.map(|date| timestamp(&date.naive_utc()));
+        let branch = filters.branch.map(|id| id.to_string());
+
+        // Only the filters of a repository, a period and a branch are sent
+        let none: Option<String> = None;
+        let params: [&dyn ToSql; 13] = [
+            &none,
+            &filters.repository_url(),
+            &none,
+            &none,
+            &none,
+            &none,
+            &since,
+            &until,
+            &none,
+            &none,
+            &branch,
+            &exclude_bots,
+            &limit,
+        ];
+
+        let connection = self.connection.lock().await;
+        let authors = connection
+            .prepare_cached(&format!(
+                "
+                {}
+                SELECT COUNT(hash) AS num, email.contributor_id,
+                    MIN(author_email) AS author_email,
+                    COALESCE(MIN(contributor.name), MIN(author_name))
+                        AS author_name,
+                    COALESCE(SUM(additions), 0) AS additions,
+                    COALESCE(SUM(deletions), 0) AS deletions,
+                    COUNT(DISTINCT substr(date, 1, 10)) AS days
+                FROM \"commit\"
+                JOIN email ON email.email = \"commit\".author_email
+                LEFT JOIN contributor ON contributor.id = email.contributor_id
+                WHERE {}
+                GROUP BY email.contributor_id,
+                    CASE WHEN email.contributor_id IS NULL
+                        THEN author_email END
+                {}
+                ORDER BY {} COUNT(hash) DESC, MIN(author_email)
+                LIMIT ?13
+                ",
+                BRANCH_HISTORY, FILTERS, having, order
+            ))?
+            .query_map(&params, |row| {
+                let contributor_id: Option<String> = row.get(1)?;
+                Ok(CommitNumAuthor {
+                    num: row.get(0)?,
+                    contributor_id: match contributor_id {
+                        Some(id) => Some(parse_uuid(&id)?),
+                        None => None,
+                    },
+                    author_email: row.get(2)?,
+                    author_name: row.get(3)?,
+                    additions: row.get(4)?,
+                    deletions: row.get(5)?,
+                    days: row.get(6)?,
+                })
+            })?
+            .collect::<Result<Vec<CommitNumAuthor>, _>>()?;
+
+        Ok(authors)
+    }
+
+    async fn find_emails(
+        &self,
+        pagination: &Pagination,
+        by_hash: bool,
+        exclude_bots: bool,
+    ) -> Result<Page<Email>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text])?;
+
+        let emails = connection
+            .prepare_cached(
+                "
+                SELECT * FROM email
+                WHERE NOT (?4 AND is_bot)
+                    AND (?1 IS NULL
+                        OR (CASE WHEN ?3 THEN hash_md5 ELSE email END) > ?1)
+                ORDER BY CASE WHEN ?3 THEN hash_md5 ELSE email END
+                LIMIT ?2
+                ",
+            )?
+            .query_map(
+                params![after[0], pagination.fetch(), by_hash, exclude_bots],
+                email,
+            )?
+            .collect::<Result<Vec<Email>, _>>()?;
+
+        let count = if pagination.count {
+            Some(connection.query_row(
+                "SELECT COUNT(*) FROM email WHERE NOT (?1 AND is_bot)",
+                params![exclude_bots],
+                |row| row.get(0),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(emails, pagination, count, |email| {
+            if by_hash {
+                vec![email.hash_md5.clone()]
+            } else {
+                vec![email.email.clone()]
+            }
+        }))
+    }
+
+    async fn search_emails(
+        &self,
+        query: &str,
+        exclude_bots: bool,
+        pagination: &Pagination,
+    ) -> Result<Page<EmailSearchResult>, AppError> {
+        let normalized = models::normalize(query);
+        let (pattern, fuzzy) = match EmailQuery::parse(&normalized)? {
+            EmailQuery::Domain(pattern) => (pattern, false),
+            EmailQuery::Fuzzy(pattern) => (pattern, true),
+        };
+
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Float, Key::Text])?;
+        let rank = match &after[0] {
+            Some(rank) => Some(rank.parse::<f64>().map_err(|_| AppError {
+                message: Some("Invalid cursor".to_string()),
+                cause: None,
+                error_type: AppErrorType::BadRequestError,
+            })?),
+            None => None,
+        };
+
+        let emails = connection
+            .prepare_cached(&format!(
+                "
+                SELECT * FROM ({})
+                WHERE ?5 IS NULL
+                    OR rank < ?5
+                    OR (rank = ?5 AND email > ?6)
+                ORDER BY rank DESC, email
+                LIMIT ?7
+                ",
+                EMAIL_SEARCH
+            ))?
+            .query_map(
+                params![
+                    normalized,
+                    pattern,
+                    fuzzy,
+                    exclude_bots,
+                    rank,
+                    after[1],
+                    pagination.fetch()
+                ],
+                |row| {
+                    Ok(EmailSearchResult {
+                        email: email(row)?,
+                        rank: row.get("rank")?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<EmailSearchResult>, _>>()?;
+
+        let count = if pagination.count {
+            Some(connection.query_row(
+                &format!("SELECT COUNT(*) FROM ({})", EMAIL_SEARCH),
+                params![normalized, pattern, fuzzy, exclude_bots],
+                |row| row.get(0),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(emails, pagination, count, |result| {
+            vec![result.rank.to_string(), result.email.email.clone()]
+        }))
+    }
+
+    async fn find_email_by_hash(&self, hash: &str) -> Result<Email, AppError> {
+        let connection = self.connection.lock().await;
+        let found = connection
+            .prepare_cached("SELECT * FROM email WHERE hash_md5 = ?1")?
+            .query_row(params![hash], email)
+            .optional()?;
+
+        found.ok_or_else(|| not_found("Email not found"))
+    }
+
+    async fn find_email_names(
+        &self,
+        email: &str,
+    ) -> Result<Vec<EmailName>, AppError> {
+        let connection = self.connection.lock().await;
+
+        find_names(&connection, email)
+    }
+
+    async fn find_profile(&self, hash: &str) -> Result<Profile, AppError> {
+        let connection = self.connection.lock().await;
+        let email = connection
+            .prepare_cached("SELECT * FROM email WHERE hash_md5 = ?1")?
+            .query_row(params![hash], email)
+            .optional()?
+            .ok_or_else(|| not_found("Email not found"))?;
+
+        let (commits, first_commit, last_commit) = connection.query_row(
+            "
+            SELECT COUNT(*), MIN(date) AS first, MAX(date) AS last
+            FROM \"commit\"
+            WHERE author_email = ?1
+            ",
+            params![email.email],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    optional_date(row, "first")?,
+                    optional_date(row, "last")?,
+                ))
+            },
+        )?;
+
+        let names = find_names(&connection, &email.email)?;
+
+        let repositories = connection
+            .prepare_cached(
+                "
+                SELECT repository_url, COUNT(*) AS commits,
+                    MIN(date) AS first, MAX(date) AS last
+                FROM \"commit\"
+                WHERE author_email = ?1
+                GROUP BY repository_url
+                ORDER BY COUNT(*) DESC, repository_url
+                ",
+            )?
+            .query_map(params![email.email], |row| {
+                Ok(ProfileRepository {
+                    repository_url: row.get("repository_url")?,
+                    commits: row.get("commits")?,
+                    first_commit: Local
+                        .from_utc_datetime(&parse_timestamp(row, "first")?),
+                    last_commit: Local
+                        .from_utc_datetime(&parse_timestamp(row, "last")?),
+                })
+            })?
+            .collect::<Result<Vec<ProfileRepository>, _>>()?;
+
+        let recent_commits = connection
+            .prepare_cached(
+                "
+                SELECT * FROM \"commit\"
+                WHERE author_email = ?1
+                ORDER BY date DESC, hash DESC
+                LIMIT ?2
+                ",
+            )?
+            .query_map(params![email.email, PROFILE_COMMITS], commit)?
+            .collect::<Result<Vec<Commit>, _>>()?;
+
+        Ok(Profile {
+            email,
+            commits,
+            first_commit,
+            last_commit,
+            names,
+            repositories,
+            recent_commits,
+        })
+    }
+
+    async fn create_email(
+        &self,
+        address: &str,
+    ) -> Result<(Email, bool), AppError> {
+        let connection = self.connection.lock().await;
+        let address = models::normalize(address);
+        let created = connection.execute(
+            "
+            INSERT OR IGNORE INTO email(email, hash_md5, username, is_bot)
+            VALUES (?1, ?2, ?3, ?4)
+            ",
+            params![
+                address,
+                models::hash(&address),
+                models::noreply_username(&address),
+                self.bots.is_bot(&address)
+            ],
+        )?;
+        let found = connection
+            .prepare_cached("SELECT * FROM email WHERE email = ?1")?
+            .query_row(params![address], email)?;
+
+        Ok((found, created == 1))
+    }
+
+    async fn find_credentials(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Credential>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Timestamp, Key::Uuid])?;
+        let created_at = cursor_timestamp(&after[0])?;
+
+        let credentials = connection
+            .prepare_cached(
+                "
+                SELECT * FROM credential
+                WHERE ?1 IS NULL OR (created_at, id) < (?1, ?2)
+                ORDER BY created_at DESC, id DESC
+                LIMIT ?3
+                ",
+            )?
+            .query_map(
+                params![created_at, after[1], pagination.fetch()],
+                credential,
+            )?
+            .collect::<Result<Vec<Credential>, _>>()?;
+
+        let count = if pagination.count {
+            Some(count(&connection, "SELECT COUNT(*) FROM credential")?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(credentials, pagination, count, |credential| {
+            vec![credential.created_at.to_string(), credential.id.to_string()]
+        }))
+    }
+
+    async fn find_credential(
+        &self,
+        id: &Uuid,
+    ) -> Result<Credential, AppError> {
+        let connection = self.connection.lock().await;
+        let found = connection
+            .prepare_cached("SELECT * FROM credential WHERE id = ?1")?
+            .query_row(params![id.to_string()], credential)
+            .optional()?;
+
+        found.ok_or_else(|| not_found("Credential not found"))
+    }
+
+    async fn create_credential(
+        &self,
+        new: &Credential,
+    ) -> Result<Credential, AppError> {
+        let connection = self.connection.lock().await;
+        connection.execute(
+            "INSERT INTO credential(id, kind, username, secret, created_at)
+            VALUES(?1, ?2, ?3, ?4, ?5)",
+            params![
+                new.id.to_string(),
+                new.kind,
+                new.username,
+                new.secret,
+                timestamp(&Utc::now().naive_utc())
+            ],
+        )?;
+
+        let created = connection
+            .prepare_cached("SELECT * FROM credential WHERE id = ?1")?
+            .query_row(params![new.id.to_string()], credential)?;
+
+        Ok(created)
+    }
+
+    async fn delete_credential(
+        &self,
+        id: &Uuid,
+    ) -> Result<Credential, AppError> {
+        let connection = self.connection.lock().await;
+        let found = connection
+            .prepare_cached("SELECT * FROM credential WHERE id = ?1")?
+            .query_row(params![id.to_string()], credential)
+            .optional()?
+            .ok_or_else(|| not_found("Credential not found"))?;
+
+        connection.execute(
+            "DELETE FROM credential WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+
+        Ok(found)
+    }
+
+    async fn find_contributors(
+        &self,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<Contributor>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+
+        let contributors = connection
+            .prepare_cached(&format!(
+                "
+                {}
+                WHERE {}
+                    AND (?2 IS NULL OR (name, id) > (?2, ?3))
+                ORDER BY name, id
+                LIMIT ?4
+                ",
+                SELECT_CONTRIBUTOR, NOT_BOTS
+            ))?
+            .query_map(
+                params![exclude_bots, after[0], after[1], pagination.fetch()],
+                contributor,
+            )?
+            .collect::<Result<Vec<Contributor>, _>>()?;
+
+        let count = if pagination.count {
+            Some(connection.query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM contributor WHERE {}",
+                    NOT_BOTS
+                ),
+                params![exclude_bots],
+                |row| row.get(0),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(contributors, pagination, count, |contributor| {
+            vec![contributor.name.clone(), contributor.id.to_string()]
+        }))
+    }
+
+    async fn find_contributor(
+        &self,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError> {
+        let connection = self.connection.lock().await;
+        find_contributor(&connection, id)
+    }
+
+    async fn create_contributor(
+        &self,
+        data: &ContributorData,
+    ) -> Result<Contributor, AppError> {
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+
+        let id = Uuid::new_v4();
+        transaction.execute(
+            "INSERT INTO contributor(id, name, created_at)
+            VALUES(?1, ?2, ?3)",
+            params![
+                id.to_string(),
+                data.name,
+                timestamp(&Utc::now().naive_utc())
+            ],
+        )?;
+        move_emails(&transaction, &id, &data.emails, &[])?;
+        transaction.commit()?;
+
+        find_contributor(&connection, &id)
+    }
+
+    async fn merge_contributors(
+        &self,
+        id: &Uuid,
+        data: &MergeData,
+    ) -> Result<Contributor, AppError> {
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+
+        find_contributor(&transaction, id)?;
+        move_emails(&transaction, id, &data.emails, &data.contributors)?;
+        transaction.commit()?;
+
+        find_contributor(&connection, id)
+    }
+
+    async fn split_contributor(
+        &self,
+        id: &Uuid,
+        data: &SplitData,
+    ) -> Result<Contributor, AppError> {
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+
+        let mut found = find_contributor(&transaction, id)?;
+        let emails = normalize_all(&data.emails);
+        for address in emails.iter() {
+            transaction.execute(
+                "
+                UPDATE email SET contributor_id = NULL
+                WHERE contributor_id = ?1 AND email = ?2
+                ",
+                params![id.to_string(), address],
+            )?;
+        }
+        delete_empty_contributors(&transaction)?;
+        transaction.commit()?;
+
+        found.emails.retain(|email| !emails.contains(email));
+        Ok(found)
+    }
+
+    async fn delete_contributor(
+        &self,
+        id: &Uuid,
+    ) -> Result<Contributor, AppError> {
+        let connection = self.connection.lock().await;
+        let found = find_contributor(&connection, id)?;
+
+        connection.execute(
+            "DELETE FROM contributor WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+
+        Ok(found)
+    }
+
+    async fn contributor_suggestions(
+        &self,
+        pagination: &Pagination,
+        exclude_bots: bool,
+    ) -> Result<Page<ContributorSuggestion>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text])?;
+
+        let suggestions = connection
+            .prepare_cached(&format!(
+                "
+                SELECT * FROM ({})
+                WHERE ?2 IS NULL OR name > ?2
+                ORDER BY name
+                LIMIT ?3
+                ",
+                SUGGESTIONS
+            ))?
+            .query_map(
+                params![exclude_bots, after[0], pagination.fetch()],
+                |row| {
+                    Ok(ContributorSuggestion {
+                        name: row.get("name")?,
+                        emails: parse_emails(row)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<ContributorSuggestion>, _>>()?;
+
+        let count = if pagination.count {
+            Some(connection.query_row(
+                &format!("SELECT COUNT(*) FROM ({})", SUGGESTIONS),
+                params![exclude_bots],
+                |row| row.get(0),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(suggestions, pagination, count, |suggestion| {
+            vec![suggestion.name.clone()]
+        }))
+    }
+
+    async fn find_organization_domains(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationDomain>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text])?;
+
+        let domains = connection
+            .prepare_cached(
+                "
+                SELECT * FROM organization_domain
+                WHERE ?1 IS NULL OR domain > ?1
+                ORDER BY domain
+                LIMIT ?2
+                ",
+            )?
+            .query_map(
+                params![after[0], pagination.fetch()],
+                organization_domain,
+            )?
+            .collect::<Result<Vec<OrganizationDomain>, _>>()?;
+
+        let count = if pagination.count {
+            Some(count(
+                &connection,
+                "SELECT COUNT(*) FROM organization_domain",
+            )?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(domains, pagination, count, |domain| {
+            vec![domain.domain.clone()]
+        }))
+    }
+
+    async fn save_organization_domain(
+        &self,
+        domain: &OrganizationDomain,
+    ) -> Result<OrganizationDomain, AppError> {
+        let connection = self.connection.lock().await;
+        connection.execute(
+            "
+            INSERT INTO organization_domain(domain, organization)
+            VALUES(?1, ?2)
+            ON CONFLICT (domain) DO UPDATE
+            SET organization = excluded.organization
+            ",
+            params![domain.domain, domain.organization],
+        )?;
+
+        Ok(OrganizationDomain {
+            domain: domain.domain.clone(),
+            organization: domain.organization.clone(),
+        })
+    }
+
+    async fn delete_organization_domain(
+        &self,
+        domain: &str,
+    ) -> Result<OrganizationDomain, AppError> {
+        let connection = self.connection.lock().await;
+        let domain = models::normalize(domain);
+        let found = connection
+            .prepare_cached(
+                "SELECT * FROM organization_domain WHERE domain = ?1",
+            )?
+            .query_row(params![domain], organization_domain)
+            .optional()?
+            .ok_or_else(|| not_found("Domain not found"))?;
+
+        connection.execute(
+            "DELETE FROM organization_domain WHERE domain = ?1",
+            params![domain],
+        )?;
+
+        Ok(found)
+    }
+
+    async fn find_affiliations(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Affiliation>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Text, Key::Uuid])?;
+
+        let affiliations = connection
+            .prepare_cached(
+                "
+                SELECT * FROM affiliation
+                WHERE ?1 IS NULL OR (email, id) > (?1, ?2)
+                ORDER BY email, id
+                LIMIT ?3
+                ",
+            )?
+            .query_map(
+                params![after[0], after[1], pagination.fetch()],
+                affiliation,
+            )?
+            .collect::<Result<Vec<Affiliation>, _>>()?;
+
+        let count = if pagination.count {
+            Some(count(&connection, "SELECT COUNT(*) FROM affiliation")?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(affiliations, pagination, count, |affiliation| {
+            vec![affiliation.email.clone(), affiliation.id.to_string()]
+        }))
+    }
+
+    async fn create_affiliation(
+        &self,
+        affiliation: &Affiliation,
+    ) -> Result<Affiliation, AppError> {
+        let connection = self.connection.lock().await;
+        connection.execute(
+            "
+            INSERT INTO affiliation(id, email, organization, since, until)
+            VALUES(?1, ?2, ?3, ?4, ?5)
+            ",
+            params![
+                affiliation.id.to_string(),
+                affiliation.email,
+                affiliation.organization,
+                affiliation.since.map(|date| date.to_string()),
+                affiliation.until.map(|date| date.to_string()),
+            ],
+        )?;
+
+        Ok(Affiliation {
+            id: affiliation.id,
+            email: affiliation.email.clone(),
+            organization: affiliation.organization.clone(),
+            since: affiliation.since,
+            until: affiliation.until,
+        })
+    }
+
+    async fn delete_affiliation(
+        &self,
+        id: &Uuid,
+    ) -> Result<Affiliation, AppError> {
+        let connection = self.connection.lock().await;
+        let found = connection
+            .prepare_cached("SELECT * FROM affiliation WHERE id = ?1")?
+            .query_row(params![id.to_string()], affiliation)
+            .optional()?
+            .ok_or_else(|| not_found("Affiliation not found"))?;
+
+        connection.execute(
+            "DELETE FROM affiliation WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+
+        Ok(found)
+    }
+
+    async fn organization_commits(
+        &self,
+        filters: &OrganizationFilters,
+        pagination: &Pagination,
+    ) -> Result<Page<OrganizationCommits>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Date, Key::Text, Key::Text])?;
+        let period = filters.period()?;
+        let repository_url = filters.repository_url();
+        let since = filters.since.map(|date| timestamp(&date.naive_utc()));
+        let until = filters.until.map(|date| timestamp(&date.naive_utc()));
+
+        let reports = connection
+            .prepare_cached(&format!(
+                "
+                SELECT organization, repository_url, period,
+                    COUNT(*) AS commits
+                FROM ({})
+                GROUP BY organization, repository_url, period
+                HAVING ?7 IS NULL
+                    OR period < ?7
+                    OR (period = ?7
+                        AND (organization, repository_url) > (?8, ?9))
+                ORDER BY period DESC, organization, repository_url
+                LIMIT ?10
+                ",
+                AFFILIATED_COMMITS
+            ))?
+            .query_map(
+                params![
+                    period,
+                    repository_url,
+                    since,
+                    until,
+                    UNKNOWN,
+                    filters.exclude_bots,
+                    after[0],
+                    after[1],
+                    after[2],
+                    pagination.fetch()
+                ],
+                |row| {
+                    Ok(OrganizationCommits {
+                        organization: row.get("organization")?,
+                        repository_url: row.get("repository_url")?,
+                        period: parse_date(&row.get::<_, String>("period")?)?,
+                        commits: row.get("commits")?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<OrganizationCommits>, _>>()?;
+
+        let count = if pagination.count {
+            Some(
+                connection
+                    .prepare_cached(&format!(
+                        "
+                        SELECT COUNT(*) FROM (
+                            SELECT DISTINCT organization, repository_url,
+                                period
+                            FROM ({})
+                        )
+                        ",
+                        AFFILIATED_COMMITS
+                    ))?
+                    .query_row(
+                        params![
+                            period,
+                            repository_url,
+                            since,
+                            until,
+                            UNKNOWN,
+                            filters.exclude_bots
+                        ],
+                        |row| row.get(0),
+                    )?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Page::new(reports, pagination, count, |report| {
+            vec![
+                report.period.to_string(),
+                report.organization.clone(),
+                report.repository_url.clone(),
+            ]
+        }))
+    }
+
+    async fn find_erasures(
+        &self,
+        pagination: &Pagination,
+    ) -> Result<Page<Erasure>, AppError> {
+        let connection = self.connection.lock().await;
+        let after = pagination.after(&[Key::Timestamp, Key::Text])?;
+        let created_at = cursor_timestamp(&after[0])?;
+
+        let erasures = connection
+            .prepare_cached(
+                "
+                SELECT * FROM erasure
+                WHERE ?1 IS NULL OR (created_at, hash_sha256) < (?1, ?2)
+                ORDER BY created_at DESC, hash_sha256 DESC
+                LIMIT ?3
+                ",
+            )?
+            .query_map(
+                params![created_at, after[1], pagination.fetch()],
+                erasure,
+            )?
+            .collect::<Result<Vec<Erasure>, _>>()?;
+
+        let count = if pagination.count {
+            Some(count(&connection, "SELECT COUNT(*) FROM erasure")?)
+        } else {
+            None
+        };
+
+        Ok(Page::new(erasures, pagination, count, |erasure| {
+            vec![erasure.created_at.to_string(), erasure.hash_sha256.clone()]
+        }))
+    }
+
+    async fn erase_email(&self, email: &str) -> Result<Erasure, AppError> {
+        let email = models::normalize(email);
+        if email.is_empty() {
+            return Err(bad_request("Email is required"));
+        }
+        let digest = digest(&email);
+
+        let mut connection = self.connection.lock().await;
+        let transaction = connection.transaction()?;
+
+        transaction.execute(
+            "
+            INSERT OR IGNORE INTO erasure(hash_sha256, pseudonym, created_at)
+            VALUES(?1, ?2, ?3)
+            ",
+            params![digest, pseudonym(), timestamp(&Utc::now().naive_utc())],
+        )?;
+        let found = transaction
+            .prepare_cached("SELECT * FROM erasure WHERE hash_sha256 = ?1")?
+            .query_row(params![digest], erasure)?;
+        let pseudonym = &found.pseudonym;
+
+        transaction.execute(
+            "INSERT OR IGNORE INTO email(email, hash_md5) VALUES(?1, ?2)",
+            params![pseudonym, models::hash(pseudonym)],
+        )?;
+        transaction.execute(
+            "
+            UPDATE \"commit\" SET author_email = ?2, author_name = ''
+            WHERE author_email = ?1
+            ",
+            params![email, pseudonym],
+        )?;
+        transaction.execute(
+            "
+            UPDATE \"commit\" SET committer_email = ?2, committer_name = ''
+            WHERE committer_email = ?1
+            ",
+            params![email, pseudonym],
+        )?;
+
+        let messages = transaction
+            .prepare_cached(
+                "
+                SELECT hash, text FROM \"commit\"
+                WHERE text LIKE ?1 ESCAPE '\\'
+                ",
+            )?
+            .query_map(params![like_pattern(&email)], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+        for (hash, text) in messages {
+            transaction.execute(
+                "UPDATE \"commit\" SET text = ?2 WHERE hash = ?1",
+                params![hash, erase_text(&text, &email, pseudonym)],
+            )?;
+        }
+
+        transaction.execute(
+            "UPDATE affiliation SET email = ?2 WHERE email = ?1",
+            params![email, pseudonym],
+        )?;
+        transaction
+            .execute("DELETE FROM email WHERE email = ?1", params![email])?;
+        delete_empty_contributors(&transaction)?;
+        transaction.commit()?;
+
+        Ok(found)
+    }
+
+    async fn begin(&self) -> Result<Box<dyn StorageTransaction>, AppError> {
+        // The write lock is taken now: a deferred transaction which reads
+        // first would fail if another connection wrote in the meantime
+        let connection = self.writer.clone().lock_owned().await;
+        connection.execute_batch("BEGIN IMMEDIATE")?;
+
+        Ok(Box::new(SqliteTransaction {
+            connection,
+            committed: false,
+            bots: self.bots.clone(),
+        }))
+    }
+}
+
+/// Transaction on the connection of the imports, which stays locked until
+/// the transaction ends
+pub struct SqliteTransaction {
+    connection: OwnedMutexGuard<Connection>,
+    committed: bool,
+    bots: Bots,
+}
+
+#[async_trait(?Send)]
+impl StorageTransaction for SqliteTransaction {
+    async fn create_repository(
+        &mut self,
+        repo: &Repository,
+    ) -> Result<Repository, AppError> {
+        let now = timestamp(&Utc::now().naive_utc());
+        self.connection.execute(
+            "
+            INSERT INTO repository(
+                id, url, created_at, updated_at, uploader_ip,
+                webhook_secret, credential_id
+            )
+            VALUES(?1, ?2, ?3, ?3, ?4, ?5, ?6)
+            ",
+            params![
+                repo.id.to_string(),
+                repo.url,
+                now,
+                repo.uploader_ip,
+                repo.webhook_secret,
+                repo.credential_id.map(|id| id.to_string())
+            ],
+        )?;
+
+        let created = self
+            .connection
+            .prepare_cached("SELECT * FROM repository WHERE id = ?1")?
+            .query_row(params![repo.id.to_string()], repository)?;
+
+        Ok(created)
+    }
+
+    async fn touch_repository(&mut self, id: &Uuid) -> Result<(), AppError> {
+        self.connection.execute(
+            "UPDATE repository SET updated_at = ?1 WHERE id = ?2",
+            params![timestamp(&Utc::now().naive_utc()), id.to_string()],
+        )?;
+
+        Ok(())
+    }
+
+    async fn find_pseudonyms(
+        &mut self,
+        emails: Vec<String>,
+    ) -> Result<HashMap<String, String>, AppError> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT pseudonym FROM erasure WHERE hash_sha256 = ?1",
+        )?;
+
+        let mut pseudonyms = HashMap::new();
+        for address in emails {
+            let found: Option<String> = statement
+                .query_row(params![digest(&address)], |row| row.get(0))
+                .optional()?;
+            if let Some(pseudonym) = found {
+                pseudonyms.insert(address, pseudonym);
+            }
+        }
+
+        Ok(pseudonyms)
+    }
+
+    async fn create_emails(
+        &mut self,
+        emails: Vec<String>,
+    ) -> Result<(), AppError> {
+        let mut statement = self.connection.prepare_cached(
+            "
+                INSERT OR IGNORE INTO email(email, hash_md5, username, is_bot)
+                VALUES (?1, ?2, ?3, ?4)
+                ",
+        )?;
+        for address in emails {
+            let digest = models::hash(&address);
+            let username = models::noreply_username(&address);
+            let is_bot = self.bots.is_bot(&address);
+            statement.execute(params![address, digest, username, is_bot])?;
+        }
+
+        Ok(())
+    }
+
+    async fn create_commits(
+        &mut self,
+        commits: Vec<Commit>,
+    ) -> Result<usize, AppError> {
+        let mut statement = self.connection.prepare_cached(
+            "
+            INSERT OR IGNORE INTO \"commit\"(
+                hash, tree, text, date, author_email, author_name,
+                committer_email, committer_name, repository_url, is_merge,
+                additions, deletions
+            )
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            ",
+        )?;
+
+        let mut complete = self.connection.prepare_cached(
+            "
+            UPDATE \"commit\" SET is_merge = ?2, additions = ?3, deletions = ?4
+            WHERE hash = ?1 AND additions IS NULL
+            ",
+        )?;
+
+        let mut name = self.connection.prepare_cached(
+            "
+            INSERT INTO email_name(email, name, commits, first_seen, last_seen)
+            VALUES(?1, ?2, 1, ?3, ?3)
+            ON CONFLICT(email, name) DO UPDATE SET
+                commits = commits + 1,
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen)
+            ",
+        )?;
+
+        let mut created: usize = 0;
+        for commit in commits {
+            let date = timestamp(&commit.date.naive_utc());
+            if statement.execute(params![
+                commit.hash,
+                commit.tree,
+                commit.text,
+                date,
+                commit.author_email,
+                commit.author_name,
+                commit.committer_email,
+                commit.committer_name,
+                commit.repository_url,
+                commit.is_merge,
+                commit.additions,
+                commit.deletions
+            ])? == 0
+            {
+                complete.execute(params![
+                    commit.hash,
+                    commit.is_merge,
+                    commit.additions,
+                    commit.deletions
+                ])?;
+                continue;
+            }
+            created += 1;
+
+            // A name used as author and as committer counts once
+            let mut uses = vec![(&commit.author_email, &commit.author_name)];
+            if (&commit.committer_email, &commit.committer_name) != uses[0] {
+                uses.push((&commit.committer_email, &commit.committer_name));
+            }
+            for (address, used) in uses {
+                if !used.is_empty() {
+                    name.execute(params![address, used, date])?;
+                }
+            }
+        }
+
+        Ok(created)
+    }
+
+    async fn create_branch(
+        &mut self,
+        data: &BranchData,
+    ) -> Result<Branch, AppError> {
+        // Create a new UUID v4
+        let id = Uuid::new_v4().to_string();
+        self.connection.execute(
+            "INSERT INTO branch(id, name, repository_id, head)
+            VALUES(?1, ?2, ?3, ?4)",
+            params![id, data.name, data.repository_id.to_string(), data.head],
+        )?;
+
+        let created = self
+            .connection
+            .prepare_cached("SELECT * FROM branch WHERE id = ?1")?
+            .query_row(params![id], branch)?;
+
+        Ok(created)
+    }
+
+    async fn update_branch_head(
+        &mut self,
+        id: &Uuid,
+        head: &str,
+    ) -> Result<Branch, AppError> {
+        self.connection.execute(
+            "UPDATE branch SET head = ?1 WHERE id = ?2",
+            params![head, id.to_string()],
+        )?;
+
+        let updated = self
+            .connection
+            .prepare_cached("SELECT * FROM branch WHERE id = ?1")?
+            .query_row(params![id.to_string()], branch)
+            .optional()?;
+
+        updated.ok_or_else(|| not_found("Branch not found"))
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<(), AppError> {
+        self.connection.execute_batch("COMMIT")?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for SqliteTransaction {
+    /// A transaction not committed is rolled back before the connection is
+    /// unlocked
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.connection.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+/// Convert a search query into a FTS5 query where every term must match.
+/// Returns `None` when there is nothing to search
+fn fts_query(query: &str) -> Option<String> {
+    let terms = search_terms(query)
+        .into_iter()
+        .map(|term| {
+            let mut phrase = format!("\"{}\"", term.words.join(" "));
+            if term.prefix {
+                phrase += "*";
+            }
+            phrase
+        })
+        .collect::<Vec<String>>();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" AND "))
+    }
+}
+
+/// Format a UTC timestamp as it is saved
+fn timestamp(date: &NaiveDateTime) -> String {
+    date.format(TIMESTAMP).to_string()
+}
+
+/// Parse a timestamp made by `timestamp` which can be `NULL`
+fn optional_date(
+    row: &Row,
+    column: &str,
+) -> rusqlite::Result<Option<DateTime<Local>>> {
+    match row.get::<_, Option<String>>(column)? {
+        Some(_) => Ok(Some(
+            Local.from_utc_datetime(&parse_timestamp(row, column)?),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Parse a timestamp made by `timestamp`
+fn parse_timestamp(
+    row: &Row,
+    column: &str,
+) -> rusqlite::Result<NaiveDateTime> {
+    let text: String = row.get(column)?;
+    NaiveDateTime::parse_from_str(&text, TIMESTAMP).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            0,
+            rusqlite::types::Type::Text,
+            Box::new(e),
+        )
+    })
+}
+
+/// Parse a date saved as text, like "2020-01-31"
+fn parse_date(text: &str) -> rusqlite::Result<NaiveDate> {
+    text.parse().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            0,
+            rusqlite::types::Type::Text,
+            Box::new(e),
+        )
+    })
+}
+
+/// Parse an uuid saved as text
+fn parse_uuid(text: &str) -> rusqlite::Result<Uuid> {
+    Uuid::parse_str(text).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            0,
+            rusqlite::types::Type::Text,
+            Box::new(e),
+        )
+    })
+}
+
+/// Convert a timestamp of a cursor, made by any backend, to the saved format
+fn cursor_timestamp(
+    value: &Option<String>,
+) -> Result<Option<String>, AppError> {
+    let text = match value {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+
+    let date = DateTime::parse_from_rfc3339(text)
+        .map(|date| date.naive_utc())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")
+        });
+
+    match date {
+        Ok(date) => Ok(Some(timestamp(&date))),
+        Err(_) => Err(AppError {
+            message: Some("Invalid cursor".to_string()),
+            cause: None,
+            error_type: AppErrorType::BadRequestError,
+        }),
+    }
+}
+
+/// Open a connection to the database at `path`, creating it when it does not
+/// exist
+fn connect(path: &str) -> Result<Connection, AppError> {
+    let connection = match Connection::open(path) {
+        Ok(connection) => connection,
+        Err(e) => {
+            return Err(AppError {
+                message: Some(format!(
+                    "SQLite database {} cannot be opened",
+                    path
+                )),
+                cause: Some(e.to_string()),
+                error_type: AppErrorType::DbError,
+            });
+        }
+    };
+    connection.busy_timeout(Duration::from_secs(5))?;
+    register_functions(&connection)?;
+    connection.execute_batch(
+        "PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;",
+    )?;
+
+    Ok(connection)
+}
+
+/// Register the functions used by the queries which SQLite lacks
+fn register_functions(connection: &Connection) -> Result<(), AppError> {
+    connection.create_scalar_function(
+        "similarity",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(similarity(&ctx.get::<String>(0)?, &ctx.get::<String>(1)?)),
+    )?;
+    connection.create_scalar_function(
+        "local_time",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let date = match ctx.get::<Option<String>>(0)? {
+                Some(date) => date,
+                None => return Ok(None),
+            };
+            let date = NaiveDateTime::parse_from_str(&date, TIMESTAMP)
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            let timezone = ctx
+                .get::<String>(1)?
+                .parse::<Tz>()
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+
+            Ok(Some(
+                timezone
+                    .from_utc_datetime(&date)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+            ))
+        },
+    )?;
+
+    Ok(())
+}
+
+fn find_contributor(
+    connection: &Connection,
+    id: &Uuid,
+) -> Result<Contributor, AppError> {
+    connection
+        .prepare_cached(&format!("{} WHERE id = ?1", SELECT_CONTRIBUTOR))?
+        .query_row(params![id.to_string()], contributor)
+        .optional()?
+        .ok_or_else(|| not_found("Contributor not found"))
+}
+
+/// Move `emails`, and the emails of `contributors`, to the contributor `id`,
+/// then delete the contributors left without emails. Every email must exist
+fn move_emails(
+    transaction: &Transaction,
+    id: &Uuid,
+    emails: &[String],
+    contributors: &[Uuid],
+) -> Result<(), AppError> {
+    let emails = normalize_all(emails);
+    if emails.is_empty() && contributors.is_empty() {
+        return Err(bad_request("At least one email is required"));
+    }
+
+    for address in emails.iter() {
+        let moved = transaction.execute(
+            "UPDATE email SET contributor_id = ?1 WHERE email = ?2",
+            params![id.to_string(), address],
+        )?;
+        if moved == 0 {
+            return Err(bad_request("Some emails do not exist"));
+        }
+    }
+    for other in contributors {
+        transaction.execute(
+            "UPDATE email SET contributor_id = ?1 WHERE contributor_id = ?2",
+            params![id.to_string(), other.to_string()],
+        )?;
+    }
+
+    delete_empty_contributors(transaction)
+}
+
+fn delete_empty_contributors(connection: &Connection) -> Result<(), AppError> {
+    connection.execute(
+        "
+        DELETE FROM contributor
+        WHERE NOT EXISTS (
+            SELECT 1 FROM email WHERE contributor_id = contributor.id
+        )
+        ",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// Names used with an email, the oldest first
+fn find_names(
+    connection: &Connection,
+    email: &str,
+) -> Result<Vec<EmailName>, AppError> {
+    let names = connection
+        .prepare_cached(
+            "
+            SELECT * FROM email_name
+            WHERE email = ?1
+            ORDER BY first_seen, name
+            ",
+        )?
+        .query_map(params![email], email_name)?
+        .collect::<Result<Vec<EmailName>, _>>()?;
+
+    Ok(names)
+}
+
+/// Count again the names used with `emails`, after some of their commits are
+/// deleted
+fn refresh_names(
+    connection: &Connection,
+    emails: &[String],
+) -> Result<(), AppError> {
+    for address in emails {
+        connection.execute(
+            "DELETE FROM email_name WHERE email = ?1",
+            params![address],
+        )?;
+        connection.execute(
+            "
+            INSERT INTO email_name(email, name, commits, first_seen, last_seen)
+            SELECT ?1, name, COUNT(*), MIN(date), MAX(date) FROM (
+                SELECT hash, date, author_name AS name FROM \"commit\"
+                WHERE author_email = ?1
+                UNION
+                SELECT hash, date, committer_name FROM \"commit\"
+                WHERE committer_email = ?1
+            )
+            WHERE name <> ''
+            GROUP BY name
+            ",
+            params![address],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn count(connection: &Connection, sql: &str) -> Result<i64, AppError> {
+    Ok(connection.query_row(sql, NO_PARAMS, |row| row.get(0))?)
+}
+
+fn not_found(message: &str) -> AppError {
+    AppError {
+        error_type: AppErrorType::NotFoundError,
+        cause: None,
+        message: Some(message.to_string()),
+    }
+}
+
+fn repository(row: &Row) -> rusqlite::Result<Repository> {
+    let credential_id: Option<String> = row.get("credential_id")?;
+
+    Ok(Repository {
+        id: parse_uuid(&row.get::<_, String>("id")?)?,
+        url: row.get("url")?,
+        created_at: parse_timestamp(row, "created_at")?,
+        updated_at: parse_timestamp(row, "updated_at")?,
+        uploader_ip: row.get("uploader_ip")?,
+        webhook_secret: row.get("webhook_secret")?,
+        credential_id: match credential_id {
+            Some(id) => Some(parse_uuid(&id)?),
+            None => None,
+        },
+    })
+}
+
+fn branch(row: &Row) -> rusqlite::Result<Branch> {
+    Ok(Branch {
+        id: parse_uuid(&row.get::<_, String>("id")?)?,
+        name: row.get("name")?,
+        repository_id: parse_uuid(&row.get::<_, String>("repository_id")?)?,
+        head: row.get("head")?,
+    })
+}
+
+fn commit(row: &Row) -> rusqlite::Result<Commit> {
+    Ok(Commit {
+        hash: row.get("hash")?,
+        tree: row.get("tree")?,
+        text: row.get("text")?,
+        date: Local.from_utc_datetime(&parse_timestamp(row, "date")?),
+        author_email: row.get("author_email")?,
+        author_name: row.get("author_name")?,
+        committer_email: row.get("committer_email")?,
+        committer_name: row.get("committer_name")?,
+        repository_url: row.get("repository_url")?,
+        is_merge: row.get("is_merge")?,
+        additions: row.get("additions")?,
+        deletions: row.get("deletions")?,
+    })
+}
+
+fn email(row: &Row) -> rusqlite::Result<Email> {
+    Ok(Email {
+        email: row.get("email")?,
+        hash_md5: row.get("hash_md5")?,
+        username: row.get("username")?,
+        contributor_id: match row.get::<_, Option<String>>("contributor_id")? {
+            Some(id) => Some(parse_uuid(&id)?),
+            None => None,
+        },
+        is_bot: row.get("is_bot")?,
+    })
+}
+
+/// Parse the JSON array of the emails of a row, sorting them
+fn parse_emails(row: &Row) -> rusqlite::Result<Vec<String>> {
+    let json: String = row.get("emails")?;
+    let mut emails: Vec<String> =
+        serde_json::from_str(&json).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(
+                0,
+                rusqlite::types::Type::Text,
+                Box::new(e),
+            )
+        })?;
+    emails.sort();
+
+    Ok(emails)
+}
+
+fn email_name(row: &Row) -> rusqlite::Result<EmailName> {
+    Ok(EmailName {
+        name: row.get("name")?,
+        commits: row.get("commits")?,
+        first_seen: Local
+            .from_utc_datetime(&parse_timestamp(row, "first_seen")?),
+        last_seen: Local
+            .from_utc_datetime(&parse_timestamp(row, "last_seen")?),
+    })
+}
+
+fn contributor(row: &Row) -> rusqlite::Result<Contributor> {
+    Ok(Contributor {
+        id: parse_uuid(&row.get::<_, String>("id")?)?,
+        name: row.get("name")?,
+        created_at: parse_timestamp(row, "created_at")?,
+        emails: parse_emails(row)?,
+    })
+}
+
+fn organization_domain(row: &Row) -> rusqlite::Result<OrganizationDomain> {
+    Ok(OrganizationDomain {
+        domain: row.get("domain")?,
+        organization: row.get("organization")?,
+    })
+}
+
+fn affiliation(row: &Row) -> rusqlite::Result<Affiliation> {
+    let since: Option<String> = row.get("since")?;
+    let until: Option<String> = row.get("until")?;
+
+    Ok(Affiliation {
+        id: parse_uuid(&row.get::<_, String>("id")?)?,
+        email: row.get("email")?,
+        organization: row.get("organization")?,
+        since: since.as_deref().map(parse_date).transpose()?,
+        until: until.as_deref().map(parse_date).transpose()?,
+    })
+}
+
+fn erasure(row: &Row) -> rusqlite::Result<Erasure> {
+    Ok(Erasure {
+        hash_sha256: row.get("hash_sha256")?,
+        pseudonym: row.get("pseudonym")?,
+        created_at: parse_timestamp(row, "created_at")?,
+    })
+}
+
+fn credential(row: &Row) -> rusqlite::Result<Credential> {
+    Ok(Credential {
+        id: parse_uuid(&row.get::<_, String>("id")?)?,
+        kind: row.get("kind")?,
+        username: row.get("username")?,
+        secret: row.get("secret")?,
+        created_at: parse_timestamp(row, "created_at")?,
+    })
+}
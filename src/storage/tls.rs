@@ -0,0 +1,63 @@
+use crate::config::{PgTlsConfig, TlsMode};
+use crate::errors::{AppError, AppErrorType};
+
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+
+/// Makes the TLS connections to PostgreSQL with OpenSSL. "verify-full"
+/// checks the certificate of the server and its hostname, "require" checks
+/// only the certificate when a CA file is set, as libpq does
+pub fn connector(config: &PgTlsConfig) -> Result<MakeTlsConnector, AppError> {
+    let error = |e: openssl::error::ErrorStack, message: &str| AppError {
+        message: Some(message.to_string()),
+        cause: Some(e.to_string()),
+        error_type: AppErrorType::ConfigError,
+    };
+
+    let mut builder = SslConnector::builder(SslMethod::tls())
+        .map_err(|e| error(e, "TLS cannot be initialized"))?;
+
+    if let Some(ca_file) = &config.ca_file {
+        builder
+            .set_ca_file(ca_file)
+            .map_err(|e| error(e, "Invalid TLS CA file"))?;
+    }
+    match (&config.cert_file, &config.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder
+                .set_certificate_chain_file(cert_file)
+                .map_err(|e| error(e, "Invalid TLS certificate file"))?;
+            builder
+                .set_private_key_file(key_file, SslFiletype::PEM)
+                .map_err(|e| error(e, "Invalid TLS key file"))?;
+        }
+        (None, None) => {}
+        _ => {
+            return Err(AppError {
+                message: Some(
+                    "TLS client certificate requires both a certificate and \
+                    a key file"
+                        .to_string(),
+                ),
+                cause: None,
+                error_type: AppErrorType::ConfigError,
+            });
+        }
+    }
+
+    let verify_hostname = config.mode == TlsMode::VerifyFull;
+    let verify = verify_hostname
+        || (config.mode == TlsMode::Require && config.ca_file.is_some());
+    if !verify {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+
+    let mut connector = MakeTlsConnector::new(builder.build());
+    connector.set_callback(move |ssl, _domain| {
+        ssl.set_verify_hostname(verify_hostname);
+        ssl.set_use_server_name_indication(verify_hostname);
+        Ok(())
+    });
+
+    Ok(connector)
+}
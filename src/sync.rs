@@ -0,0 +1,70 @@
+use crate::config::ImportConfig;
+use crate::git::ImportLimits;
+use crate::repository::models::Repository;
+use crate::storage::{SharedStorage, Storage};
+
+use slog::{error, info, Logger};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// A request of incremental import for a branch of a repository
+pub struct SyncJob {
+    pub repository_id: Uuid,
+    pub branch_id: Uuid,
+}
+
+/// Sender side of the queue of the imports to run
+pub type SyncQueue = mpsc::UnboundedSender<SyncJob>;
+
+/// Start the worker which executes the enqueued imports, one at a time.
+/// Returns the queue used to enqueue new imports
+pub fn start(
+    storage: SharedStorage,
+    log: Logger,
+    config: ImportConfig,
+) -> SyncQueue {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<SyncJob>();
+
+    actix_rt::spawn(async move {
+        while let Some(job) = receiver.recv().await {
+            run(storage.as_ref(), &log, &config, job).await;
+        }
+    });
+
+    sender
+}
+
+/// Run an import. The branch is read again because its head could be moved
+/// by a previous job
+async fn run(
+    storage: &dyn Storage,
+    log: &Logger,
+    config: &ImportConfig,
+    job: SyncJob,
+) {
+    let branch = match storage.find_branch(&job.branch_id).await {
+        Ok(branch) => branch,
+        Err(_) => {
+            info!(log, "Sync of branch {} skipped: not found", job.branch_id);
+            return;
+        }
+    };
+
+    let limits = ImportLimits::from_config(config);
+    match Repository::sync(storage, &branch, limits).await {
+        Ok(imported) => info!(
+            log,
+            "Sync of repository {} branch {}: {} new commits",
+            job.repository_id,
+            branch.name,
+            imported
+        ),
+        Err(e) => error!(
+            log,
+            "Sync of repository {} branch {} failed: {}",
+            job.repository_id,
+            branch.name,
+            e.message()
+        ),
+    }
+}
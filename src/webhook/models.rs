@@ -0,0 +1,163 @@
+use actix_web::HttpRequest;
+use serde::Deserialize;
+
+use hex;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+/// Git hosts which can notify a push with a webhook
+pub enum Host {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+/// Push event payload. These fields have the same name for every `Host`
+#[derive(Deserialize)]
+pub struct PushEvent {
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub after: String,
+}
+
+impl PushEvent {
+    /// Returns the name of the pushed branch. Tags and deleted branches
+    /// return `None`
+    pub fn branch(&self) -> Option<&str> {
+        if self.after.chars().all(|c| c == '0') {
+            return None;
+        }
+
+        self.git_ref.strip_prefix("refs/heads/")
+    }
+}
+
+impl Host {
+    /// Check if the request has been sent by the host using the repository
+    /// `secret`. GitHub and Gitea sign the body with an HMAC-SHA256, GitLab
+    /// sends the secret as a token
+    pub fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+        secret: &str,
+    ) -> bool {
+        match self {
+            Host::GitHub => match header(req, "X-Hub-Signature-256") {
+                Some(signature) => match signature.strip_prefix("sha256=") {
+                    Some(signature) => verify_hmac(secret, body, signature),
+                    None => false,
+                },
+                None => false,
+            },
+            Host::GitLab => match header(req, "X-Gitlab-Token") {
+                Some(token) => {
+                    token.as_bytes().ct_eq(secret.as_bytes()).into()
+                }
+                None => false,
+            },
+            Host::Gitea => match header(req, "X-Gitea-Signature") {
+                Some(signature) => verify_hmac(secret, body, signature),
+                None => false,
+            },
+        }
+    }
+
+    /// Check if the request notifies a push. Other events (e.g. the GitHub
+    /// "ping") are ignored
+    pub fn is_push(&self, req: &HttpRequest) -> bool {
+        let event = match self {
+            Host::GitHub => header(req, "X-GitHub-Event"),
+            Host::GitLab => header(req, "X-Gitlab-Event"),
+            Host::Gitea => header(req, "X-Gitea-Event"),
+        };
+
+        match self {
+            Host::GitLab => event == Some("Push Hook"),
+            _ => event == Some("push"),
+        }
+    }
+}
+
+/// Returns the value of the header `name`, if it is a valid string
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Check if `signature` is the hex encoded HMAC-SHA256 of `body`
+fn verify_hmac(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_varkey(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+
+    mac.verify(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const SECRET: &str = "hook secret";
+    const BODY: &[u8] = br#"{"ref":"refs/heads/main"}"#;
+
+    #[test]
+    fn verify_github_signature() {
+        let valid = format!("sha256={}", sign(SECRET, BODY));
+        let req = request("X-Hub-Signature-256", &valid);
+        assert!(Host::GitHub.verify(&req, BODY, SECRET));
+        assert!(!Host::GitHub.verify(&req, b"{}", SECRET));
+        assert!(!Host::GitHub.verify(&req, BODY, "other secret"));
+
+        let unprefixed = request("X-Hub-Signature-256", &sign(SECRET, BODY));
+        assert!(!Host::GitHub.verify(&unprefixed, BODY, SECRET));
+        let gitea = request("X-Gitea-Signature", &sign(SECRET, BODY));
+        assert!(!Host::GitHub.verify(&gitea, BODY, SECRET));
+    }
+
+    #[test]
+    fn verify_gitlab_token() {
+        let req = request("X-Gitlab-Token", SECRET);
+        assert!(Host::GitLab.verify(&req, BODY, SECRET));
+        assert!(!Host::GitLab.verify(&req, BODY, "other secret"));
+        assert!(!Host::GitLab.verify(&req, BODY, "hook"));
+
+        let missing = TestRequest::default().to_http_request();
+        assert!(!Host::GitLab.verify(&missing, BODY, SECRET));
+    }
+
+    #[test]
+    fn verify_gitea_signature() {
+        let req = request("X-Gitea-Signature", &sign(SECRET, BODY));
+        assert!(Host::Gitea.verify(&req, BODY, SECRET));
+        assert!(!Host::Gitea.verify(&req, b"{}", SECRET));
+
+        let invalid = request("X-Gitea-Signature", "not hex");
+        assert!(!Host::Gitea.verify(&invalid, BODY, SECRET));
+        let missing = TestRequest::default().to_http_request();
+        assert!(!Host::Gitea.verify(&missing, BODY, SECRET));
+    }
+
+    fn request(header: &str, value: &str) -> HttpRequest {
+        TestRequest::default()
+            .header(header, value)
+            .to_http_request()
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+        mac.update(body);
+
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
@@ -0,0 +1,142 @@
+use crate::config::AppState;
+use crate::credential::models::decrypt;
+use crate::errors::{AppError, AppErrorType};
+use crate::helpers::uuid_from_string;
+use crate::sync::SyncJob;
+use crate::webhook::models::{Host, PushEvent};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use slog::info;
+use uuid::Uuid;
+
+/// Push payloads can be larger than the default 256kB limit
+const PAYLOAD_LIMIT: usize = 25 * 1024 * 1024;
+
+/// Verify a webhook sent by `host` for the repository `id` and, if it
+/// notifies a push on a saved branch, enqueue an import of the new commits
+async fn receive(
+    host: Host,
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    id: &String,
+) -> Result<HttpResponse, AppError> {
+    let uuid: Uuid = uuid_from_string(id);
+    let repo = state.storage.find_repository(&uuid).await?;
+
+    let secret = match &repo.webhook_secret {
+        Some(secret) => decrypt(&repo.id, secret)?,
+        None => {
+            return Err(AppError {
+                error_type: AppErrorType::AuthorizationError,
+                message: Some(
+                    "Webhooks are not enabled for this repository".to_string(),
+                ),
+                cause: None,
+            });
+        }
+    };
+
+    let secret = String::from_utf8_lossy(&secret);
+    if !host.verify(&req, &body, &secret) {
+        return Err(AppError {
+            error_type: AppErrorType::AuthorizationError,
+            message: Some("Invalid webhook signature".to_string()),
+            cause: None,
+        });
+    }
+
+    if !host.is_push(&req) {
+        return Ok(HttpResponse::NoContent().body(""));
+    }
+
+    let event: PushEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(e) => {
+            return Err(AppError {
+                error_type: AppErrorType::BadRequestError,
+                message: Some("Invalid push payload".to_string()),
+                cause: Some(e.to_string()),
+            });
+        }
+    };
+
+    let name = match event.branch() {
+        Some(name) => name,
+        None => return Ok(HttpResponse::NoContent().body("")),
+    };
+
+    // Only the branches already imported are synchronized
+    let branch = match state.storage.find_branch_by_name(&repo.id, name).await
+    {
+        Ok(branch) => branch,
+        Err(e) if e.error_type == AppErrorType::NotFoundError => {
+            return Ok(HttpResponse::NoContent().body(""));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let job = SyncJob {
+        repository_id: repo.id,
+        branch_id: branch.id,
+    };
+    if let Err(e) = state.sync.send(job) {
+        return Err(AppError {
+            error_type: AppErrorType::DbError,
+            message: Some("Import queue is not available".to_string()),
+            cause: Some(e.to_string()),
+        });
+    }
+
+    Ok(HttpResponse::Accepted().body(""))
+}
+
+/// Endpoint used by GitHub push webhooks
+async fn github(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "POST /webhook/github/{}/", &id);
+    receive(Host::GitHub, req, body, state, &id).await
+}
+
+/// Endpoint used by GitLab push webhooks
+async fn gitlab(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "POST /webhook/gitlab/{}/", &id);
+    receive(Host::GitLab, req, body, state, &id).await
+}
+
+/// Endpoint used by Gitea push webhooks
+async fn gitea(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+) -> impl Responder {
+    info!(state.log, "POST /webhook/gitea/{}/", &id);
+    receive(Host::Gitea, req, body, state, &id).await
+}
+
+/// Routes for webhooks. `id` is the repository id
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/webhook")
+            .app_data(web::PayloadConfig::new(PAYLOAD_LIMIT))
+            .service(
+                web::resource("/github/{id}/").route(web::post().to(github)),
+            )
+            .service(
+                web::resource("/gitlab/{id}/").route(web::post().to(gitlab)),
+            )
+            .service(
+                web::resource("/gitea/{id}/").route(web::post().to(gitea)),
+            ),
+    );
+}